@@ -0,0 +1,36 @@
+use std::process::ExitStatus;
+
+use clap::ValueEnum;
+use tokio::process::Command;
+
+use crate::error::ActionError;
+
+/// Whether the command runs before or after the ready notification is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExecWhen {
+    Before,
+    After,
+}
+
+/// A shell command run once readiness is reached, such as smoke tests to run once an api is up
+#[derive(Debug, Clone)]
+pub struct Exec {
+    pub command: String,
+    pub when: ExecWhen,
+}
+
+impl Exec {
+    /// Runs the command with the given environment variables added, waiting for it to exit
+    pub async fn run(&self, envs: &[(&str, String)]) -> Result<ExitStatus, ActionError> {
+        Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(envs.iter().map(|(key, value)| (*key, value.as_str())))
+            .status()
+            .await
+            .map_err(|e| ActionError::Exec {
+                source: e,
+                command: self.command.clone(),
+            })
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local, NaiveTime};
+
+use crate::{notify::Notifier, output::OutputFormat};
+
+/// Time of the day during which watch notifications are held back, such as `22:00-07:00`.
+/// It wraps around midnight when it ends before it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Parses `START-END` with times as `HH:MM`
+    pub fn parse(value: &str) -> Result<QuietHours, String> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("expected START-END, e.g. 22:00-07:00, got `{}`", value))?;
+        let parse_time = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|e| format!("invalid time `{}`: {}", time, e))
+        };
+        Ok(QuietHours {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[test]
+fn test_quiet_hours() {
+    let time = |time| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+    let night = QuietHours::parse("22:00-07:00").unwrap();
+    let lunch = QuietHours::parse("12:00-13:30").unwrap();
+
+    assert!(night.contains(time("23:15")));
+    assert!(night.contains(time("00:00")));
+    assert!(night.contains(time("06:59")));
+    assert!(!night.contains(time("07:00")));
+    assert!(!night.contains(time("12:00")));
+    assert!(lunch.contains(time("12:00")));
+    assert!(lunch.contains(time("13:29")));
+    assert!(!lunch.contains(time("13:30")));
+    assert!(!lunch.contains(time("23:00")));
+    assert!(QuietHours::parse("22:00").is_err());
+    assert!(QuietHours::parse("25:00-07:00").is_err());
+}
+
+/// A notification of a target changing state in watch mode
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub notifier: Notifier,
+    pub summary: String,
+    pub body: String,
+    pub at: DateTime<Local>,
+}
+
+/// Limits the notifications of a single target to one per `every`. The last alert held back
+/// is sent once `every` elapsed, mentioning how many were skipped before it.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    every: Option<Duration>,
+    last_sent: Option<Instant>,
+    pending: Option<Alert>,
+    skipped: u32,
+}
+
+impl Throttle {
+    pub fn new(every: Option<Duration>) -> Throttle {
+        Throttle {
+            every,
+            ..Throttle::default()
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match (self.every, self.last_sent) {
+            (Some(every), Some(last_sent)) => last_sent.elapsed() >= every,
+            _ => true,
+        }
+    }
+}
+
+/// Sends the alerts of every target, holding them back during quiet hours to summarize them
+/// once the quiet hours end
+#[derive(Debug)]
+pub struct Alerts {
+    quiet_hours: Vec<QuietHours>,
+    held: Mutex<Vec<Alert>>,
+    /// Output format passed to the notifiers
+    output: OutputFormat,
+}
+
+impl Alerts {
+    pub fn new(quiet_hours: Vec<QuietHours>, output: OutputFormat) -> Alerts {
+        Alerts {
+            quiet_hours,
+            held: Mutex::new(Vec::new()),
+            output,
+        }
+    }
+
+    /// Sends the alert of a target now, or later when its throttle or quiet hours hold it back
+    pub fn send(&self, throttle: &mut Throttle, alert: Alert) {
+        if throttle.pending.replace(alert).is_some() {
+            throttle.skipped += 1;
+        }
+        self.flush(throttle);
+    }
+
+    /// Sends what was held back and can be sent by now. Called after every check so that held
+    /// alerts go out soon after the throttle elapsed or the quiet hours ended.
+    pub fn flush(&self, throttle: &mut Throttle) {
+        if self.is_quiet(Local::now().time()) {
+            if let Some(alert) = throttle.pending.take() {
+                self.held.lock().expect("alerts lock poisoned").push(alert);
+                throttle.skipped = 0;
+            }
+            return;
+        }
+        self.send_held();
+
+        if !throttle.is_open() {
+            return;
+        }
+        if let Some(alert) = throttle.pending.take() {
+            let body = match throttle.skipped {
+                0 => alert.body,
+                skipped => format!("{} ({} earlier changes not notified)", alert.body, skipped),
+            };
+            if let Err(e) = alert.notifier.notify(&alert.summary, &body, self.output) {
+                eprintln!("{}", e);
+            }
+            throttle.last_sent = Some(Instant::now());
+            throttle.skipped = 0;
+        }
+    }
+
+    fn is_quiet(&self, time: NaiveTime) -> bool {
+        self.quiet_hours.iter().any(|quiet| quiet.contains(time))
+    }
+
+    /// Summarizes the alerts held back during quiet hours in a notification per notifier
+    fn send_held(&self) {
+        let held = std::mem::take(&mut *self.held.lock().expect("alerts lock poisoned"));
+        let mut notifiers: Vec<Notifier> = Vec::new();
+        for alert in &held {
+            if !notifiers.contains(&alert.notifier) {
+                notifiers.push(alert.notifier);
+            }
+        }
+        for notifier in notifiers {
+            let lines: Vec<String> = held
+                .iter()
+                .filter(|alert| alert.notifier == notifier)
+                .map(|alert| format!("{} {}", alert.at.format("%H:%M"), alert.body))
+                .collect();
+            if let Err(e) = notifier.notify(
+                &format!("{} changes during quiet hours", lines.len()),
+                &lines.join("\n"),
+                self.output,
+            ) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::time;
+
+/// Longest stretch slept at once, so that time spent suspended is noticed soon after resuming
+const SLICE: Duration = Duration::from_secs(5);
+/// How much more the wall clock must have advanced than the monotonic clock while sleeping
+/// for the difference to be treated as the machine having been suspended
+const JUMP_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// Sleeps for `duration`, returning early when the machine resumes from a suspend so that
+/// targets are checked again right away. The monotonic clock used by timers doesn't advance
+/// while suspended, so a plain sleep would overshoot by the time spent suspended.
+pub async fn sleep(duration: Duration) {
+    let wall_start = SystemTime::now();
+    let start = Instant::now();
+    loop {
+        let slept = start.elapsed();
+        let wall_slept = SystemTime::now()
+            .duration_since(wall_start)
+            .unwrap_or_default();
+        if wall_slept >= slept + JUMP_THRESHOLD {
+            eprintln!(
+                "Clock jumped by {}, probably resumed from a suspend, checking again",
+                humantime::format_duration(Duration::from_secs((wall_slept - slept).as_secs()))
+            );
+            return;
+        }
+        if slept >= duration {
+            return;
+        }
+        time::sleep((duration - slept).min(SLICE)).await;
+    }
+}
+
+/// Completes once the wall clock reaches `deadline`, counting the time spent suspended
+pub async fn until(deadline: SystemTime) {
+    loop {
+        let remaining = match deadline.duration_since(SystemTime::now()) {
+            Ok(remaining) if remaining > Duration::from_secs(0) => remaining,
+            _ => return,
+        };
+        time::sleep(remaining.min(SLICE)).await;
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use alert_ready_api::request::RequestSpec;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::ConfigError;
+use crate::matcher::{JsonPathMatch, Matcher};
+use crate::notify::Notifier;
+use crate::probe::ProbeKind;
+
+/// Targets that can be waited for by name instead of retyping them.
+///
+/// ```toml
+/// [targets.staging-api]
+/// target = "https://staging.example.com/health"
+/// json_path = ["status=ok"]
+/// interval = 5
+/// message = "Staging is up"
+///
+/// [targets.staging-api.request]
+/// method = "POST"
+/// bearer_token = "..."
+///
+/// [targets.db]
+/// probe = "cmd"
+/// target = "pg_isready -h localhost"
+/// notifier = "stdout"
+///
+/// [targets.frontend]
+/// target = "https://staging.example.com"
+/// depends_on = ["staging-api", "db"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub targets: BTreeMap<String, TargetConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    /// The url, `host:port`, shell command or name to check, depending on the probe
+    #[serde(alias = "url")]
+    pub target: String,
+    pub probe: Option<ProbeKind>,
+    #[serde(default)]
+    pub contains: Vec<String>,
+    #[serde(default)]
+    pub json_path: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    /// Seconds between checks
+    pub interval: Option<u64>,
+    /// Days the certificate must still be valid for, with the cert probe
+    pub cert_days: Option<u32>,
+    pub message: Option<String>,
+    pub notifier: Option<Notifier>,
+    /// Seconds that must pass between notifications of the target in watch mode
+    pub throttle: Option<u64>,
+    /// How to request the url of http targets
+    #[serde(default)]
+    pub request: RequestSpec,
+    /// Names of the targets that must be ready before this one is checked, with --chain
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+impl TargetConfig {
+    pub fn probe_kind(&self) -> ProbeKind {
+        self.probe.unwrap_or(ProbeKind::Http)
+    }
+
+    /// Parses the body matchers of the target named `name`
+    pub fn matchers(&self, name: &str) -> Result<Vec<Matcher>, ConfigError> {
+        let mut matchers: Vec<Matcher> = self
+            .contains
+            .iter()
+            .cloned()
+            .map(Matcher::Contains)
+            .collect();
+        for json_path in &self.json_path {
+            let json_match =
+                JsonPathMatch::parse(json_path).map_err(|reason| ConfigError::InvalidJsonPath {
+                    name: name.to_string(),
+                    reason,
+                })?;
+            matchers.push(Matcher::JsonPath(json_match));
+        }
+        for regex in &self.regex {
+            let re = Regex::new(regex).map_err(|e| ConfigError::InvalidRegex {
+                source: e,
+                name: name.to_string(),
+            })?;
+            matchers.push(Matcher::Regex(re));
+        }
+
+        if !matchers.is_empty() && self.probe_kind() != ProbeKind::Http {
+            return Err(ConfigError::MatchersNeedHttp {
+                name: name.to_string(),
+            });
+        }
+        Ok(matchers)
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|e| ConfigError::Read {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse {
+            source: e,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Orders the targets named `names`, along with every target they depend on, into stages
+    /// that only depend on the targets of the stages before them. Targets are sorted by name
+    /// within a stage.
+    pub fn stages(&self, names: &[String]) -> Result<Vec<Vec<String>>, ConfigError> {
+        let mut depths = BTreeMap::new();
+        for name in names {
+            if !self.targets.contains_key(name) {
+                return Err(ConfigError::UnknownTarget { name: name.clone() });
+            }
+            self.depth(name, &mut depths, &mut Vec::new())?;
+        }
+
+        let mut stages = vec![Vec::new(); depths.values().max().map_or(0, |max| max + 1)];
+        for (name, depth) in depths {
+            stages[depth].push(name);
+        }
+        Ok(stages)
+    }
+
+    /// Stage of the target named `name`, 0 when it has no dependencies. `visiting` holds the
+    /// targets depending on it, to detect cycles.
+    fn depth(
+        &self,
+        name: &str,
+        depths: &mut BTreeMap<String, usize>,
+        visiting: &mut Vec<String>,
+    ) -> Result<usize, ConfigError> {
+        if let Some(depth) = depths.get(name) {
+            return Ok(*depth);
+        }
+        if visiting.iter().any(|visited| visited == name) {
+            visiting.push(name.to_string());
+            return Err(ConfigError::DependencyCycle {
+                cycle: visiting.join(" -> "),
+            });
+        }
+
+        visiting.push(name.to_string());
+        let mut depth = 0;
+        for dependency in &self.targets[name].depends_on {
+            if !self.targets.contains_key(dependency) {
+                return Err(ConfigError::UnknownDependency {
+                    name: name.to_string(),
+                    dependency: dependency.clone(),
+                });
+            }
+            depth = depth.max(self.depth(dependency, depths, visiting)? + 1);
+        }
+        visiting.pop();
+        depths.insert(name.to_string(), depth);
+        Ok(depth)
+    }
+
+    /// Loads the config at `path`, or at `~/.config/alert-ready-api/config.toml` when
+    /// no path is given and that file exists
+    pub fn load_or_default(path: Option<&Path>) -> Result<Config, ConfigError> {
+        match path {
+            Some(path) => Config::load(path),
+            None => match default_config_path().filter(|path| path.is_file()) {
+                Some(path) => Config::load(&path),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("alert-ready-api")
+            .join("config.toml")
+    })
+}
+
+#[test]
+fn test_stages() {
+    let config: Config = toml::from_str(
+        r#"
+        [targets.db]
+        target = "localhost:5432"
+        [targets.cache]
+        target = "localhost:6379"
+        [targets.api]
+        target = "http://localhost:8080/health"
+        depends_on = ["db", "cache"]
+        [targets.frontend]
+        target = "http://localhost:3000"
+        depends_on = ["api", "db"]
+        [targets.loop-a]
+        target = "a:1"
+        depends_on = ["loop-b"]
+        [targets.loop-b]
+        target = "b:1"
+        depends_on = ["loop-a"]
+        [targets.broken]
+        target = "c:1"
+        depends_on = ["missing"]
+        "#,
+    )
+    .unwrap();
+    let names = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        config.stages(&names(&["frontend"])).unwrap(),
+        vec![
+            names(&["cache", "db"]),
+            names(&["api"]),
+            names(&["frontend"])
+        ]
+    );
+    assert_eq!(
+        config.stages(&names(&["db", "cache"])).unwrap(),
+        vec![names(&["cache", "db"])]
+    );
+    assert!(matches!(
+        config.stages(&names(&["loop-a"])),
+        Err(ConfigError::DependencyCycle { .. })
+    ));
+    assert!(matches!(
+        config.stages(&names(&["broken"])),
+        Err(ConfigError::UnknownDependency { .. })
+    ));
+    assert!(matches!(
+        config.stages(&names(&["unknown"])),
+        Err(ConfigError::UnknownTarget { .. })
+    ));
+}
@@ -0,0 +1,118 @@
+use std::{io, net::SocketAddr, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProbeError {
+    #[error("Error requesting `{}`: {}", url, source)]
+    Request { source: reqwest::Error, url: String },
+
+    #[error("Error reading the response body of `{}`: {}", url, source)]
+    ReadBody { source: reqwest::Error, url: String },
+
+    #[error("Error resolving `{}`: {}", addr, source)]
+    Resolve { source: io::Error, addr: String },
+
+    #[error("Error connecting to `{}`: {}", addr, source)]
+    Connect { source: io::Error, addr: String },
+
+    #[error("Error running `{}`: {}", command, source)]
+    Spawn { source: io::Error, command: String },
+
+    #[error("Error building http client: {}", source)]
+    Client { source: reqwest::Error },
+
+    #[error("`{}` didn't present a certificate", url)]
+    NoCertificate { url: String },
+
+    #[error("Invalid certificate of `{}`: {}", url, reason)]
+    Certificate { url: String, reason: String },
+}
+
+#[derive(Error, Debug)]
+pub enum ActionError {
+    #[error("Error running `{}`: {}", command, source)]
+    Exec { source: io::Error, command: String },
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Error reading config `{}`: {}", path.display(), source)]
+    Read { source: io::Error, path: PathBuf },
+
+    #[error("Invalid config `{}`: {}", path.display(), source)]
+    Parse {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[error("Invalid json_path of target `{}`: {}", name, reason)]
+    InvalidJsonPath { name: String, reason: String },
+
+    #[error("Invalid regex of target `{}`: {}", name, source)]
+    InvalidRegex { source: regex::Error, name: String },
+
+    #[error("Target `{}` has body matchers but doesn't use the http probe", name)]
+    MatchersNeedHttp { name: String },
+
+    #[error("No target named `{}` in the config", name)]
+    UnknownTarget { name: String },
+
+    #[error(
+        "Target `{}` depends on `{}`, which isn't in the config",
+        name,
+        dependency
+    )]
+    UnknownDependency { name: String, dependency: String },
+
+    #[error("Targets depend on each other: {}", cycle)]
+    DependencyCycle { cycle: String },
+}
+
+#[derive(Error, Debug)]
+pub enum HistoryError {
+    #[error("Error writing history `{}`: {}", path.display(), source)]
+    Write { source: io::Error, path: PathBuf },
+
+    #[error("Error serializing history entry for `{}`: {}", path.display(), source)]
+    Serialize {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum ServeError {
+    #[error("Error listening on `{}`: {}", addr, source)]
+    Bind {
+        source: hyper::Error,
+        addr: SocketAddr,
+    },
+
+    #[error("Error serving status on `{}`: {}", addr, source)]
+    Serve {
+        source: hyper::Error,
+        addr: SocketAddr,
+    },
+}
+
+#[derive(Error, Debug)]
+pub enum StateError {
+    #[error("Error reading state `{}`: {}", path.display(), source)]
+    Read { source: io::Error, path: PathBuf },
+
+    #[error("Invalid state `{}`: {}", path.display(), source)]
+    Parse {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[error("Error writing state `{}`: {}", path.display(), source)]
+    Write { source: io::Error, path: PathBuf },
+}
+
+#[derive(Error, Debug)]
+pub enum NotifyError {
+    #[error("Error showing notification: {}", source)]
+    Desktop { source: notify_rust::Error },
+}
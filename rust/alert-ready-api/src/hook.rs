@@ -0,0 +1,148 @@
+use std::{convert::Infallible, fmt, net::SocketAddr};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::error::ServeError;
+
+/// Header carrying the shared secret of --hook-secret
+const SECRET_HEADER: &str = "x-hook-secret";
+const DEFAULT_PATH: &str = "/hook";
+
+/// Address and path readiness events are posted to, such as `0.0.0.0:9901/hook`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookAddr {
+    pub addr: SocketAddr,
+    pub path: String,
+}
+
+impl HookAddr {
+    /// Parses `ADDR[/PATH]`, the path defaulting to `/hook`
+    pub fn parse(value: &str) -> Result<HookAddr, String> {
+        let (addr, path) = match value.find('/') {
+            Some(i) => (&value[..i], value[i..].to_string()),
+            None => (value, DEFAULT_PATH.to_string()),
+        };
+        let addr = addr
+            .parse()
+            .map_err(|e| format!("invalid address `{}`: {}", addr, e))?;
+        Ok(HookAddr { addr, path })
+    }
+}
+
+impl fmt::Display for HookAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.addr, self.path)
+    }
+}
+
+#[test]
+fn test_hook_addr_parse() {
+    let hook = HookAddr::parse("0.0.0.0:9901/deploys/done").unwrap();
+    assert_eq!(hook.addr, "0.0.0.0:9901".parse().unwrap());
+    assert_eq!(hook.path, "/deploys/done");
+
+    let hook = HookAddr::parse("127.0.0.1:9901").unwrap();
+    assert_eq!(hook.path, "/hook");
+    assert_eq!(hook.to_string(), "http://127.0.0.1:9901/hook");
+
+    assert!(HookAddr::parse("localhost/hook").is_err());
+}
+
+/// Readiness event posted to the hook. Every field is optional and so is the body.
+#[derive(Debug, Default, Deserialize)]
+pub struct HookEvent {
+    /// What became ready
+    pub target: Option<String>,
+    /// Notification shown, unless one was given with --message
+    pub message: Option<String>,
+}
+
+/// Serves the hook until a readiness event is posted to it, with the shared secret in the
+/// `X-Hook-Secret` header when there is one. Returns the event posted.
+pub async fn wait_for_hook(
+    hook: &HookAddr,
+    secret: Option<String>,
+) -> Result<HookEvent, ServeError> {
+    let (events, mut received) = mpsc::unbounded_channel();
+    let path = hook.path.clone();
+    let make_service = make_service_fn(move |_conn| {
+        let (events, path, secret) = (events.clone(), path.clone(), secret.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let (events, path, secret) = (events.clone(), path.clone(), secret.clone());
+                async move {
+                    Ok::<_, Infallible>(respond(request, &path, secret.as_deref(), &events).await)
+                }
+            }))
+        }
+    });
+
+    let addr = hook.addr;
+    let server = Server::try_bind(&addr)
+        .map_err(|e| ServeError::Bind { source: e, addr })?
+        .serve(make_service);
+    tokio::select! {
+        event = received.recv() => Ok(event.expect("hook server dropped its events")),
+        served = server => match served {
+            Err(e) => Err(ServeError::Serve { source: e, addr }),
+            Ok(()) => unreachable!("hook server stopped without being shut down"),
+        },
+    }
+}
+
+async fn respond(
+    request: Request<Body>,
+    path: &str,
+    secret: Option<&str>,
+    events: &UnboundedSender<HookEvent>,
+) -> Response<Body> {
+    if request.uri().path() != path {
+        return with_status(StatusCode::NOT_FOUND, "not found");
+    }
+    if request.method() != Method::POST {
+        return with_status(StatusCode::METHOD_NOT_ALLOWED, "only POST is allowed");
+    }
+    if let Some(secret) = secret {
+        let given = request
+            .headers()
+            .get(SECRET_HEADER)
+            .map(|value| value.as_bytes())
+            .unwrap_or_default();
+        if !constant_time_eq(given, secret.as_bytes()) {
+            return with_status(StatusCode::UNAUTHORIZED, "invalid secret");
+        }
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return with_status(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let event = if body.iter().all(u8::is_ascii_whitespace) {
+        HookEvent::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(event) => event,
+            Err(e) => return with_status(StatusCode::BAD_REQUEST, e.to_string()),
+        }
+    };
+    // Only the first event is waited for, later ones are accepted and ignored
+    let _ = events.send(event);
+    with_status(StatusCode::ACCEPTED, "ok")
+}
+
+/// Compares without returning early so that the time taken doesn't reveal the secret
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn with_status<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .expect("failed building response")
+}
@@ -0,0 +1,2 @@
+//! The parts of alert-ready-api that other tools can reuse to check readiness
+pub mod request;
@@ -1,27 +1,995 @@
-use notify_rust::Notification;
-use std::{env, thread, time};
+use alert_ready_api::request::RequestSpec;
+use chrono::Local;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use regex::Regex;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::task::JoinSet;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+mod action;
+mod alerts;
+mod clock;
+mod config;
+mod error;
+mod hook;
+mod matcher;
+mod notify;
+mod output;
+mod probe;
+mod server;
+mod state;
+mod stats;
 
-    let url = &args[1]; //.expect("failed no arguments");
-    dbg!(url);
-    let mut ready: bool = is_ready(url);
+use action::{Exec, ExecWhen};
+use alerts::{Alert, Alerts, QuietHours, Throttle};
+use config::Config;
+use error::ProbeError;
+use hook::HookAddr;
+use matcher::{JsonPathMatch, Matcher};
+use notify::Notifier;
+use output::{Attempt, OutputFormat, StageProgress, StageStatus};
+use probe::{CertProbe, CmdProbe, DnsProbe, HttpProbe, Probe, ProbeKind, TcpProbe};
+use state::{States, Status};
+use stats::Stats;
 
-    while !ready {
-        thread::sleep(time::Duration::from_millis(1000));
-        ready = is_ready(url);
+/// Waits for things to become ready and shows a notification once they are
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// What to wait for: names of targets in the config, or urls, `host:port`s, shell commands
+    /// or names, depending on the probe
+    #[arg(required_unless_present = "listen")]
+    targets: Vec<String>,
+
+    /// Config file defining named targets. Defaults to ~/.config/alert-ready-api/config.toml
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// How to check whether the targets that aren't named in the config are ready.
+    /// The options about http requests and response bodies also only apply to those targets
+    #[arg(short, long, value_enum, default_value_t = ProbeKind::Http)]
+    probe: ProbeKind,
+
+    /// Seconds between checks. Given once, it applies to every target. Given once per target,
+    /// it applies to the target in the same position
+    #[arg(short, long, value_name = "SECONDS")]
+    interval: Vec<u64>,
+
+    /// Notification shown once a target is ready, given like --interval.
+    /// Defaults to `<target> is now ready`
+    #[arg(short, long, value_name = "TEXT")]
+    message: Vec<String>,
+
+    /// Where to show notifications, for targets whose config doesn't say
+    #[arg(short, long, value_enum, default_value_t = Notifier::Desktop)]
+    notifier: Notifier,
+
+    /// Wait for every target to be ready. This is the default
+    #[arg(long, conflicts_with = "any")]
+    all: bool,
+
+    /// Wait for any target to be ready
+    #[arg(long)]
+    any: bool,
+
+    /// Wait for the targets of the config in the order of their `depends_on`, only checking a
+    /// target once every target it depends on is ready. The targets depended on are waited for
+    /// as if they were given too
+    #[arg(long, conflicts_with = "any")]
+    chain: bool,
+
+    /// Give up when a stage of --chain isn't ready after this long, e.g. `5m`, exiting with
+    /// code 2
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = humantime::parse_duration,
+        requires = "chain"
+    )]
+    stage_timeout: Option<Duration>,
+
+    /// Days a certificate must still be valid for to be ready, with the cert probe
+    #[arg(long, default_value_t = 14, value_name = "DAYS")]
+    cert_days: u32,
+
+    /// Seconds to wait for a tcp connection before giving up on an attempt
+    #[arg(long, default_value_t = 5, value_name = "SECONDS")]
+    connect_timeout: u64,
+
+    /// Also wait for the response body to contain this text
+    #[arg(long, value_name = "TEXT")]
+    contains: Vec<String>,
+
+    /// Also wait for a field of the JSON response body to equal a value, e.g. `status.db=ok`
+    #[arg(long, value_name = "PATH=VALUE", value_parser = JsonPathMatch::parse)]
+    json_path: Vec<JsonPathMatch>,
+
+    /// Also wait for the response body to match this regex
+    #[arg(long, value_name = "REGEX", value_parser = Regex::new)]
+    regex: Vec<Regex>,
+
+    /// Http method of the requests
+    #[arg(short = 'X', long, default_value = "GET")]
+    method: String,
+
+    /// Header added to the requests, as `Name: value`
+    #[arg(short = 'H', long)]
+    header: Vec<String>,
+
+    /// Body of the requests
+    #[arg(short, long)]
+    body: Option<String>,
+
+    /// Accept invalid certificates, such as self signed ones
+    #[arg(short = 'k', long)]
+    insecure: bool,
+
+    /// Basic auth credentials of the requests, as `user:password`
+    #[arg(
+        short = 'u',
+        long,
+        value_name = "USER:PASSWORD",
+        conflicts_with = "bearer_token"
+    )]
+    basic_auth: Option<String>,
+
+    /// Bearer token sent in the Authorization header of the requests
+    #[arg(long, value_name = "TOKEN")]
+    bearer_token: Option<String>,
+
+    /// User agent of the requests
+    #[arg(short = 'A', long)]
+    user_agent: Option<String>,
+
+    /// Give up on a target after this many consecutive failed checks, such as network errors.
+    /// Retries forever by default
+    #[arg(long, value_name = "COUNT")]
+    max_failures: Option<u32>,
+
+    /// Longest delay in seconds between checks when backing off after failed checks
+    #[arg(long, default_value_t = 60, value_name = "SECONDS")]
+    max_backoff: u64,
+
+    /// Shell command to run once ready, e.g. to run smoke tests once an api is up. The target
+    /// is available to it in $ALERT_READY_TARGET, and every ready target in $ALERT_READY_TARGETS
+    #[arg(short, long, value_name = "COMMAND")]
+    exec: Option<String>,
+
+    /// Whether to run the --exec command before or after the ready notification
+    #[arg(long, value_enum, default_value_t = ExecWhen::After, requires = "exec")]
+    exec_when: ExecWhen,
+
+    /// Give up waiting after this long, e.g. `90s` or `15m`, exiting with code 2
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    max_wait: Option<Duration>,
+
+    /// Append a JSON line reporting the attempts and latencies of every ready target to this file
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+
+    /// Format of the progress printed on stdout
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Serve the status of every target as JSON on http://ADDR/status. Implies --watch
+    #[arg(long, value_name = "ADDR")]
+    serve: Option<SocketAddr>,
+
+    /// Keep monitoring once ready, notifying whenever a target goes down or comes back up
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Instead of checking targets, wait for a readiness event POSTed to http://ADDR/PATH, e.g.
+    /// `0.0.0.0:9901/hook`. The path defaults to /hook. The JSON body of the event can give the
+    /// `target` that is ready and the `message` to notify
+    #[arg(
+        long,
+        value_name = "ADDR[/PATH]",
+        value_parser = HookAddr::parse,
+        conflicts_with_all = ["targets", "chain", "serve", "watch"]
+    )]
+    listen: Option<HookAddr>,
+
+    /// Only accept readiness events sending this secret in their X-Hook-Secret header
+    #[arg(long, value_name = "SECRET", requires = "listen")]
+    hook_secret: Option<String>,
+
+    /// Consecutive checks a new state must hold for before notifying of it in watch mode
+    #[arg(long, default_value_t = 3, value_name = "COUNT")]
+    debounce: u32,
+
+    /// Notify of each target at most once per DURATION in watch mode, e.g. `10m`. The last
+    /// change held back is notified once DURATION elapsed
+    #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+    throttle: Option<Duration>,
+
+    /// Hold back watch notifications during this time of the day, e.g. `22:00-07:00`, and
+    /// summarize them once it ends
+    #[arg(long, value_name = "START-END", value_parser = QuietHours::parse)]
+    quiet_hours: Vec<QuietHours>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List the targets of the config along with their last known state
+    List,
+}
+
+const DEFAULT_INTERVAL_SECS: u64 = 1;
+
+/// Exit code once ready
+const EXIT_READY: i32 = 0;
+/// Exit code on errors, such as giving up on a target after too many failed checks
+const EXIT_ERROR: i32 = 1;
+/// Exit code when --max-wait elapsed before being ready
+const EXIT_TIMEOUT: i32 = 2;
+
+/// A probe along with how often it is checked and how to notify once it is ready
+struct Target {
+    /// Name of the target in the config, whose state is saved across runs
+    name: Option<String>,
+    probe: Box<dyn Probe>,
+    interval: Duration,
+    message: String,
+    notifier: Notifier,
+    /// Least time between notifications in watch mode
+    throttle: Option<Duration>,
+    stats: Mutex<Stats>,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli_args = CliArgs::parse();
+
+    let config = Config::load_or_default(cli_args.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(EXIT_ERROR);
+    });
+    let states_path = state::default_state_path();
+    let states = Arc::new(States::load(&states_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(EXIT_ERROR);
+    }));
+
+    if let Some(Command::List) = cli_args.command {
+        list(&config, &states, cli_args.config.as_deref());
+        return;
+    }
+
+    if let Some(hook) = cli_args.listen {
+        eprintln!("Waiting for a readiness event on {}", hook);
+        let waiting = hook::wait_for_hook(&hook, cli_args.hook_secret);
+        let event = within_max_wait(waiting, cli_args.max_wait)
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            });
+        let target = event.target.unwrap_or_else(|| hook.to_string());
+        let message = cli_args
+            .message
+            .into_iter()
+            .next()
+            .or(event.message)
+            .unwrap_or_else(|| format!("{} is now ready", target));
+        let when = cli_args.exec_when;
+        let exec = cli_args.exec.map(|command| Exec { command, when });
+        let envs = vec![
+            ("ALERT_READY_TARGET", target.clone()),
+            ("ALERT_READY_TARGETS", target),
+        ];
+        let notifier = cli_args.notifier;
+        let output = cli_args.output;
+        notify_ready(exec.as_ref(), &envs, || {
+            if let Err(e) = notifier.notify("What you are waiting for is ready", &message, output) {
+                eprintln!("{}", e);
+            }
+        })
+        .await;
+        process::exit(EXIT_READY);
+    }
+
+    let matchers: Vec<Matcher> = cli_args
+        .contains
+        .into_iter()
+        .map(Matcher::Contains)
+        .chain(cli_args.json_path.into_iter().map(Matcher::JsonPath))
+        .chain(cli_args.regex.into_iter().map(Matcher::Regex))
+        .collect();
+    if !matchers.is_empty() && cli_args.probe != ProbeKind::Http {
+        CliArgs::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--contains, --json-path and --regex can only be used with --probe http",
+            )
+            .exit();
     }
 
-    Notification::new()
-        .summary("What you are waiting for is ready")
-        .body(&format!("{} is now ready", url))
-        .show()
-        .expect("failed notification");
+    let mut request = RequestSpec {
+        method: Some(cli_args.method),
+        body: cli_args.body,
+        insecure: cli_args.insecure,
+        basic_auth: cli_args.basic_auth,
+        bearer_token: cli_args.bearer_token,
+        user_agent: cli_args.user_agent,
+        ..RequestSpec::default()
+    };
+    for header in &cli_args.header {
+        if let Err(e) = request.add_header(header) {
+            CliArgs::command()
+                .error(ErrorKind::ValueValidation, e)
+                .exit();
+        }
+    }
+
+    // Indices of the targets of each stage with --chain, the targets being ordered by stage,
+    // along with the position of each target on the command line
+    let given = cli_args.targets.len();
+    let (target_args, stages, positions) = if cli_args.chain {
+        let stages = config.stages(&cli_args.targets).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        });
+        let mut next = 0;
+        let indices = stages
+            .iter()
+            .map(|stage| {
+                next += stage.len();
+                (next - stage.len()..next).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let target_args = stages.concat();
+        let named = &cli_args.targets;
+        let positions = target_args
+            .iter()
+            .map(|arg| named.iter().position(|name| name == arg))
+            .collect::<Vec<_>>();
+        (target_args, Some(indices), positions)
+    } else {
+        (cli_args.targets, None, (0..given).map(Some).collect())
+    };
+
+    let count = target_args.len();
+    let intervals = at_positions(
+        cli_args.interval.into_iter().map(Some).collect(),
+        &positions,
+        given,
+        None,
+        "--interval",
+    );
+    let messages = at_positions(
+        cli_args.message.into_iter().map(Some).collect(),
+        &positions,
+        given,
+        None,
+        "--message",
+    );
+    let probe_kind = cli_args.probe;
+    let notifier = cli_args.notifier;
+    let connect_timeout = Duration::from_secs(cli_args.connect_timeout);
+    let new_probe = |kind: ProbeKind,
+                     target: String,
+                     matchers: Vec<Matcher>,
+                     request: &RequestSpec,
+                     cert_days: u32|
+     -> Box<dyn Probe> {
+        match kind {
+            ProbeKind::Http => Box::new(HttpProbe {
+                request: request.prepare().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(EXIT_ERROR);
+                }),
+                url: target,
+                matchers,
+            }),
+            ProbeKind::Tcp => Box::new(TcpProbe {
+                addr: target,
+                timeout: connect_timeout,
+            }),
+            ProbeKind::Cmd => Box::new(CmdProbe { command: target }),
+            ProbeKind::Dns => Box::new(DnsProbe { name: target }),
+            ProbeKind::Cert => Box::new(CertProbe::new(target, cert_days).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            })),
+        }
+    };
+
+    let mut targets = Vec::with_capacity(count);
+    for ((arg, interval), message) in target_args.into_iter().zip(intervals).zip(messages) {
+        let target = match config.targets.get(&arg) {
+            Some(named) => {
+                let matchers = named.matchers(&arg).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(EXIT_ERROR);
+                });
+                Target {
+                    probe: new_probe(
+                        named.probe_kind(),
+                        named.target.clone(),
+                        matchers,
+                        &named.request,
+                        named.cert_days.unwrap_or(cli_args.cert_days),
+                    ),
+                    interval: Duration::from_secs(
+                        interval.or(named.interval).unwrap_or(DEFAULT_INTERVAL_SECS),
+                    ),
+                    message: message
+                        .or_else(|| named.message.clone())
+                        .unwrap_or_else(|| format!("{} is now ready", arg)),
+                    notifier: named.notifier.unwrap_or(notifier),
+                    throttle: named
+                        .throttle
+                        .map(Duration::from_secs)
+                        .or(cli_args.throttle),
+                    stats: Mutex::new(Stats::new()),
+                    name: Some(arg),
+                }
+            }
+            None => {
+                let probe = new_probe(
+                    probe_kind,
+                    arg,
+                    matchers.clone(),
+                    &request,
+                    cli_args.cert_days,
+                );
+                Target {
+                    name: None,
+                    interval: Duration::from_secs(interval.unwrap_or(DEFAULT_INTERVAL_SECS)),
+                    message: message.unwrap_or_else(|| format!("{} is now ready", probe.target())),
+                    notifier,
+                    throttle: cli_args.throttle,
+                    stats: Mutex::new(Stats::new()),
+                    probe,
+                }
+            }
+        };
+        targets.push(Arc::new(target));
+    }
+
+    if let Some(addr) = cli_args.serve {
+        let served = targets.clone();
+        tokio::spawn(async move {
+            let statuses = move || {
+                served
+                    .iter()
+                    .map(|target| {
+                        target
+                            .stats
+                            .lock()
+                            .expect("stats lock poisoned")
+                            .status(target.probe.target(), target.name.as_deref())
+                    })
+                    .collect::<Vec<_>>()
+            };
+            if let Err(e) = server::serve(addr, statuses).await {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            }
+        });
+    }
+
+    let retry = Retry {
+        max_failures: cli_args.max_failures,
+        max_backoff: Duration::from_secs(cli_args.max_backoff),
+    };
+    // --all is the default, it only exists to be explicit
+    let any = cli_args.any && !cli_args.all;
+    let output = cli_args.output;
+    let stage_timeout = cli_args.stage_timeout;
+    let waiting = async {
+        match &stages {
+            Some(stages) => {
+                wait_for_chain(&targets, stages, retry, stage_timeout, &states, output).await
+            }
+            None => wait_for_targets(&targets, retry, any, &states, output).await,
+        }
+    };
+    let (ready, last) = within_max_wait(waiting, cli_args.max_wait).await;
+    for target in targets
+        .iter()
+        .zip(&ready)
+        .filter(|(_, ready)| **ready)
+        .map(|(target, _)| target)
+    {
+        let report = target
+            .stats
+            .lock()
+            .expect("stats lock poisoned")
+            .report(target.probe.target(), target.name.as_deref());
+        report.print(output);
+        if let Some(history) = &cli_args.history {
+            if let Err(e) = report.append_to(history) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    let when = cli_args.exec_when;
+    let exec = cli_args.exec.map(|command| Exec { command, when });
+    let envs = exec_envs(&targets, &ready, last);
+    let last_target = &targets[last];
+    notify_ready(exec.as_ref(), &envs, || {
+        if let Err(e) = last_target.notifier.notify(
+            "What you are waiting for is ready",
+            &with_detail(&last_target.message, last_target.probe.detail()),
+            output,
+        ) {
+            eprintln!("{}", e);
+        }
+        if !any && targets.len() > 1 {
+            if let Err(e) = notifier.notify(
+                "Everything you are waiting for is ready",
+                &format!("All {} targets are ready", targets.len()),
+                output,
+            ) {
+                eprintln!("{}", e);
+            }
+        }
+    })
+    .await;
+
+    if cli_args.watch || cli_args.serve.is_some() {
+        let alerts = Arc::new(Alerts::new(cli_args.quiet_hours, output));
+        let mut watching = JoinSet::new();
+        for (target, up) in targets.into_iter().zip(ready) {
+            watching.spawn(watch(
+                target,
+                up,
+                cli_args.debounce,
+                Arc::clone(&states),
+                Arc::clone(&alerts),
+                output,
+            ));
+        }
+        while watching.join_next().await.is_some() {}
+    }
+    process::exit(EXIT_READY);
+}
+
+/// Waits for `waiting`, exiting with EXIT_TIMEOUT once `max_wait` elapsed
+async fn within_max_wait<T>(waiting: impl Future<Output = T>, max_wait: Option<Duration>) -> T {
+    match max_wait {
+        // measured on the wall clock so that time spent suspended counts
+        Some(max_wait) => tokio::select! {
+            waited = waiting => waited,
+            _ = clock::until(SystemTime::now() + max_wait) => {
+                eprintln!(
+                    "Timed out after {} waiting for targets to be ready",
+                    humantime::format_duration(max_wait)
+                );
+                process::exit(EXIT_TIMEOUT);
+            }
+        },
+        None => waiting.await,
+    }
+}
+
+/// Shows the ready notifications with `notify`, running the --exec command before or after
+/// them. Exits with the exit code of the command when it fails.
+async fn notify_ready(exec: Option<&Exec>, envs: &[(&str, String)], notify: impl FnOnce()) {
+    let mut exec_status = None;
+    if let Some(exec) = exec.filter(|exec| exec.when == ExecWhen::Before) {
+        exec_status = Some(exec.run(envs).await);
+    }
+    notify();
+    if let Some(exec) = exec.filter(|exec| exec.when == ExecWhen::After) {
+        exec_status = Some(exec.run(envs).await);
+    }
+    match exec_status {
+        Some(Ok(status)) if !status.success() => {
+            eprintln!(
+                "`{}` failed with {}",
+                exec.expect("exec ran").command,
+                status
+            );
+            process::exit(status.code().unwrap_or(EXIT_ERROR));
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+        _ => {}
+    }
+}
+
+/// Prints the targets of the config with their last known state
+fn list(config: &Config, states: &States, config_path: Option<&Path>) {
+    if config.targets.is_empty() {
+        match config_path {
+            Some(path) => println!("No targets configured in {}", path.display()),
+            None => println!("No targets configured"),
+        }
+        return;
+    }
+
+    for (name, target) in &config.targets {
+        let state = match states.get(name) {
+            Some(state) => format!(
+                "{:<7} {}",
+                format!("{:?}", state.status).to_lowercase(),
+                state.checked_at.format("%Y-%m-%d %H:%M:%S")
+            ),
+            None => format!("{:<7} {:<19}", "unknown", ""),
+        };
+        println!(
+            "{:<20} {}  {:<4} {}",
+            name,
+            state,
+            format!("{:?}", target.probe_kind()).to_lowercase(),
+            target.target
+        );
+    }
+}
+
+/// Spreads the values of an option given either once for every target or once per target
+fn per_target<T: Clone>(values: Vec<T>, count: usize, default: T, name: &str) -> Vec<T> {
+    match values.len() {
+        0 => vec![default; count],
+        1 => vec![values[0].clone(); count],
+        len if len == count => values,
+        len => CliArgs::command()
+            .error(
+                ErrorKind::WrongNumberOfValues,
+                format!(
+                    "{} was given {} times, expected once or once per target ({})",
+                    name, len, count
+                ),
+            )
+            .exit(),
+    }
+}
+
+/// The values of the per-target option for the targets at `positions` on the command line.
+/// Targets without a position, the dependencies added by --chain, only get a value given once
+/// for every target.
+fn at_positions<T: Clone>(
+    values: Vec<T>,
+    positions: &[Option<usize>],
+    given: usize,
+    default: T,
+    name: &str,
+) -> Vec<T> {
+    let shared = match values.as_slice() {
+        [value] => value.clone(),
+        _ => default.clone(),
+    };
+    let values = per_target(values, given, default, name);
+    positions
+        .iter()
+        .map(|position| match position {
+            Some(i) => values[*i].clone(),
+            None => shared.clone(),
+        })
+        .collect()
+}
+
+/// Saves the status of a named target, only warning on failure since it is not needed to
+/// wait for the target
+fn record(states: &States, target: &Target, status: Status) {
+    if let Some(name) = &target.name {
+        if let Err(e) = states.record(name, status) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Environment variables describing the ready targets to the --exec command
+fn exec_envs(targets: &[Arc<Target>], ready: &[bool], last: usize) -> Vec<(&'static str, String)> {
+    let ready_targets: Vec<&str> = targets
+        .iter()
+        .zip(ready)
+        .filter(|(_, ready)| **ready)
+        .map(|(target, _)| target.probe.target())
+        .collect();
+    let mut envs = vec![
+        (
+            "ALERT_READY_TARGET",
+            targets[last].probe.target().to_string(),
+        ),
+        ("ALERT_READY_TARGETS", ready_targets.join("\n")),
+    ];
+    if let Some(name) = &targets[last].name {
+        envs.push(("ALERT_READY_NAME", name.clone()));
+    }
+    envs
 }
 
-fn is_ready(url: &str) -> bool {
-    let response = reqwest::get(url).expect("get failed");
-    let status = response.status();
-    status.is_success()
+/// Polls every target concurrently until all of them, or any of them when `any` is set,
+/// are ready, notifying each target as it becomes ready except the one completing the wait,
+/// which is left to the caller. Exits when a target that is needed runs out of failed checks.
+/// Returns which targets are ready along with the index of the one completing the wait.
+async fn wait_for_targets(
+    targets: &[Arc<Target>],
+    retry: Retry,
+    any: bool,
+    states: &States,
+    output: OutputFormat,
+) -> (Vec<bool>, usize) {
+    let mut waiting = JoinSet::new();
+    for (i, target) in targets.iter().enumerate() {
+        let target = Arc::clone(target);
+        waiting.spawn(async move { (i, wait_until_ready(&target, retry, output).await) });
+    }
+
+    let mut ready = vec![false; targets.len()];
+    let mut last = 0;
+    let mut gave_up = 0;
+    while let Some(joined) = waiting.join_next().await {
+        let (i, result) = joined.expect("failed joining probe task");
+        let target = &targets[i];
+        match result {
+            Ok(()) => {
+                ready[i] = true;
+                record(states, target, Status::Ready);
+                if any || ready.iter().all(|ready| *ready) {
+                    last = i;
+                    break;
+                }
+                if let Err(e) = target.notifier.notify(
+                    "What you are waiting for is ready",
+                    &with_detail(&target.message, target.probe.detail()),
+                    output,
+                ) {
+                    eprintln!("{}", e);
+                }
+            }
+            Err(e) => {
+                record(states, target, Status::Failed);
+                eprintln!(
+                    "Giving up on {} after {} consecutive failures: {}",
+                    target.probe.target(),
+                    retry.max_failures.unwrap_or_default(),
+                    e
+                );
+                gave_up += 1;
+                if !any || gave_up == targets.len() {
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+    (ready, last)
+}
+
+/// Waits for the stages of targets one after the other, each like `wait_for_targets` waits for
+/// every target. Exits when a stage isn't ready before `stage_timeout`. Targets completing a stage
+/// are notified, except the one completing the last stage which is left to the caller.
+async fn wait_for_chain(
+    targets: &[Arc<Target>],
+    stages: &[Vec<usize>],
+    retry: Retry,
+    stage_timeout: Option<Duration>,
+    states: &States,
+    output: OutputFormat,
+) -> (Vec<bool>, usize) {
+    let mut ready = vec![false; targets.len()];
+    let mut last = 0;
+    for (n, stage) in stages.iter().enumerate() {
+        let stage_targets: Vec<Arc<Target>> =
+            stage.iter().map(|i| Arc::clone(&targets[*i])).collect();
+        let progress = |status, elapsed: Option<Duration>| StageProgress {
+            timestamp: Local::now(),
+            stage: n + 1,
+            stages: stages.len(),
+            targets: stage_targets
+                .iter()
+                .map(|target| target.name.as_deref().unwrap_or(target.probe.target()))
+                .collect(),
+            status,
+            elapsed_ms: elapsed.map(|elapsed| elapsed.as_millis() as u64),
+        };
+        progress(StageStatus::Waiting, None).print(output);
+
+        let started = Instant::now();
+        let waiting = wait_for_targets(&stage_targets, retry, false, states, output);
+        let (_, stage_last) = match stage_timeout {
+            Some(stage_timeout) => tokio::select! {
+                waited = waiting => waited,
+                _ = clock::until(SystemTime::now() + stage_timeout) => {
+                    let timed_out = progress(StageStatus::TimedOut, Some(started.elapsed()));
+                    timed_out.print(output);
+                    if let Err(e) = stage_targets[0].notifier.notify(
+                        "What you are waiting for timed out",
+                        &format!(
+                            "Stage {}/{} wasn't ready after {}: {}",
+                            n + 1,
+                            stages.len(),
+                            humantime::format_duration(stage_timeout),
+                            timed_out.targets.join(", ")
+                        ),
+                        output,
+                    ) {
+                        eprintln!("{}", e);
+                    }
+                    process::exit(EXIT_TIMEOUT);
+                }
+            },
+            None => waiting.await,
+        };
+        progress(StageStatus::Ready, Some(started.elapsed())).print(output);
+
+        for i in stage {
+            ready[*i] = true;
+        }
+        last = stage[stage_last];
+        if n + 1 < stages.len() {
+            let target = &targets[last];
+            if let Err(e) = target.notifier.notify(
+                "What you are waiting for is ready",
+                &with_detail(&target.message, target.probe.detail()),
+                output,
+            ) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    (ready, last)
+}
+
+/// Monitors a target forever, notifying when it goes down and when it comes back up.
+/// A state change is only notified once it held for `debounce` consecutive checks so that
+/// a flapping target doesn't flood notifications, on top of the throttle of the target and
+/// quiet hours applied by `alerts`. Failed checks count as down.
+async fn watch(
+    target: Arc<Target>,
+    mut up: bool,
+    debounce: u32,
+    states: Arc<States>,
+    alerts: Arc<Alerts>,
+    output: OutputFormat,
+) {
+    let probe = &target.probe;
+    let mut changed_checks = 0;
+    let mut throttle = Throttle::new(target.throttle);
+    let alert = |summary: &str, body: String| Alert {
+        notifier: target.notifier,
+        summary: summary.to_string(),
+        body,
+        at: Local::now(),
+    };
+    loop {
+        clock::sleep(target.interval).await;
+        let (is_up, detail) = match check(&target, output).await {
+            Ok(ready) => (ready, probe.detail()),
+            Err(e) => {
+                eprintln!("{}", e);
+                (false, Some(e.to_string()))
+            }
+        };
+        alerts.flush(&mut throttle);
+        if is_up == up {
+            changed_checks = 0;
+            continue;
+        }
+
+        changed_checks += 1;
+        if changed_checks >= debounce {
+            up = is_up;
+            changed_checks = 0;
+            if up {
+                record(&states, &target, Status::Ready);
+                alerts.send(
+                    &mut throttle,
+                    alert(
+                        "What you are watching is back up",
+                        with_detail(&format!("{} is ready again", probe.target()), detail),
+                    ),
+                );
+            } else {
+                record(&states, &target, Status::Down);
+                alerts.send(
+                    &mut throttle,
+                    alert(
+                        "What you are watching went down",
+                        with_detail(&format!("{} is no longer ready", probe.target()), detail),
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Appends the details of the last check, if any, to a notification
+fn with_detail(body: &str, detail: Option<String>) -> String {
+    match detail {
+        Some(detail) => format!("{}: {}", body, detail),
+        None => body.to_string(),
+    }
+}
+
+/// How failed checks are retried
+#[derive(Debug, Clone, Copy)]
+struct Retry {
+    max_failures: Option<u32>,
+    max_backoff: Duration,
+}
+
+impl Retry {
+    /// Doubles the interval for every consecutive failure, up to the maximum backoff
+    fn delay(&self, interval: Duration, failures: u32) -> Duration {
+        interval
+            .checked_mul(2u32.saturating_pow(failures))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Polls the target until it is ready. Failed checks count as not ready and are retried
+/// with a backoff, until `max_failures` consecutive ones have failed.
+async fn wait_until_ready(
+    target: &Target,
+    retry: Retry,
+    output: OutputFormat,
+) -> Result<(), ProbeError> {
+    let mut failures = 0;
+    loop {
+        match check(target, output).await {
+            Ok(true) => return Ok(()),
+            Ok(false) => {
+                failures = 0;
+                clock::sleep(target.interval).await;
+            }
+            Err(e) => {
+                failures += 1;
+                if retry.max_failures.is_some_and(|max| failures >= max) {
+                    return Err(e);
+                }
+                let delay = retry.delay(target.interval, failures);
+                eprintln!("{} (retrying in {}s)", e, delay.as_secs());
+                clock::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Checks the target once, recording its latency and printing the attempt in the output format
+async fn check(target: &Target, output: OutputFormat) -> Result<bool, ProbeError> {
+    let started = Instant::now();
+    let result = target.probe.is_ready().await;
+    let attempt = Attempt::new(
+        target.probe.target(),
+        target.name.as_deref(),
+        started.elapsed(),
+        &result,
+    );
+    target
+        .stats
+        .lock()
+        .expect("stats lock poisoned")
+        .record(&attempt);
+    attempt.print(output);
+    result
+}
+
+#[test]
+fn test_at_positions() {
+    // --chain db api where api depends on cache, ordered as the stages [cache, db] and [api]
+    let positions = [None, Some(0), Some(1)];
+
+    assert_eq!(
+        at_positions(vec![Some(5), Some(10)], &positions, 2, None, "--interval"),
+        [None, Some(5), Some(10)]
+    );
+    assert_eq!(
+        at_positions(vec![Some(5)], &positions, 2, None, "--interval"),
+        [Some(5), Some(5), Some(5)]
+    );
+    assert_eq!(
+        at_positions(Vec::new(), &positions, 2, None::<u64>, "--interval"),
+        [None, None, None]
+    );
 }
@@ -0,0 +1,88 @@
+use regex::Regex;
+use serde_json::Value;
+
+/// A condition on the response body that must hold for the url to be ready
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Contains(String),
+    JsonPath(JsonPathMatch),
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn is_match(&self, body: &str) -> bool {
+        match self {
+            Matcher::Contains(text) => body.contains(text.as_str()),
+            Matcher::JsonPath(json_match) => json_match.is_match(body),
+            Matcher::Regex(re) => re.is_match(body),
+        }
+    }
+}
+
+/// Matches a field of a JSON body, selected by a dot separated path such as
+/// `status.db` or `checks.0.ok`, against a value
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPathMatch {
+    pub path: Vec<String>,
+    /// The expected value. Values that aren't valid JSON are compared as strings
+    /// so that `status=ok` doesn't need quotes.
+    pub value: Value,
+}
+
+impl JsonPathMatch {
+    /// Parses `<path>=<value>`, where the path may start with `$.`
+    pub fn parse(input: &str) -> Result<JsonPathMatch, String> {
+        let (path, value) = match input.find('=') {
+            Some(i) => (&input[..i], &input[i + 1..]),
+            None => return Err(format!("expected <path>=<value>, got `{}`", input)),
+        };
+        let path = path.trim_start_matches('$').trim_start_matches('.');
+        if path.is_empty() {
+            return Err(format!("missing path in `{}`", input));
+        }
+
+        Ok(JsonPathMatch {
+            path: path.split('.').map(|segment| segment.to_string()).collect(),
+            value: serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())),
+        })
+    }
+
+    pub fn is_match(&self, body: &str) -> bool {
+        let json: Value = match serde_json::from_str(body) {
+            Ok(json) => json,
+            Err(_) => return false,
+        };
+        let mut field = &json;
+        for segment in &self.path {
+            let next = match field {
+                Value::Object(map) => map.get(segment),
+                Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            };
+            field = match next {
+                Some(next) => next,
+                None => return false,
+            };
+        }
+        *field == self.value
+    }
+}
+
+#[test]
+fn test_json_path_match() {
+    let body = r#"{"status": "ok", "checks": [{"name": "db", "ok": true}], "workers": 3}"#;
+
+    assert!(JsonPathMatch::parse("status=ok").unwrap().is_match(body));
+    assert!(JsonPathMatch::parse("$.checks.0.ok=true")
+        .unwrap()
+        .is_match(body));
+    assert!(JsonPathMatch::parse("workers=3").unwrap().is_match(body));
+    assert!(!JsonPathMatch::parse("workers=4").unwrap().is_match(body));
+    assert!(!JsonPathMatch::parse("checks.1.ok=true")
+        .unwrap()
+        .is_match(body));
+    assert!(!JsonPathMatch::parse("status=ok")
+        .unwrap()
+        .is_match("not json"));
+    assert!(JsonPathMatch::parse("status").is_err());
+}
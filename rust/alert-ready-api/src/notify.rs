@@ -0,0 +1,40 @@
+use clap::ValueEnum;
+use notify_rust::Notification;
+use serde::Deserialize;
+
+use crate::{error::NotifyError, output::OutputFormat};
+
+/// Where notifications are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Notifier {
+    /// A desktop notification
+    Desktop,
+    /// A line printed on stdout, for terminals and logs. It goes to stderr with --output json
+    /// so that stdout only has json lines.
+    Stdout,
+}
+
+impl Notifier {
+    pub fn notify(
+        &self,
+        summary: &str,
+        body: &str,
+        output: OutputFormat,
+    ) -> Result<(), NotifyError> {
+        match self {
+            Notifier::Desktop => {
+                Notification::new()
+                    .summary(summary)
+                    .body(body)
+                    .show()
+                    .map_err(|source| NotifyError::Desktop { source })?;
+            }
+            Notifier::Stdout => match output {
+                OutputFormat::Text => println!("{}: {}", summary, body),
+                OutputFormat::Json => eprintln!("{}: {}", summary, body),
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::error::ProbeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Only errors and notifications sent to stdout are printed
+    Text,
+    /// One JSON line per check is printed on stdout
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptStatus {
+    Ready,
+    NotReady,
+    Error,
+}
+
+/// A single check of a target
+#[derive(Debug, Serialize)]
+pub struct Attempt<'a> {
+    pub timestamp: DateTime<Local>,
+    pub target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    pub latency_ms: u64,
+    pub status: AttemptStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<'a> Attempt<'a> {
+    pub fn new(
+        target: &'a str,
+        name: Option<&'a str>,
+        latency: Duration,
+        result: &Result<bool, ProbeError>,
+    ) -> Attempt<'a> {
+        let (status, error) = match result {
+            Ok(true) => (AttemptStatus::Ready, None),
+            Ok(false) => (AttemptStatus::NotReady, None),
+            Err(e) => (AttemptStatus::Error, Some(e.to_string())),
+        };
+        Attempt {
+            timestamp: Local::now(),
+            target,
+            name,
+            latency_ms: latency.as_millis() as u64,
+            status,
+            error,
+        }
+    }
+
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => match serde_json::to_string(self) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Error writing json output: {}", e),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageStatus {
+    Waiting,
+    Ready,
+    TimedOut,
+}
+
+/// Progress of a stage of the targets waited for with --chain
+#[derive(Debug, Serialize)]
+pub struct StageProgress<'a> {
+    pub timestamp: DateTime<Local>,
+    /// 1-based position of the stage
+    pub stage: usize,
+    pub stages: usize,
+    pub targets: Vec<&'a str>,
+    pub status: StageStatus,
+    /// Time since the stage started, once it is ready or timed out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+}
+
+impl<'a> StageProgress<'a> {
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => {
+                let elapsed = humantime::format_duration(Duration::from_secs(
+                    self.elapsed_ms.unwrap_or_default() / 1000,
+                ));
+                let targets = self.targets.join(", ");
+                match self.status {
+                    StageStatus::Waiting => {
+                        println!(
+                            "Stage {}/{}: waiting for {}",
+                            self.stage, self.stages, targets
+                        )
+                    }
+                    StageStatus::Ready => println!(
+                        "Stage {}/{} ready after {}: {}",
+                        self.stage, self.stages, elapsed, targets
+                    ),
+                    StageStatus::TimedOut => println!(
+                        "Stage {}/{} timed out after {}: {}",
+                        self.stage, self.stages, elapsed, targets
+                    ),
+                }
+            }
+            OutputFormat::Json => match serde_json::to_string(self) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Error writing json output: {}", e),
+            },
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::{io, process::Stdio, sync::Mutex, time::Duration};
+
+use alert_ready_api::request::HttpRequest;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::{
+    net::{self, TcpStream},
+    process::Command,
+    time,
+};
+
+use crate::error::ProbeError;
+use crate::matcher::Matcher;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeKind {
+    /// The url responds with a successful status
+    Http,
+    /// A connection to `host:port` is accepted
+    Tcp,
+    /// The shell command exits successfully
+    Cmd,
+    /// The name resolves
+    Dns,
+    /// The certificate of the https url is valid for more than --cert-days days
+    Cert,
+}
+
+/// A check of whether something is ready, polled until it is
+#[async_trait]
+pub trait Probe: Send + Sync {
+    /// Returns whether the target is ready, or the error that prevented checking it
+    async fn is_ready(&self) -> Result<bool, ProbeError>;
+
+    /// What is being waited for, as shown in the notification
+    fn target(&self) -> &str;
+
+    /// Details about the last check to show in notifications
+    fn detail(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Ready once the url responds successfully with a body satisfying every matcher
+pub struct HttpProbe {
+    pub request: HttpRequest,
+    pub url: String,
+    pub matchers: Vec<Matcher>,
+}
+
+#[async_trait]
+impl Probe for HttpProbe {
+    async fn is_ready(&self) -> Result<bool, ProbeError> {
+        let response =
+            self.request
+                .to(&self.url)
+                .send()
+                .await
+                .map_err(|e| ProbeError::Request {
+                    source: e,
+                    url: self.url.clone(),
+                })?;
+        let status = response.status();
+        if !status.is_success() {
+            return Ok(false);
+        }
+        if self.matchers.is_empty() {
+            return Ok(true);
+        }
+
+        let body = response.text().await.map_err(|e| ProbeError::ReadBody {
+            source: e,
+            url: self.url.clone(),
+        })?;
+        Ok(self.matchers.iter().all(|matcher| matcher.is_match(&body)))
+    }
+
+    fn target(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Ready once a connection to `host:port` is accepted
+pub struct TcpProbe {
+    pub addr: String,
+    pub timeout: Duration,
+}
+
+#[async_trait]
+impl Probe for TcpProbe {
+    async fn is_ready(&self) -> Result<bool, ProbeError> {
+        let addrs = net::lookup_host(&self.addr)
+            .await
+            .map_err(|e| ProbeError::Resolve {
+                source: e,
+                addr: self.addr.clone(),
+            })?;
+
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+        for addr in addrs {
+            match time::timeout(self.timeout, TcpStream::connect(addr)).await {
+                Ok(Ok(_)) => return Ok(true),
+                Ok(Err(e)) => last_err = e,
+                Err(_) => {
+                    last_err = io::Error::new(io::ErrorKind::TimedOut, "connection timed out")
+                }
+            }
+        }
+        Err(ProbeError::Connect {
+            source: last_err,
+            addr: self.addr.clone(),
+        })
+    }
+
+    fn target(&self) -> &str {
+        &self.addr
+    }
+}
+
+/// Ready once the shell command exits successfully, e.g. `pg_isready`
+pub struct CmdProbe {
+    pub command: String,
+}
+
+#[async_trait]
+impl Probe for CmdProbe {
+    async fn is_ready(&self) -> Result<bool, ProbeError> {
+        Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .status()
+            .await
+            .map(|status| status.success())
+            .map_err(|e| ProbeError::Spawn {
+                source: e,
+                command: self.command.clone(),
+            })
+    }
+
+    fn target(&self) -> &str {
+        &self.command
+    }
+}
+
+/// Ready once the name resolves to at least one address. Failing to resolve is what is
+/// being waited for, so it is not an error.
+pub struct DnsProbe {
+    pub name: String,
+}
+
+#[async_trait]
+impl Probe for DnsProbe {
+    async fn is_ready(&self) -> Result<bool, ProbeError> {
+        Ok(net::lookup_host((self.name.as_str(), 0))
+            .await
+            .map(|mut addrs| addrs.next().is_some())
+            .unwrap_or(false))
+    }
+
+    fn target(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Ready once the certificate of the https url expires in more than `min_days` days.
+/// Failed handshakes, such as with an expired certificate, are failed checks.
+pub struct CertProbe {
+    client: reqwest::Client,
+    url: String,
+    min_days: i64,
+    /// Issuer and expiry of the certificate seen by the last check
+    last_seen: Mutex<Option<String>>,
+}
+
+impl CertProbe {
+    pub fn new(url: String, min_days: u32) -> Result<CertProbe, ProbeError> {
+        let client = reqwest::Client::builder()
+            .tls_info(true)
+            .build()
+            .map_err(|e| ProbeError::Client { source: e })?;
+        Ok(CertProbe {
+            client,
+            url,
+            min_days: i64::from(min_days),
+            last_seen: Mutex::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl Probe for CertProbe {
+    async fn is_ready(&self) -> Result<bool, ProbeError> {
+        let response =
+            self.client
+                .head(&self.url)
+                .send()
+                .await
+                .map_err(|e| ProbeError::Request {
+                    source: e,
+                    url: self.url.clone(),
+                })?;
+        let der = response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|tls_info| tls_info.peer_certificate())
+            .ok_or_else(|| ProbeError::NoCertificate {
+                url: self.url.clone(),
+            })?;
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(der).map_err(|e| ProbeError::Certificate {
+                url: self.url.clone(),
+                reason: e.to_string(),
+            })?;
+
+        let not_after = cert.validity().not_after.timestamp();
+        let days_left = (not_after - Utc::now().timestamp()) / (24 * 60 * 60);
+        let expires = Utc
+            .timestamp_opt(not_after, 0)
+            .single()
+            .map(|expires| expires.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| not_after.to_string());
+        *self.last_seen.lock().expect("last seen lock poisoned") = Some(format!(
+            "certificate issued by {} expires {} ({} days left)",
+            cert.issuer(),
+            expires,
+            days_left
+        ));
+        Ok(days_left > self.min_days)
+    }
+
+    fn target(&self) -> &str {
+        &self.url
+    }
+
+    fn detail(&self) -> Option<String> {
+        self.last_seen
+            .lock()
+            .expect("last seen lock poisoned")
+            .clone()
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue},
+    Client, Method, RequestBuilder,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RequestError {
+    #[error("Invalid http method `{}`", method)]
+    InvalidMethod { method: String },
+
+    #[error("Invalid header `{}`, expected `Name: value`", header)]
+    MalformedHeader { header: String },
+
+    #[error("Invalid header name `{}`: {}", name, source)]
+    InvalidHeaderName {
+        source: InvalidHeaderName,
+        name: String,
+    },
+
+    #[error("Invalid value of header `{}`: {}", name, source)]
+    InvalidHeaderValue {
+        source: InvalidHeaderValue,
+        name: String,
+    },
+
+    #[error("Error building http client: {}", source)]
+    Client { source: reqwest::Error },
+}
+
+/// How to request a url, as given on the command line or in the config.
+///
+/// ```toml
+/// method = "POST"
+/// headers = { Content-Type = "application/json" }
+/// body = '{"check": "deep"}'
+/// bearer_token = "..."
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestSpec {
+    /// Defaults to GET
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: Option<String>,
+    /// Accept invalid certificates, such as self signed ones
+    #[serde(default)]
+    pub insecure: bool,
+    /// `user:password`, or `user` alone for no password
+    pub basic_auth: Option<String>,
+    pub bearer_token: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl RequestSpec {
+    /// Parses a `Name: value` header and adds it to the headers
+    pub fn add_header(&mut self, header: &str) -> Result<(), RequestError> {
+        match header.split_once(':') {
+            Some((name, value)) => {
+                self.headers
+                    .insert(name.trim().to_string(), value.trim().to_string());
+                Ok(())
+            }
+            None => Err(RequestError::MalformedHeader {
+                header: header.to_string(),
+            }),
+        }
+    }
+
+    /// Validates the spec, returning a request that can be sent repeatedly
+    pub fn prepare(&self) -> Result<HttpRequest, RequestError> {
+        let method = match &self.method {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|_| {
+                RequestError::InvalidMethod {
+                    method: method.clone(),
+                }
+            })?,
+            None => Method::GET,
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                RequestError::InvalidHeaderName {
+                    source: e,
+                    name: name.clone(),
+                }
+            })?;
+            let header_value =
+                HeaderValue::from_str(value).map_err(|e| RequestError::InvalidHeaderValue {
+                    source: e,
+                    name: name.clone(),
+                })?;
+            headers.insert(header_name, header_value);
+        }
+
+        let mut client = Client::builder().danger_accept_invalid_certs(self.insecure);
+        if let Some(user_agent) = &self.user_agent {
+            client = client.user_agent(user_agent.as_str());
+        }
+        let client = client
+            .build()
+            .map_err(|e| RequestError::Client { source: e })?;
+
+        Ok(HttpRequest {
+            client,
+            method,
+            headers,
+            body: self.body.clone(),
+            basic_auth: self
+                .basic_auth
+                .as_ref()
+                .map(|auth| match auth.split_once(':') {
+                    Some((user, password)) => (user.to_string(), Some(password.to_string())),
+                    None => (auth.to_string(), None),
+                }),
+            bearer_token: self.bearer_token.clone(),
+        })
+    }
+}
+
+/// A validated request, along with the client sending it
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    client: Client,
+    method: Method,
+    headers: HeaderMap,
+    body: Option<String>,
+    basic_auth: Option<(String, Option<String>)>,
+    bearer_token: Option<String>,
+}
+
+impl HttpRequest {
+    /// Returns a builder of the request to `url`, ready to be sent
+    pub fn to(&self, url: &str) -> RequestBuilder {
+        let mut builder = self
+            .client
+            .request(self.method.clone(), url)
+            .headers(self.headers.clone());
+        if let Some(body) = &self.body {
+            builder = builder.body(body.clone());
+        }
+        if let Some((user, password)) = &self.basic_auth {
+            builder = builder.basic_auth(user, password.as_ref());
+        }
+        if let Some(token) = &self.bearer_token {
+            builder = builder.bearer_auth(token);
+        }
+        builder
+    }
+}
@@ -0,0 +1,66 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+
+use crate::error::ServeError;
+use crate::stats::TargetStatus;
+
+#[derive(Serialize)]
+struct StatusResponse {
+    targets: Vec<TargetStatus>,
+}
+
+/// Serves `GET /status`, the JSON of the current status of every target as returned by
+/// `statuses`, until the process exits
+pub async fn serve<F>(addr: SocketAddr, statuses: F) -> Result<(), ServeError>
+where
+    F: Fn() -> Vec<TargetStatus> + Send + Sync + 'static,
+{
+    let statuses = Arc::new(statuses);
+    let make_service = make_service_fn(move |_conn| {
+        let statuses = Arc::clone(&statuses);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let statuses = Arc::clone(&statuses);
+                async move { Ok::<_, Infallible>(respond(&request, &*statuses)) }
+            }))
+        }
+    });
+
+    Server::try_bind(&addr)
+        .map_err(|e| ServeError::Bind { source: e, addr })?
+        .serve(make_service)
+        .await
+        .map_err(|e| ServeError::Serve { source: e, addr })
+}
+
+fn respond(request: &Request<Body>, statuses: &dyn Fn() -> Vec<TargetStatus>) -> Response<Body> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/status") => {
+            let response = StatusResponse {
+                targets: statuses(),
+            };
+            match serde_json::to_vec(&response) {
+                Ok(body) => Response::builder()
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .expect("failed building status response"),
+                Err(e) => with_status(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            }
+        }
+        (_, "/status") => with_status(StatusCode::METHOD_NOT_ALLOWED, "only GET is allowed"),
+        _ => with_status(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn with_status<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .expect("failed building response")
+}
@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::error::StateError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ready,
+    Down,
+    /// Given up on after too many failed checks
+    Failed,
+}
+
+/// The last known state of a named target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetState {
+    pub status: Status,
+    pub checked_at: DateTime<Local>,
+}
+
+/// The last known states of the named targets, saved across runs
+pub struct States {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, TargetState>>,
+}
+
+impl States {
+    /// Loads the states saved at `path`, starting empty when there are none yet
+    pub fn load(path: &Path) -> Result<States, StateError> {
+        let entries = if path.is_file() {
+            let contents = fs::read_to_string(path).map_err(|e| StateError::Read {
+                source: e,
+                path: path.to_path_buf(),
+            })?;
+            serde_json::from_str(&contents).map_err(|e| StateError::Parse {
+                source: e,
+                path: path.to_path_buf(),
+            })?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(States {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<TargetState> {
+        self.entries
+            .lock()
+            .expect("states lock poisoned")
+            .get(name)
+            .cloned()
+    }
+
+    /// Records the status of the target as of now and saves every state
+    pub fn record(&self, name: &str, status: Status) -> Result<(), StateError> {
+        let mut entries = self.entries.lock().expect("states lock poisoned");
+        entries.insert(
+            name.to_string(),
+            TargetState {
+                status,
+                checked_at: Local::now(),
+            },
+        );
+
+        let write_err = |e| StateError::Write {
+            source: e,
+            path: self.path.clone(),
+        };
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(write_err)?;
+        }
+        let contents = serde_json::to_string_pretty(&*entries).map_err(|e| StateError::Parse {
+            source: e,
+            path: self.path.clone(),
+        })?;
+        fs::write(&self.path, contents).map_err(write_err)
+    }
+}
+
+/// Returns `$XDG_STATE_HOME/alert-ready-api/state.json`, defaulting to `~/.local/state`
+pub fn default_state_path() -> PathBuf {
+    let state_home = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    state_home.join("alert-ready-api").join("state.json")
+}
@@ -0,0 +1,189 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::error::HistoryError;
+use crate::output::{Attempt, AttemptStatus, OutputFormat};
+
+/// The checks of a target since it started being waited for
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    attempts: u32,
+    failed_attempts: u32,
+    /// Latencies of the checks that didn't fail
+    latencies: Vec<Duration>,
+    ready_after: Option<Duration>,
+    last_check: Option<LastCheck>,
+}
+
+/// The outcome of the latest check of a target
+#[derive(Debug, Clone, Serialize)]
+pub struct LastCheck {
+    pub timestamp: DateTime<Local>,
+    pub status: AttemptStatus,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The current state of a target, as served on `/status`
+#[derive(Debug, Serialize)]
+pub struct TargetStatus {
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub attempts: u32,
+    pub failed_attempts: u32,
+    pub last_check: Option<LastCheck>,
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            attempts: 0,
+            failed_attempts: 0,
+            latencies: Vec::new(),
+            ready_after: None,
+            last_check: None,
+        }
+    }
+
+    pub fn record(&mut self, attempt: &Attempt) {
+        self.attempts += 1;
+        match attempt.status {
+            AttemptStatus::Ready | AttemptStatus::NotReady => {
+                self.latencies
+                    .push(Duration::from_millis(attempt.latency_ms));
+                if attempt.status == AttemptStatus::Ready && self.ready_after.is_none() {
+                    self.ready_after = Some(self.started.elapsed());
+                }
+            }
+            AttemptStatus::Error => self.failed_attempts += 1,
+        }
+        self.last_check = Some(LastCheck {
+            timestamp: attempt.timestamp,
+            status: attempt.status,
+            latency_ms: attempt.latency_ms,
+            error: attempt.error.clone(),
+        });
+    }
+
+    pub fn status(&self, target: &str, name: Option<&str>) -> TargetStatus {
+        TargetStatus {
+            target: target.to_string(),
+            name: name.map(str::to_string),
+            attempts: self.attempts,
+            failed_attempts: self.failed_attempts,
+            last_check: self.last_check.clone(),
+        }
+    }
+
+    pub fn report(&self, target: &str, name: Option<&str>) -> Report {
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        Report {
+            timestamp: Local::now(),
+            target: target.to_string(),
+            name: name.map(str::to_string),
+            attempts: self.attempts,
+            failed_attempts: self.failed_attempts,
+            elapsed_ms: millis(self.ready_after.unwrap_or_else(|| self.started.elapsed())),
+            p50_latency_ms: percentile(&latencies, 50).map(millis),
+            p95_latency_ms: percentile(&latencies, 95).map(millis),
+        }
+    }
+}
+
+/// Returns the nearest-rank percentile of sorted durations
+fn percentile(sorted: &[Duration], percent: usize) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100);
+    Some(sorted[rank.max(1) - 1])
+}
+
+#[test]
+fn test_percentile() {
+    let latencies: Vec<Duration> = (1..=20).map(Duration::from_millis).collect();
+
+    assert_eq!(percentile(&latencies, 50), Some(Duration::from_millis(10)));
+    assert_eq!(percentile(&latencies, 95), Some(Duration::from_millis(19)));
+    assert_eq!(
+        percentile(&latencies[..1], 95),
+        Some(Duration::from_millis(1))
+    );
+    assert_eq!(percentile(&[], 50), None);
+}
+
+/// Summary of how long a target took to be ready
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub timestamp: DateTime<Local>,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub attempts: u32,
+    pub failed_attempts: u32,
+    pub elapsed_ms: u64,
+    pub p50_latency_ms: Option<u64>,
+    pub p95_latency_ms: Option<u64>,
+}
+
+impl Report {
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => {
+                let latency = match (self.p50_latency_ms, self.p95_latency_ms) {
+                    (Some(p50), Some(p95)) => format!(", latency p50 {}ms p95 {}ms", p50, p95),
+                    _ => String::new(),
+                };
+                println!(
+                    "{} ready after {}: {} attempts ({} failed){}",
+                    self.target,
+                    humantime::format_duration(Duration::from_millis(self.elapsed_ms)),
+                    self.attempts,
+                    self.failed_attempts,
+                    latency
+                );
+            }
+            OutputFormat::Json => match serde_json::to_string(self) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Error writing json output: {}", e),
+            },
+        }
+    }
+
+    /// Appends the report as a JSON line to the history file
+    pub fn append_to(&self, path: &Path) -> Result<(), HistoryError> {
+        let line = serde_json::to_string(self).map_err(|e| HistoryError::Serialize {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        let write_err = |e| HistoryError::Write {
+            source: e,
+            path: path.to_path_buf(),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(write_err)?;
+        writeln!(file, "{}", line).map_err(write_err)
+    }
+}
@@ -1,9 +1,25 @@
-use clap::{IntoApp, Parser, Subcommand};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{Cursor, Write},
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use clap::{ArgEnum, IntoApp, Parser, Subcommand};
 use clap_complete;
 use cmd_queue::{
-    cli_util, client::Client, constants, error::CmdqClientError, CommandRequest, TaskState,
+    cli_util,
+    client::Client,
+    constants,
+    daemon::{self, PidStatus},
+    error::CmdqClientError,
+    execution::outcome::SuccessPolicy,
+    ytdlp_args, CommandRequest, CommandResponse, ResubmitRequest, Task, TaskState,
 };
+use dialoguer::Select;
 use reqwest;
+use skim::prelude::{Skim, SkimItemReader, SkimOptionsBuilder};
 
 #[derive(Parser, Debug)]
 #[clap(name = "cmdq")]
@@ -13,6 +29,58 @@ use reqwest;
 struct Cli {
     #[clap(help = "server url", env = "CMDQ_SERVER_URL")]
     pub server_url: String,
+    #[clap(long, help = "Queue without asking for confirmation on warnings")]
+    pub force: bool,
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Label the queued task, as KEY=VALUE"
+    )]
+    pub label: Vec<String>,
+    #[clap(
+        long,
+        help = "Upload FILE with the command, its path on the server replaces {input} in the args"
+    )]
+    pub upload: Option<String>,
+    #[clap(
+        long,
+        parse(try_from_str = humantime::parse_duration),
+        help = "Expire the task instead of running it if it is still queued after TTL, e.g. 2h"
+    )]
+    pub ttl: Option<Duration>,
+    #[clap(
+        long,
+        parse(try_from_str = humantime::parse_duration),
+        help = "Kill the task if it runs for longer than TIMEOUT, e.g. 30m"
+    )]
+    pub timeout: Option<Duration>,
+    #[clap(
+        long,
+        help = "Run the task over ssh on HOST, in the same directory on HOST as the current one"
+    )]
+    pub host: Option<String>,
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Run the task with the environment variables matching PATTERN, where * matches anything, e.g. HTTP_PROXY*"
+    )]
+    pub capture_env: Vec<String>,
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Non-zero exit code the task succeeds with, e.g. 1 for yt-dlp playlists with unavailable videos"
+    )]
+    pub success_exit_code: Vec<i32>,
+    #[clap(
+        long,
+        help = "The task succeeds whatever its exit code when REGEX matches its stdout or stderr"
+    )]
+    pub success_regex: Option<String>,
+    #[clap(
+        long,
+        help = "Queue the command only once for KEY, submitting it again with the same KEY returns the task queued first"
+    )]
+    pub idempotency_key: Option<String>,
     #[clap(help = "command to queue")]
     pub input: Vec<String>,
 
@@ -28,9 +96,51 @@ enum Subcommands {
         #[clap(long, short, help = "Optional prefix to filename downloaded")]
         prefix: Option<String>,
     },
+    /// Transcode a file with ffmpeg
+    Ffmpeg {
+        input: String,
+        #[clap(long, short, arg_enum, default_value = "h264", help = "Output preset")]
+        preset: FfmpegPreset,
+        #[clap(
+            long,
+            short,
+            default_value = "mp4",
+            help = "Container of the output file"
+        )]
+        container: String,
+        #[clap(
+            long,
+            short,
+            help = "Output file, defaults to the input file name with the preset and container"
+        )]
+        output: Option<String>,
+    },
+    /// Queue again a task that failed or completed, optionally with modified args
+    Resubmit {
+        id: String,
+        #[clap(
+            long,
+            multiple_occurrences = true,
+            help = "Replace OLD with NEW in every arg of the task, as OLD=NEW"
+        )]
+        arg_replace: Vec<String>,
+    },
+    /// List the tasks that finished, failed or expired
+    History,
+    /// Show a task with the exit code and stderr of each of its attempts
+    Show { id: String },
+    /// Pick tasks from a fuzzy-searchable list to cancel, retry or bump to the front of the queue
+    Pick,
     List {
         #[clap(long, short, help = "Filter by running tasks")]
         running: bool,
+        #[clap(long, short, help = "Filter by label, as KEY:VALUE or KEY")]
+        label: Option<String>,
+    },
+    /// Start, stop or check on the server running as a daemon
+    Server {
+        #[clap(subcommand)]
+        action: ServerAction,
     },
     GenerateCompletion {
         #[clap(arg_enum)]
@@ -38,32 +148,135 @@ enum Subcommands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum ServerAction {
+    /// Start the server as a daemon, unless one is already running
+    Start,
+    /// Stop the server started with `cmdq server start`
+    Stop,
+    /// Show whether the server is running
+    Status,
+}
+
+/// How many times and how often to check whether a server that was just started answers
+const SERVER_START_CHECKS: usize = 20;
+const SERVER_START_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum FfmpegPreset {
+    /// H.264 video and AAC audio, plays about everywhere
+    H264,
+    /// H.265 video and AAC audio, about half the size of H.264 for the same quality
+    Hevc,
+    /// Only change the container, copying the streams as they are
+    Copy,
+}
+
+impl FfmpegPreset {
+    fn name(&self) -> &'static str {
+        match self {
+            FfmpegPreset::H264 => "h264",
+            FfmpegPreset::Hevc => "hevc",
+            FfmpegPreset::Copy => "copy",
+        }
+    }
+
+    fn args(&self) -> Vec<&'static str> {
+        match self {
+            FfmpegPreset::H264 => vec![
+                "-c:v", "libx264", "-preset", "medium", "-crf", "23", "-c:a", "aac",
+            ],
+            FfmpegPreset::Hevc => vec![
+                "-c:v", "libx265", "-preset", "medium", "-crf", "28", "-c:a", "aac",
+            ],
+            FfmpegPreset::Copy => vec!["-c", "copy"],
+        }
+    }
+}
+
 fn main() -> Result<(), CmdqClientError> {
     let cli = Cli::parse();
     // TODO print as debug
     //println!("{:?}", cli);
     let cwd = std::env::current_dir().expect("current dir");
 
-    let cli_app = CliApp::new(cli.server_url);
+    let labels = cli
+        .label
+        .iter()
+        .map(|label| {
+            label
+                .split_once('=')
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .ok_or_else(|| CmdqClientError::InvalidLabel(label.to_string()))
+        })
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    let env = std::env::vars()
+        .filter(|(name, _)| {
+            cli.capture_env
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let options = SubmitOptions {
+        force: cli.force,
+        labels,
+        env,
+        upload: cli.upload,
+        ttl: cli.ttl,
+        timeout: cli.timeout,
+        host: cli.host,
+        success: SuccessPolicy {
+            exit_codes: cli.success_exit_code,
+            output_regex: cli.success_regex,
+        },
+        idempotency_key: cli.idempotency_key,
+    };
+    let cli_app = CliApp::new(cli.server_url, options);
 
     if !cli.input.is_empty() && cli.subcommands.is_some() {
         println!("Sorry, but I don't know what to do both INPUT and subcommand were encountered. Going to sleep instead.");
         Ok(())
     } else if let Some(subcommand) = cli.subcommands {
         match subcommand {
-            Subcommands::Ytdlp { url, prefix } => {
-                let args = if let Some(prefix) = prefix {
-                    vec![
-                        "-o".to_string(),
-                        format!("{} %(title)s [%(id)s].%(ext)s", prefix),
-                        url,
-                    ]
-                } else {
-                    vec![url]
-                };
-                cli_app.command_request(&cwd.to_string_lossy(), "yt-dlp", args)
+            Subcommands::Ytdlp { url, prefix } => cli_app.command_request(
+                &cwd.to_string_lossy(),
+                "yt-dlp",
+                ytdlp_args(url, prefix.as_deref()),
+            ),
+            Subcommands::Ffmpeg {
+                input,
+                preset,
+                container,
+                output,
+            } => {
+                let output = output.unwrap_or_else(|| {
+                    let input = Path::new(&input);
+                    let stem = input
+                        .file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                        .unwrap_or("output".to_string());
+                    input
+                        .with_file_name(format!("{}.{}.{}", stem, preset.name(), container))
+                        .to_string_lossy()
+                        .to_string()
+                });
+                // -n never overwrites an existing output, the task fails instead
+                let mut args = vec!["-nostdin".to_string(), "-n".to_string()];
+                args.extend(["-i".to_string(), input]);
+                args.extend(preset.args().into_iter().map(str::to_string));
+                args.push(output);
+                cli_app.command_request(&cwd.to_string_lossy(), "ffmpeg", args)
             }
-            Subcommands::List { running } => cli_app.list_tasks(running),
+            Subcommands::Resubmit { id, arg_replace } => cli_app.resubmit(&id, &arg_replace),
+            Subcommands::History => cli_app.list_history(),
+            Subcommands::Show { id } => cli_app.show_task(&id),
+            Subcommands::Pick => cli_app.pick(),
+            Subcommands::Server { action } => match action {
+                ServerAction::Start => cli_app.start_server(),
+                ServerAction::Stop => cli_app.stop_server(),
+                ServerAction::Status => cli_app.server_status(),
+            },
+            Subcommands::List { running, label } => cli_app.list_tasks(running, label.as_deref()),
             Subcommands::GenerateCompletion { shell } => {
                 print_completions(shell, &mut Cli::command_for_update());
                 Ok(())
@@ -81,19 +294,164 @@ fn main() -> Result<(), CmdqClientError> {
     }
 }
 
+/// Whether `name` matches `pattern`, in which `*` matches any number of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts = parts.collect::<Vec<_>>();
+    match parts.split_last() {
+        // No * in the pattern
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim(), "y" | "Y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+/// What is done to the tasks picked with `cmdq pick`
+#[derive(Clone, Copy, Debug)]
+enum PickAction {
+    Cancel,
+    Retry,
+    Bump,
+}
+
+impl PickAction {
+    const ALL: [PickAction; 3] = [PickAction::Cancel, PickAction::Retry, PickAction::Bump];
+
+    fn describe(&self) -> &'static str {
+        match self {
+            PickAction::Cancel => "Cancel queued tasks",
+            PickAction::Retry => "Retry finished tasks",
+            PickAction::Bump => "Bump queued tasks to the front of the queue",
+        }
+    }
+}
+
+/// Line of a task in the list of `cmdq pick`, starting with its id
+fn pick_line(task: &Task, status: &str) -> String {
+    let command = task.command();
+    format!(
+        "{}  {:<17}  {} {}  {}",
+        task.id(),
+        status,
+        command.program,
+        command.args.join(" "),
+        command.path
+    )
+}
+
+/// Ids of the tasks picked from a fuzzy-searchable list of `pick_line`s, empty if aborted
+fn pick_tasks(lines: Vec<String>) -> Vec<String> {
+    let options = SkimOptionsBuilder::default()
+        .multi(true)
+        .prompt(Some("task> "))
+        .header(Some("TAB to pick tasks, ENTER to confirm, ESC to quit"))
+        .build()
+        .expect("failed building task picker");
+    let items = SkimItemReader::default().of_bufread(Cursor::new(lines.join("\n")));
+    Skim::run_with(&options, Some(items))
+        .filter(|output| !output.is_abort)
+        .map(|output| {
+            output
+                .selected_items
+                .iter()
+                .filter_map(|item| item.output().split_whitespace().next().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
 fn print_completions<G: clap_complete::Generator>(gen: G, cmd: &mut clap::Command) {
     clap_complete::generate(gen, cmd, cmd.get_name().to_string(), &mut std::io::stdout());
 }
 
+/// Options of the Cli applying to every queued command
+struct SubmitOptions {
+    force: bool,
+    labels: BTreeMap<String, String>,
+    env: BTreeMap<String, String>,
+    upload: Option<String>,
+    ttl: Option<Duration>,
+    timeout: Option<Duration>,
+    host: Option<String>,
+    success: SuccessPolicy,
+    idempotency_key: Option<String>,
+}
+
 pub struct CliApp {
     client: Client,
+    server_url: String,
+    options: SubmitOptions,
 }
 
 impl CliApp {
-    fn new(server_url: String) -> Self {
+    fn new(server_url: String, options: SubmitOptions) -> Self {
         CliApp {
             client: Client::new(&server_url).expect("failed creating client"),
+            server_url,
+            options,
+        }
+    }
+
+    /// Queues the command, asking whether to queue it anyway when the server warns about it
+    fn queue(&self, command: CommandRequest) -> Result<(), CmdqClientError> {
+        let response = match &self.options.upload {
+            Some(file) => self.client.queue_command_with_upload(&command, file)?,
+            None => self.client.queue_command(&command, self.options.force)?,
+        };
+        self.handle_response(response, || self.client.queue_command(&command, true))
+    }
+
+    /// Prints the outcome of queueing a command, queueing it anyway with `force` when the
+    /// server warns about it and the user confirms
+    fn handle_response(
+        &self,
+        response: CommandResponse,
+        force: impl FnOnce() -> Result<CommandResponse, CmdqClientError>,
+    ) -> Result<(), CmdqClientError> {
+        match response {
+            CommandResponse::Warning(warning) => {
+                println!("{}", warning);
+                if confirm("Queue anyway?") {
+                    force()?;
+                } else {
+                    println!("no command queued");
+                }
+            }
+            CommandResponse::Rejected(rejected) => {
+                return Err(CmdqClientError::CommandRejected(rejected))
+            }
+            CommandResponse::Failed(_) => println!("Failed to queue command"),
+            CommandResponse::Success(receipt) => match (receipt.task_id, receipt.duplicate) {
+                (Some(id), true) => println!("already queued as {}", id),
+                (Some(id), false) => println!("queued as {}", id),
+                (None, _) => {}
+            },
         }
+        Ok(())
     }
 
     fn command_request(
@@ -102,21 +460,233 @@ impl CliApp {
         program: &str,
         args: Vec<String>,
     ) -> Result<(), CmdqClientError> {
-        let _cmd_resp = self.client.queue_command(CommandRequest {
+        self.queue(CommandRequest {
             path: dir.to_string(),
             program: program.to_string(),
             args: args,
-        })?;
+            resubmitted_from: None,
+            labels: self.options.labels.clone(),
+            ttl_secs: self.options.ttl.map(|ttl| ttl.as_secs()),
+            timeout_secs: self.options.timeout.map(|timeout| timeout.as_secs()),
+            host: self.options.host.clone(),
+            env: self.options.env.clone(),
+            success: self.options.success.clone(),
+            idempotency_key: self.options.idempotency_key.clone(),
+        })
+    }
+
+    fn resubmit(&self, id: &str, arg_replace: &[String]) -> Result<(), CmdqClientError> {
+        let replacements = arg_replace
+            .iter()
+            .map(|replace| {
+                replace
+                    .split_once('=')
+                    .map(|(old, new)| (old.to_string(), new.to_string()))
+                    .ok_or_else(|| CmdqClientError::InvalidArgReplace(replace.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Resubmitted by the server, since the environment captured by the task isn't sent
+        let resubmit = ResubmitRequest {
+            arg_replace: replacements,
+            idempotency_key: self.options.idempotency_key.clone(),
+        };
+        let response = self.client.resubmit(id, &resubmit, self.options.force)?;
+        self.handle_response(response, || self.client.resubmit(id, &resubmit, true))
+    }
+
+    fn list_history(&self) -> Result<(), CmdqClientError> {
+        let entries = self.client.list_history()?;
+        cli_util::print_history_as_table(entries).expect("failed print history");
+        Ok(())
+    }
+
+    fn show_task(&self, id: &str) -> Result<(), CmdqClientError> {
+        let detail = self.client.get_task(id)?;
+        cli_util::print_task_detail(detail).expect("failed print task");
+        Ok(())
+    }
+
+    fn pick(&self) -> Result<(), CmdqClientError> {
+        let queued = self.client.list_tasks(TaskState::Queued, None)?;
+        let running = self.client.list_tasks(TaskState::Running, None)?;
+        let pending = queued
+            .iter()
+            .chain(&running)
+            .map(|listed| listed.task.id().to_string())
+            .collect::<HashSet<_>>();
+        // Tasks failing with retries left are in the history as well as the queue
+        let finished = self
+            .client
+            .list_history()?
+            .into_iter()
+            .filter(|entry| !pending.contains(entry.task.id()))
+            .collect::<Vec<_>>();
+
+        let lines =
+            queued
+                .iter()
+                .map(|listed| pick_line(&listed.task, "queued"))
+                .chain(
+                    running
+                        .iter()
+                        .map(|listed| pick_line(&listed.task, "running")),
+                )
+                .chain(finished.iter().map(|entry| {
+                    pick_line(&entry.task, &format!("{:?}", entry.result).to_lowercase())
+                }))
+                .collect::<Vec<_>>();
+        if lines.is_empty() {
+            println!("no tasks");
+            return Ok(());
+        }
+        let ids = pick_tasks(lines);
+        if ids.is_empty() {
+            println!("no task picked");
+            return Ok(());
+        }
+
+        let descriptions = PickAction::ALL
+            .iter()
+            .map(|action| action.describe())
+            .collect::<Vec<_>>();
+        let selection = Select::new()
+            .with_prompt(format!("{} tasks picked", ids.len()))
+            .items(&descriptions)
+            .default(0)
+            .interact_opt()
+            .expect("failed reading action");
+        let action = match selection {
+            Some(i) => PickAction::ALL[i],
+            None => {
+                println!("nothing done");
+                return Ok(());
+            }
+        };
+
+        let finished = finished
+            .iter()
+            .map(|entry| entry.task.id())
+            .collect::<HashSet<_>>();
+        for id in &ids {
+            let result = match action {
+                PickAction::Cancel => self.client.cancel_task(id),
+                PickAction::Bump => self.client.bump_task(id),
+                PickAction::Retry if finished.contains(id.as_str()) => self.resubmit(id, &[]),
+                PickAction::Retry => {
+                    println!("Task {} didn't finish, only finished tasks are retried", id);
+                    continue;
+                }
+            };
+            match result {
+                Ok(()) => println!("{:?} {}", action, id),
+                Err(err) => println!("{}", err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the server unless it is running. A pid file left behind by a server that died is
+    /// removed first, and a server answering without a pid file isn't started twice.
+    fn start_server(&self) -> Result<(), CmdqClientError> {
+        match daemon::pid_status()? {
+            PidStatus::Running(pid) => {
+                println!("Server is already running with pid {}", pid);
+                return Ok(());
+            }
+            PidStatus::Stale(pid) => {
+                println!(
+                    "Removing the pid file of server pid {}, which isn't running anymore",
+                    pid
+                );
+                daemon::remove_pid_file()?;
+            }
+            PidStatus::Missing => {}
+        }
+        if self.client.is_live() {
+            println!(
+                "A server without a pid file is already answering at {}, not starting another one",
+                self.server_url
+            );
+            return Ok(());
+        }
+
+        daemon::start_server()?;
+        // The server only answers once it loaded its queue
+        for _ in 0..SERVER_START_CHECKS {
+            if self.client.is_live() {
+                println!("Server started at {}", self.server_url);
+                return Ok(());
+            }
+            thread::sleep(SERVER_START_CHECK_INTERVAL);
+        }
+        println!(
+            "Server started but isn't answering at {} yet, see {}",
+            self.server_url,
+            constants::SERVER_LOGFILE
+        );
+        Ok(())
+    }
+
+    fn stop_server(&self) -> Result<(), CmdqClientError> {
+        match daemon::pid_status()? {
+            PidStatus::Running(pid) => {
+                daemon::stop_server(pid)?;
+                println!("Stopped server pid {}", pid);
+            }
+            PidStatus::Stale(pid) => {
+                daemon::remove_pid_file()?;
+                println!(
+                    "Server pid {} wasn't running, removed its stale pid file",
+                    pid
+                );
+            }
+            PidStatus::Missing if self.client.is_live() => println!(
+                "A server without a pid file is answering at {}, it has to be stopped where it was started",
+                self.server_url
+            ),
+            PidStatus::Missing => println!("Server isn't running"),
+        }
+        Ok(())
+    }
+
+    fn server_status(&self) -> Result<(), CmdqClientError> {
+        match (daemon::pid_status()?, self.client.is_live()) {
+            (PidStatus::Running(pid), true) => {
+                println!("Server is running with pid {} at {}", pid, self.server_url)
+            }
+            (PidStatus::Running(pid), false) => println!(
+                "Server pid {} is running but doesn't answer at {}, it may still be starting",
+                pid, self.server_url
+            ),
+            (PidStatus::Stale(pid), live) => {
+                println!(
+                    "Server pid {} isn't running anymore, its pid file is stale",
+                    pid
+                );
+                if live {
+                    println!(
+                        "A server without a pid file is answering at {}",
+                        self.server_url
+                    );
+                }
+            }
+            (PidStatus::Missing, true) => println!(
+                "A server without a pid file is answering at {}",
+                self.server_url
+            ),
+            (PidStatus::Missing, false) => println!("Server isn't running"),
+        }
         Ok(())
     }
 
-    fn list_tasks(&self, running: bool) -> Result<(), CmdqClientError> {
+    fn list_tasks(&self, running: bool, label: Option<&str>) -> Result<(), CmdqClientError> {
         let state_filter = if running {
             TaskState::Running
         } else {
             TaskState::Queued
         };
-        let tasks = self.client.list_tasks(state_filter)?;
+        let tasks = self.client.list_tasks(state_filter, label)?;
         cli_util::print_tasks_as_table(tasks).expect("failed print tasks");
         Ok(())
     }
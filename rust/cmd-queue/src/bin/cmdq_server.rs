@@ -1,40 +1,104 @@
-use std::sync::Arc;
+use std::{fs::OpenOptions, sync::Arc};
 
 use actix_web::{web, App, HttpServer, Responder};
 use clap::Parser;
 use cmd_queue::{
-    constants::DEFAULT_PORT,
+    config::ServerConfig,
+    constants::{self, DEFAULT_PORT},
     web::{
-        api::{list_queued_tasks, list_running_tasks, queue_command},
-        html::index,
+        api::{
+            bump_task, cancel_task, get_history_entry, get_task, list_history, list_queued_tasks,
+            list_running_tasks, queue_command, queue_command_with_upload, resubmit_history_entry,
+        },
+        health::{live, ready},
+        html::{
+            history_page, html_cancel_task, html_retry_task, index, quick_add, quick_add_urls,
+            task_page,
+        },
+        metrics::metrics,
     },
     CommandQApp,
 };
+use daemonize::Daemonize;
 
 #[derive(Parser, Debug)]
 #[clap(name = "cmdq_server")]
 #[clap(author = "Jonathan Fok kan <jonathan@fokkan.ca>")]
 #[clap(version = "1.0")]
 #[clap(about = "cmdq server", long_about = None)]
-struct ServerCli {}
+struct ServerCli {
+    #[clap(
+        long,
+        help = "Run in the background, with the pid and output of the server in /tmp/command-queue-daemon"
+    )]
+    daemon: bool,
+
+    #[clap(flatten)]
+    config: ServerConfig,
+}
 
 async fn health() -> impl Responder {
     "UP"
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
+fn main() -> std::io::Result<()> {
     let cli = ServerCli::parse();
-    let cmdq_app = Arc::new(CommandQApp::new().expect("Failed to start server"));
+    // Forking has to happen before the runtime and the scheduler threads are started
+    if cli.daemon {
+        daemonize();
+    }
+    let served = actix_web::rt::System::new("cmdq_server").block_on(serve(cli.config));
+    if cli.daemon {
+        if let Err(err) = std::fs::remove_file(constants::PIDFILE) {
+            println!("Error removing pid file {}", err);
+        }
+    }
+    served
+}
+
+fn daemonize() {
+    std::fs::create_dir_all(constants::DAEMON_DIR).expect("Failed to create daemon directory");
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(constants::SERVER_LOGFILE)
+        .expect("Failed to open server log file");
+    Daemonize::new()
+        .pid_file(constants::PIDFILE)
+        .working_directory(std::env::current_dir().expect("current dir"))
+        .stdout(log.try_clone().expect("Failed to open server log file"))
+        .stderr(log)
+        .start()
+        .expect("Failed to start server as a daemon");
+}
+
+async fn serve(config: ServerConfig) -> std::io::Result<()> {
+    let cmdq_app = Arc::new(CommandQApp::new(config).expect("Failed to start server"));
 
     HttpServer::new(move || {
         App::new()
             .data(cmdq_app.clone())
             .service(queue_command)
+            .service(queue_command_with_upload)
             .service(list_queued_tasks)
             .service(list_running_tasks)
+            .service(get_task)
+            .service(cancel_task)
+            .service(bump_task)
+            .service(list_history)
+            .service(get_history_entry)
+            .service(resubmit_history_entry)
             .service(index)
+            .service(metrics)
+            .service(quick_add)
+            .service(quick_add_urls)
+            .service(task_page)
+            .service(html_cancel_task)
+            .service(html_retry_task)
+            .service(history_page)
             .service(web::resource("/health").to(health))
+            .service(live)
+            .service(ready)
     })
     .bind(format!("0.0.0.0:{}", DEFAULT_PORT))?
     .run()
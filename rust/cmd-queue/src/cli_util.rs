@@ -1,18 +1,28 @@
 use cli_table::{print_stdout, Table};
 
-use crate::Task;
+use std::time::{Duration, SystemTime};
+
+use crate::{history::HistoryEntry, ListedTask, TaskDetail};
 
 #[derive(Table)]
 struct TaskCliTable<'t> {
     id: &'t str,
     destination: &'t str,
+    labels: String,
+    env: String,
     command: String,
     tries: usize,
     last_attempt: String,
+    last_outcome: String,
+    position: String,
+    eta: String,
+    progress: String,
+    resubmitted_from: &'t str,
 }
 
 impl<'t> TaskCliTable<'t> {
-    fn from(task: &'t Task) -> Self {
+    fn from(listed: &'t ListedTask) -> Self {
+        let task = &listed.task;
         let last_attempt_since = task
             .last_attempt
             .and_then(|last_attempt| last_attempt.elapsed().ok())
@@ -23,15 +33,137 @@ impl<'t> TaskCliTable<'t> {
         TaskCliTable {
             id: &task.id,
             destination: &task.command.path,
+            labels: task.format_labels(),
+            env: task.format_env(),
             command: format!("{} {}", task.command.program, task.command.args.join(" ")),
             tries: task.tries,
             last_attempt: last_attempt_since,
+            last_outcome: task.format_last_outcome(),
+            position: listed
+                .position
+                .map(|position| position.to_string())
+                .unwrap_or("-".to_string()),
+            eta: listed.format_eta(),
+            progress: task
+                .progress
+                .map(|progress| progress.to_string())
+                .unwrap_or("-".to_string()),
+            resubmitted_from: task.command.resubmitted_from.as_deref().unwrap_or("-"),
         }
     }
 }
 
-pub fn print_tasks_as_table(tasks: Vec<Task>) -> Result<(), std::io::Error> {
+pub fn print_tasks_as_table(tasks: Vec<ListedTask>) -> Result<(), std::io::Error> {
     let table: Vec<_> = tasks.iter().map(|t| TaskCliTable::from(t)).collect();
     print_stdout(table)?;
     Ok(())
 }
+
+#[derive(Table)]
+struct HistoryCliTable<'t> {
+    id: &'t str,
+    destination: &'t str,
+    command: String,
+    result: String,
+    started: String,
+    duration: String,
+}
+
+impl<'t> HistoryCliTable<'t> {
+    fn from(entry: &'t HistoryEntry) -> Self {
+        let task = &entry.task;
+        HistoryCliTable {
+            id: &task.id,
+            destination: &task.command.path,
+            command: format!("{} {}", task.command.program, task.command.args.join(" ")),
+            result: match &task.last_outcome {
+                Some(outcome) => format!("{:?}, {}", entry.result, outcome),
+                None => format!("{:?}", entry.result),
+            },
+            started: entry
+                .started_at
+                .elapsed()
+                .map(|elapsed| {
+                    format!(
+                        "{} ago",
+                        humantime::format_duration(Duration::from_secs(elapsed.as_secs()))
+                    )
+                })
+                .unwrap_or("None".to_string()),
+            duration: humantime::format_duration(Duration::from_secs(entry.duration.as_secs()))
+                .to_string(),
+        }
+    }
+}
+
+pub fn print_history_as_table(entries: Vec<HistoryEntry>) -> Result<(), std::io::Error> {
+    let table: Vec<_> = entries.iter().map(HistoryCliTable::from).collect();
+    print_stdout(table)?;
+    Ok(())
+}
+
+#[derive(Table)]
+struct AttemptCliTable {
+    attempt: usize,
+    started: String,
+    duration: String,
+    outcome: String,
+}
+
+/// Prints the task followed by its attempts, each with the end of its stderr
+pub fn print_task_detail(detail: TaskDetail) -> Result<(), std::io::Error> {
+    let task = &detail.task;
+    println!("id:       {}", task.id);
+    println!("status:   {}", detail.status);
+    println!(
+        "command:  {} {}",
+        task.command.program,
+        task.command.args.join(" ")
+    );
+    println!("path:     {}", task.command.path);
+    if !task.command.labels.is_empty() {
+        println!("labels:   {}", task.format_labels());
+    }
+    if let Some(submitted_at) = task.submitted_at {
+        println!("submitted {}", format_ago(submitted_at));
+    }
+    if task.attempts.is_empty() {
+        println!("no attempts");
+        return Ok(());
+    }
+
+    let table: Vec<_> = task
+        .attempts
+        .iter()
+        .enumerate()
+        .map(|(i, attempt)| AttemptCliTable {
+            attempt: i + 1,
+            started: format_ago(attempt.started_at),
+            duration: humantime::format_duration(Duration::from_secs(attempt.duration.as_secs()))
+                .to_string(),
+            outcome: attempt.outcome.to_string(),
+        })
+        .collect();
+    print_stdout(table)?;
+    for (i, attempt) in task.attempts.iter().enumerate() {
+        let stderr = attempt.stderr.trim_end();
+        if !stderr.is_empty() {
+            println!("\nstderr of attempt {}:", i + 1);
+            for line in stderr.lines() {
+                println!("  {}", line);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_ago(time: SystemTime) -> String {
+    time.elapsed()
+        .map(|elapsed| {
+            format!(
+                "{} ago",
+                humantime::format_duration(Duration::from_secs(elapsed.as_secs()))
+            )
+        })
+        .unwrap_or("None".to_string())
+}
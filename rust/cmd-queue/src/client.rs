@@ -1,6 +1,10 @@
+use reqwest::blocking::multipart::Form;
 use url::Url;
 
-use crate::{error::CmdqClientError, CommandRequest, CommandResponse, Task, TaskState};
+use crate::{
+    error::CmdqClientError, history::HistoryEntry, CommandRequest, CommandResponse, ListedTask,
+    ResubmitRequest, TaskDetail, TaskState,
+};
 
 pub struct Client {
     client: reqwest::blocking::Client,
@@ -17,15 +21,19 @@ impl Client {
 
     pub fn queue_command(
         &self,
-        cmd_req: CommandRequest,
+        cmd_req: &CommandRequest,
+        force: bool,
     ) -> Result<CommandResponse, CmdqClientError> {
         let mut req_url = self.host.clone();
         req_url.set_path("api/commands");
+        if force {
+            req_url.set_query(Some("force=true"));
+        }
 
         let response = self
             .client
             .post(req_url)
-            .json(&cmd_req)
+            .json(cmd_req)
             .send()
             .map_err(|e| CmdqClientError::HttpClientError(e))?;
 
@@ -36,7 +44,41 @@ impl Client {
         Ok(cmd_response)
     }
 
-    pub fn list_tasks(&self, state_filter: TaskState) -> Result<Vec<Task>, CmdqClientError> {
+    /// Queues the command along with `file`, uploaded to the server and substituted for the
+    /// `{input}` args of the command
+    pub fn queue_command_with_upload(
+        &self,
+        cmd_req: &CommandRequest,
+        file: &str,
+    ) -> Result<CommandResponse, CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path("api/commands/upload");
+
+        let form = Form::new()
+            .text(
+                "command",
+                serde_json::to_string(cmd_req).expect("failed serializing command"),
+            )
+            .file("file", file)
+            .map_err(|e| CmdqClientError::ReadUploadFile(file.to_string(), e))?;
+        let response = self
+            .client
+            .post(req_url)
+            .multipart(form)
+            .send()
+            .map_err(|e| CmdqClientError::HttpClientError(e))?;
+
+        let cmd_response = response
+            .json::<CommandResponse>()
+            .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
+        Ok(cmd_response)
+    }
+
+    pub fn list_tasks(
+        &self,
+        state_filter: TaskState,
+        label: Option<&str>,
+    ) -> Result<Vec<ListedTask>, CmdqClientError> {
         //todo!("deprecated. To remove")
         let req_url = match state_filter {
             TaskState::Queued => {
@@ -51,15 +93,142 @@ impl Client {
             }
         };
 
+        let mut request = self.client.get(req_url);
+        if let Some(label) = label {
+            request = request.query(&[("label", label)]);
+        }
+        let response = request
+            .send()
+            .map_err(|e| CmdqClientError::HttpClientError(e))?;
+
+        //println!("{:?}", response);
+        let cmd_response = response
+            .json::<Vec<ListedTask>>()
+            .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
+        Ok(cmd_response)
+    }
+
+    /// Whether a server answers at the host, whether or not it was started by `cmdq server`
+    pub fn is_live(&self) -> bool {
+        let mut req_url = self.host.clone();
+        req_url.set_path("/health/live");
+
+        self.client
+            .get(req_url)
+            .send()
+            .map(|response| response.status().is_success())
+            .unwrap_or(false)
+    }
+
+    pub fn get_task(&self, id: &str) -> Result<TaskDetail, CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path(&format!("/api/commands/{}", id));
+
         let response = self
             .client
             .get(req_url)
             .send()
             .map_err(|e| CmdqClientError::HttpClientError(e))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(CmdqClientError::UnknownTask(id.to_string()));
+        }
+
+        let detail = response
+            .json::<TaskDetail>()
+            .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
+        Ok(detail)
+    }
+
+    /// Removes the queued task from the queue
+    pub fn cancel_task(&self, id: &str) -> Result<(), CmdqClientError> {
+        self.post_task_action(id, "cancel")
+    }
+
+    /// Moves the queued task to the front of the queue
+    pub fn bump_task(&self, id: &str) -> Result<(), CmdqClientError> {
+        self.post_task_action(id, "bump")
+    }
+
+    fn post_task_action(&self, id: &str, action: &str) -> Result<(), CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path(&format!("/api/commands/{}/{}", id, action));
+
+        let response = self
+            .client
+            .post(req_url)
+            .send()
+            .map_err(|e| CmdqClientError::HttpClientError(e))?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Err(CmdqClientError::UnknownTask(id.to_string())),
+            reqwest::StatusCode::CONFLICT => Err(CmdqClientError::TaskNotQueued(id.to_string())),
+            _ => response
+                .error_for_status()
+                .map(|_| ())
+                .map_err(|e| CmdqClientError::HttpClientError(e)),
+        }
+    }
+
+    pub fn list_history(&self) -> Result<Vec<HistoryEntry>, CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path("/api/history");
+
+        let response = self
+            .client
+            .get(req_url)
+            .send()
+            .map_err(|e| CmdqClientError::HttpClientError(e))?;
+
+        let entries = response
+            .json::<Vec<HistoryEntry>>()
+            .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
+        Ok(entries)
+    }
+
+    pub fn get_history_entry(&self, id: &str) -> Result<HistoryEntry, CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path(&format!("/api/history/{}", id));
+
+        let response = self
+            .client
+            .get(req_url)
+            .send()
+            .map_err(|e| CmdqClientError::HttpClientError(e))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(CmdqClientError::TaskNotFound(id.to_string()));
+        }
+
+        let entry = response
+            .json::<HistoryEntry>()
+            .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
+        Ok(entry)
+    }
+
+    /// Queues the command of the finished task again on the server, which keeps the environment
+    /// it captured
+    pub fn resubmit(
+        &self,
+        id: &str,
+        resubmit: &ResubmitRequest,
+        force: bool,
+    ) -> Result<CommandResponse, CmdqClientError> {
+        let mut req_url = self.host.clone();
+        req_url.set_path(&format!("/api/history/{}/resubmit", id));
+        if force {
+            req_url.set_query(Some("force=true"));
+        }
+
+        let response = self
+            .client
+            .post(req_url)
+            .json(resubmit)
+            .send()
+            .map_err(CmdqClientError::HttpClientError)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(CmdqClientError::TaskNotFound(id.to_string()));
+        }
 
-        //println!("{:?}", response);
         let cmd_response = response
-            .json::<Vec<Task>>()
+            .json::<CommandResponse>()
             .map_err(|e| CmdqClientError::ResponseDeserializationError(e))?;
         Ok(cmd_response)
     }
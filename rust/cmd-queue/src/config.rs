@@ -0,0 +1,190 @@
+use std::{collections::BTreeMap, path::Path};
+
+use clap::{ArgEnum, Args};
+
+use crate::CommandRejected;
+
+/// Environment variables changing which program runs or what it loads, rejected so that the
+/// environment of a task can't get around the allowlist
+const DENIED_ENV_KEYS: [&str; 11] = [
+    "PATH",
+    "IFS",
+    "ENV",
+    "BASH_ENV",
+    "SHELLOPTS",
+    "PS4",
+    "NODE_OPTIONS",
+    "PERL5OPT",
+    "PERL5LIB",
+    "RUBYOPT",
+    "RUBYLIB",
+];
+/// Prefixes of the variables of the dynamic loaders and of python, which yt-dlp runs on
+const DENIED_ENV_PREFIXES: [&str; 3] = ["LD_", "DYLD_", "PYTHON"];
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct ServerConfig {
+    #[clap(
+        long,
+        help = "Run tasks sharing a working directory one after the other rather than concurrently"
+    )]
+    pub sequential_per_dir: bool,
+
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Program that can be queued, given exactly as submitted. Any program that isn't denied can be queued when none is given"
+    )]
+    pub allow_program: Vec<String>,
+
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        help = "Program that can never be queued, by name or path"
+    )]
+    pub deny_program: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Allow queueing any program that isn't denied, even with --allow-program. Only use when the server isn't reachable by others"
+    )]
+    pub unsafe_allow_all: bool,
+
+    #[clap(
+        long,
+        help = "Directory downloads queued from the web UI go to when none is given. Defaults to the working directory of the server"
+    )]
+    pub download_dir: Option<String>,
+
+    #[clap(long, help = "Most tasks that can be queued or running at once")]
+    pub max_queue_len: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Most tasks a single client address can have queued or running at once"
+    )]
+    pub max_pending_per_client: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Most tasks with the same label that can be queued or running at once"
+    )]
+    pub max_pending_per_label: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Run tasks over ssh on HOST unless they have their own host, e.g. user@nas"
+    )]
+    pub ssh_host: Option<String>,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "requeue",
+        help = "What happens on startup to the tasks that were running when the server stopped"
+    )]
+    pub on_interrupted: InterruptedPolicy,
+}
+
+/// What happens to the tasks that were running when the server stopped, see
+/// `execution::recovery`
+#[derive(ArgEnum, Clone, Copy, Debug, Default)]
+pub enum InterruptedPolicy {
+    /// Queue the tasks again, counting the interrupted run as a failed try
+    #[default]
+    Requeue,
+    /// Record the tasks as interrupted in the history without running them again
+    MarkInterrupted,
+}
+
+impl ServerConfig {
+    pub fn download_dir(&self) -> String {
+        self.download_dir.clone().unwrap_or_else(|| {
+            std::env::current_dir()
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Checks that `program` can be queued. Without an allowlist, every program that isn't
+    /// denied can be. The denylist applies to the program name as well as to the path
+    /// submitted so that `/usr/bin/rm` is denied by `rm`.
+    pub fn check_program(&self, program: &str) -> Result<(), CommandRejected> {
+        let name = Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let denied = self
+            .deny_program
+            .iter()
+            .any(|denied| denied == program || Some(denied) == name.as_ref());
+        let allowed = self.unsafe_allow_all
+            || self.allow_program.is_empty()
+            || self.allow_program.iter().any(|allowed| allowed == program);
+        if denied || !allowed {
+            Err(CommandRejected::ProgramNotAllowed {
+                program: program.to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the environment of a task doesn't set variables like PATH or LD_PRELOAD,
+    /// with which an allowed program name could run any binary or library
+    pub fn check_env(&self, env: &BTreeMap<String, String>) -> Result<(), CommandRejected> {
+        match env.keys().find(|key| {
+            DENIED_ENV_KEYS.contains(&key.as_str())
+                || DENIED_ENV_PREFIXES
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix))
+        }) {
+            Some(key) => Err(CommandRejected::EnvNotAllowed { key: key.clone() }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_check_program() {
+    let config = ServerConfig::default();
+    assert!(config.check_program("yt-dlp").is_ok());
+    assert!(config.check_program("/usr/bin/rsync").is_ok());
+
+    let config = ServerConfig {
+        allow_program: vec!["yt-dlp".to_string()],
+        ..Default::default()
+    };
+    assert!(config.check_program("yt-dlp").is_ok());
+    assert!(config.check_program("ffmpeg").is_err());
+    assert!(config.check_program("/usr/local/bin/yt-dlp").is_err());
+
+    let config = ServerConfig {
+        deny_program: vec!["rm".to_string()],
+        ..Default::default()
+    };
+    assert!(config.check_program("ffmpeg").is_ok());
+    assert!(config.check_program("rm").is_err());
+    assert!(config.check_program("/usr/bin/rm").is_err());
+
+    let config = ServerConfig {
+        allow_program: vec!["yt-dlp".to_string()],
+        deny_program: vec!["rm".to_string()],
+        unsafe_allow_all: true,
+        ..Default::default()
+    };
+    assert!(config.check_program("ffmpeg").is_ok());
+    assert!(config.check_program("rm").is_err());
+}
+
+#[test]
+fn test_check_env() {
+    let config = ServerConfig::default();
+    let env = |key: &str| BTreeMap::from([(key.to_string(), "/tmp/attacker".to_string())]);
+
+    assert!(config.check_env(&BTreeMap::new()).is_ok());
+    assert!(config.check_env(&env("HTTP_PROXY")).is_ok());
+    assert!(config.check_env(&env("PATH")).is_err());
+    assert!(config.check_env(&env("LD_PRELOAD")).is_err());
+    assert!(config.check_env(&env("DYLD_INSERT_LIBRARIES")).is_err());
+    assert!(config.check_env(&env("PYTHONPATH")).is_err());
+}
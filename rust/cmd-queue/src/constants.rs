@@ -1,4 +1,14 @@
 pub const DEFAULT_PORT: &'static str = "8392";
 pub const DEFAULT_CONCURRENCY_LEVEL: usize = 3;
 
+pub const DAEMON_DIR: &'static str = "/tmp/command-queue-daemon";
+pub const PIDFILE: &'static str = "/tmp/command-queue-daemon/cmdq.pid";
+/// Where the server writes its output when started as a daemon
+pub const SERVER_LOGFILE: &'static str = "/tmp/command-queue-daemon/cmdq_server.log";
 pub const DBFILE: &'static str = "/tmp/command-queue-daemon/cmdq.db";
+pub const UPLOADS_DIR: &'static str = "/tmp/command-queue-daemon/uploads";
+pub const HISTORY_DBFILE: &'static str = "/tmp/command-queue-daemon/cmdq-history.db";
+pub const IDEMPOTENCY_DBFILE: &'static str = "/tmp/command-queue-daemon/cmdq-idempotency.db";
+
+/// Submitting a command in a directory with less free space than this needs confirmation
+pub const MIN_FREE_DISK_SPACE_BYTES: u64 = 5 * 1024 * 1024 * 1024;
@@ -0,0 +1,90 @@
+use std::{fs, io, path::PathBuf, process::Command};
+
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+
+use crate::{constants, error::CmdqClientError};
+
+/// Server daemon according to its pid file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PidStatus {
+    /// The process of the pid file is running
+    Running(i32),
+    /// The pid file was left behind by a server that is no longer running
+    Stale(i32),
+    /// There is no pid file
+    Missing,
+}
+
+pub fn pid_status() -> Result<PidStatus, CmdqClientError> {
+    let contents = match fs::read_to_string(constants::PIDFILE) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(PidStatus::Missing),
+        Err(e) => {
+            return Err(CmdqClientError::ReadServerPidFile(
+                constants::PIDFILE.to_string(),
+                e,
+            ))
+        }
+    };
+    let pid = contents
+        .trim()
+        .parse::<i32>()
+        .map_err(CmdqClientError::ParseServerPid)?;
+    if is_alive(pid) {
+        Ok(PidStatus::Running(pid))
+    } else {
+        Ok(PidStatus::Stale(pid))
+    }
+}
+
+/// Whether a process with the pid exists, by sending it the null signal. A process owned by
+/// another user can't be signalled but still exists.
+fn is_alive(pid: i32) -> bool {
+    match kill(Pid::from_raw(pid), None) {
+        Ok(()) => true,
+        Err(Errno::EPERM) => true,
+        Err(_) => false,
+    }
+}
+
+pub fn remove_pid_file() -> Result<(), CmdqClientError> {
+    match fs::remove_file(constants::PIDFILE) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(CmdqClientError::RemoveServerPidFile(
+            constants::PIDFILE.to_string(),
+            e,
+        )),
+    }
+}
+
+/// Starts `cmdq_server` as a daemon writing its pid file, returning once it forked. The server
+/// binary next to the running one is used if there is one, otherwise the one on the PATH.
+pub fn start_server() -> Result<(), CmdqClientError> {
+    let server = std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name("cmdq_server"))
+        .filter(|server| server.exists())
+        .unwrap_or_else(|| PathBuf::from("cmdq_server"));
+    let status = Command::new(&server)
+        .arg("--daemon")
+        .status()
+        .map_err(|e| CmdqClientError::StartServer(server.display().to_string(), e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CmdqClientError::StartServer(
+            server.display().to_string(),
+            io::Error::other(format!("exited with {}", status)),
+        ))
+    }
+}
+
+/// Asks the server to shut down
+pub fn stop_server(pid: i32) -> Result<(), CmdqClientError> {
+    kill(Pid::from_raw(pid), Signal::SIGTERM).map_err(|e| CmdqClientError::KillServer(pid, e))
+}
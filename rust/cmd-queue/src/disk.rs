@@ -0,0 +1,21 @@
+use nix::sys::statvfs::statvfs;
+
+use crate::{constants::MIN_FREE_DISK_SPACE_BYTES, CommandWarning};
+
+/// Warns when the filesystem of `path` has less than `MIN_FREE_DISK_SPACE_BYTES` free. Paths
+/// that can't be checked aren't warned about, the task will fail on its own if it can't run.
+pub fn check_free_space(path: &str) -> Option<CommandWarning> {
+    let stat = statvfs(path).ok()?;
+    let fragment_size = stat.fragment_size() as u64;
+    let available_bytes = stat.blocks_available() as u64 * fragment_size;
+    let total_bytes = stat.blocks() as u64 * fragment_size;
+    if available_bytes < MIN_FREE_DISK_SPACE_BYTES {
+        Some(CommandWarning::LowDiskSpace {
+            path: path.to_string(),
+            available_bytes,
+            total_bytes,
+        })
+    } else {
+        None
+    }
+}
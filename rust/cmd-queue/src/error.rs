@@ -3,6 +3,8 @@ use std::num::ParseIntError;
 use nix::errno::Errno;
 use thiserror::Error;
 
+use crate::CommandRejected;
+
 #[derive(Error, Debug)]
 pub enum CmdqError {
     #[error("Error creating or loading db file at {}. {}", .0, .1)]
@@ -10,6 +12,18 @@ pub enum CmdqError {
 
     #[error("Error writing to db {}", .0)]
     PickleDbWriteError(pickledb::error::Error),
+
+    #[error("Error reading upload {}", .0)]
+    UploadRead(actix_multipart::MultipartError),
+
+    #[error("Upload is missing the {} field", .0)]
+    UploadMissingField(&'static str),
+
+    #[error("Invalid command in upload {}", .0)]
+    UploadInvalidCommand(serde_json::Error),
+
+    #[error("Error writing upload to {}. {}", .0, .1)]
+    UploadWrite(String, std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -20,6 +34,12 @@ pub enum CmdqClientError {
     #[error("Error parsing PID of server with {}", .0)]
     ParseServerPid(ParseIntError),
 
+    #[error("Error removing PID file of server at {}. {}", .0, .1)]
+    RemoveServerPidFile(String, std::io::Error),
+
+    #[error("Error starting server {}. {}", .0, .1)]
+    StartServer(String, std::io::Error),
+
     #[error("Error sending kill signal to server pid {} with {}", .0, .1)]
     KillServer(i32, Errno),
 
@@ -29,6 +49,27 @@ pub enum CmdqClientError {
     #[error("Error deserializing HTTP response with {}", .0)]
     ResponseDeserializationError(reqwest::Error),
 
+    #[error("{}", .0)]
+    CommandRejected(CommandRejected),
+
+    #[error("Task {} not found in history", .0)]
+    TaskNotFound(String),
+
+    #[error("Task {} not found in the queue or history", .0)]
+    UnknownTask(String),
+
+    #[error("Task {} is not queued", .0)]
+    TaskNotQueued(String),
+
+    #[error("Invalid --arg-replace {}, expected OLD=NEW", .0)]
+    InvalidArgReplace(String),
+
+    #[error("Invalid --label {}, expected KEY=VALUE", .0)]
+    InvalidLabel(String),
+
+    #[error("Error reading file to upload {}. {}", .0, .1)]
+    ReadUploadFile(String, std::io::Error),
+
     #[error("Error parsing server host {}. {}", .0, .1)]
     ServerHostUrlParseError(String, url::ParseError),
 }
@@ -0,0 +1,76 @@
+use std::process::Command;
+
+use crate::CommandRequest;
+
+/// Where the command of a task runs. The scheduler spawns the returned process the same way
+/// for every executor, capturing its output and exit status.
+pub trait Executor: Send + Sync {
+    fn command(&self, request: &CommandRequest) -> Command;
+}
+
+/// Runs commands as processes of the server
+pub struct LocalExecutor;
+
+impl Executor for LocalExecutor {
+    fn command(&self, request: &CommandRequest) -> Command {
+        let mut command = Command::new(&request.program);
+        command
+            .args(&request.args)
+            .envs(&request.env)
+            .current_dir(&request.path);
+        command
+    }
+}
+
+/// Runs commands on a remote host over ssh. The path of the request is a directory of the
+/// remote host and the exit status is the one of the remote command, or 255 if ssh failed.
+pub struct SshExecutor {
+    pub host: String,
+}
+
+impl Executor for SshExecutor {
+    fn command(&self, request: &CommandRequest) -> Command {
+        // Killing ssh on timeout leaves the remote command running, so it is given the timeout too
+        let timeout = request
+            .timeout_secs
+            .map(|timeout_secs| format!("timeout -s KILL {} ", timeout_secs))
+            .unwrap_or_default();
+        let remote_command = format!(
+            "cd {} && exec {}env {}",
+            shell_quote(&request.path),
+            timeout,
+            request
+                .env
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .chain(std::iter::once(request.program.clone()))
+                .chain(request.args.iter().cloned())
+                .map(|word| shell_quote(&word))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        let mut command = Command::new("ssh");
+        // BatchMode fails rather than prompting for a password nobody can type
+        command
+            .args(["-o", "BatchMode=yes"])
+            .arg(&self.host)
+            .arg("--")
+            .arg(remote_command);
+        command
+    }
+}
+
+/// The executor of the task, over ssh when the task or the server has a host
+pub fn executor_for(request: &CommandRequest, default_host: Option<&str>) -> Box<dyn Executor> {
+    match request.host.as_deref().or(default_host) {
+        Some(host) => Box::new(SshExecutor {
+            host: host.to_string(),
+        }),
+        None => Box::new(LocalExecutor),
+    }
+}
+
+/// Quotes `word` for a POSIX shell
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
@@ -1,5 +1,9 @@
 use std::time::Duration;
 
+pub mod executor;
+pub mod outcome;
+pub mod progress;
+pub mod recovery;
 pub mod scheduler;
 
 const MAX_RETRIES: usize = 20;
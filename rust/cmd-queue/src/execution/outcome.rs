@@ -0,0 +1,236 @@
+use std::{
+    fmt,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::CommandRejected;
+
+/// Exit code of programs called with invalid arguments, running them again won't help
+const USAGE_ERROR_EXIT_CODE: i32 = 2;
+
+/// Bytes of stderr kept per attempt, from the end where the errors usually are
+const ATTEMPT_STDERR_LIMIT: usize = 4096;
+
+/// Why a run of a task failed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailureCategory {
+    /// The program couldn't be started or its output couldn't be read
+    Spawn,
+    /// The program exited with a non-zero exit code
+    NonZeroExit,
+    /// The program ran for longer than the timeout of the task and was killed
+    Timeout,
+    /// The program was terminated by a signal
+    Killed,
+    /// The server stopped while the program was running
+    Interrupted,
+}
+
+impl fmt::Display for FailureCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category = match self {
+            FailureCategory::Spawn => "spawn error",
+            FailureCategory::NonZeroExit => "non-zero exit",
+            FailureCategory::Timeout => "timeout",
+            FailureCategory::Killed => "killed",
+            FailureCategory::Interrupted => "interrupted",
+        };
+        write!(f, "{}", category)
+    }
+}
+
+/// How the last run of a task ended
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RunOutcome {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    /// None when the run succeeded
+    pub category: Option<FailureCategory>,
+    /// Error starting the program
+    pub error: Option<String>,
+}
+
+/// When a run of a task that exited with a non-zero exit code is a success anyway, for
+/// programs that exit with one on partial success such as yt-dlp when some items of a playlist
+/// fail. Runs that exited with 0 are always successes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SuccessPolicy {
+    /// Non-zero exit codes of successful runs
+    #[serde(default)]
+    pub exit_codes: Vec<i32>,
+    /// Regex that marks the run as a success when it matches its stdout or stderr
+    #[serde(default)]
+    pub output_regex: Option<String>,
+}
+
+impl SuccessPolicy {
+    /// Checks that the output regex is valid, so that it doesn't fail when tasks finish
+    pub fn check(&self) -> Result<(), CommandRejected> {
+        match self.output_regex.as_deref().map(Regex::new) {
+            Some(Err(err)) => Err(CommandRejected::InvalidSuccessRegex {
+                regex: self.output_regex.clone().unwrap_or_default(),
+                error: err.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_success(&self, exit_code: Option<i32>, stdout: &[u8], stderr: &[u8]) -> bool {
+        if exit_code.is_some_and(|exit_code| self.exit_codes.contains(&exit_code)) {
+            return true;
+        }
+        match self.output_regex.as_deref().map(Regex::new) {
+            Some(Ok(regex)) => regex.is_match(stdout) || regex.is_match(stderr),
+            _ => false,
+        }
+    }
+}
+
+impl RunOutcome {
+    pub fn from_status(status: ExitStatus, timed_out: bool) -> Self {
+        let category = if timed_out {
+            Some(FailureCategory::Timeout)
+        } else if status.signal().is_some() {
+            Some(FailureCategory::Killed)
+        } else if !status.success() {
+            Some(FailureCategory::NonZeroExit)
+        } else {
+            None
+        };
+        RunOutcome {
+            exit_code: status.code(),
+            signal: status.signal(),
+            category,
+            error: None,
+        }
+    }
+
+    /// Counts a non-zero exit as a success when the success policy of the task allows it.
+    /// Timeouts and runs killed by a signal stay failures.
+    pub fn with_policy(mut self, policy: &SuccessPolicy, stdout: &[u8], stderr: &[u8]) -> Self {
+        if self.category == Some(FailureCategory::NonZeroExit)
+            && policy.is_success(self.exit_code, stdout, stderr)
+        {
+            self.category = None;
+        }
+        self
+    }
+
+    pub fn from_error(error: &std::io::Error) -> Self {
+        RunOutcome {
+            category: Some(FailureCategory::Spawn),
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn interrupted() -> Self {
+        RunOutcome {
+            category: Some(FailureCategory::Interrupted),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.category.is_none()
+    }
+
+    /// Whether running the task again could succeed. Programs that can't be started and usage
+    /// errors will fail the same way every time.
+    pub fn is_retryable(&self) -> bool {
+        match self.category {
+            None => false,
+            Some(FailureCategory::Spawn) => false,
+            Some(FailureCategory::NonZeroExit) => self.exit_code != Some(USAGE_ERROR_EXIT_CODE),
+            Some(FailureCategory::Timeout)
+            | Some(FailureCategory::Killed)
+            | Some(FailureCategory::Interrupted) => true,
+        }
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.category, self.exit_code, self.signal, &self.error) {
+            (None, exit_code, _, _) => write!(f, "exit {}", exit_code.unwrap_or(0)),
+            (Some(category), _, _, Some(error)) => write!(f, "{}: {}", category, error),
+            (Some(category), _, Some(signal), _) => write!(f, "signal {} ({})", signal, category),
+            (Some(category), Some(exit_code), _, _) => {
+                write!(f, "exit {} ({})", exit_code, category)
+            }
+            (Some(category), None, None, None) => write!(f, "{}", category),
+        }
+    }
+}
+
+/// A run of a task
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Attempt {
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub outcome: RunOutcome,
+    /// End of what the program wrote to stderr
+    pub stderr: String,
+}
+
+impl Attempt {
+    pub fn new(
+        started_at: SystemTime,
+        duration: Duration,
+        outcome: RunOutcome,
+        stderr: &[u8],
+    ) -> Self {
+        let start = stderr.len().saturating_sub(ATTEMPT_STDERR_LIMIT);
+        Attempt {
+            started_at,
+            duration,
+            outcome,
+            stderr: String::from_utf8_lossy(&stderr[start..]).to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_with_policy() {
+    let exited = |code: i32| RunOutcome::from_status(ExitStatus::from_raw(code << 8), false);
+    let exit_codes = SuccessPolicy {
+        exit_codes: vec![1],
+        ..Default::default()
+    };
+    let output_regex = SuccessPolicy {
+        output_regex: Some("has already been downloaded".to_string()),
+        ..Default::default()
+    };
+    let output = b"[download] video.mp4 has already been downloaded";
+
+    assert!(exited(0)
+        .with_policy(&SuccessPolicy::default(), b"", b"")
+        .is_success());
+    assert!(!exited(1)
+        .with_policy(&SuccessPolicy::default(), b"", b"")
+        .is_success());
+
+    assert!(exited(1).with_policy(&exit_codes, b"", b"").is_success());
+    assert!(!exited(2).with_policy(&exit_codes, b"", b"").is_success());
+
+    assert!(exited(1)
+        .with_policy(&output_regex, output, b"")
+        .is_success());
+    assert!(exited(1)
+        .with_policy(&output_regex, b"", output)
+        .is_success());
+    assert!(!exited(1)
+        .with_policy(&output_regex, b"ERROR: unable to download", b"")
+        .is_success());
+
+    // Timeouts and kills stay failures whatever the policy
+    let timed_out = RunOutcome::from_status(ExitStatus::from_raw(1 << 8), true);
+    assert!(!timed_out.with_policy(&exit_codes, b"", b"").is_success());
+    let killed = RunOutcome::from_status(ExitStatus::from_raw(9), false);
+    assert!(!killed.with_policy(&output_regex, output, b"").is_success());
+}
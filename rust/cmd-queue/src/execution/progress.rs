@@ -0,0 +1,65 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// How far a running task is, as reported by the program
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Progress {
+    pub processed: Duration,
+    pub total: Option<Duration>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let processed = Duration::from_secs(self.processed.as_secs());
+        match self.total.filter(|total| !total.is_zero()) {
+            Some(total) => write!(
+                f,
+                "{}% ({} of {})",
+                (processed.as_secs_f64() / total.as_secs_f64() * 100.0).min(100.0) as u32,
+                humantime::format_duration(processed),
+                humantime::format_duration(Duration::from_secs(total.as_secs()))
+            ),
+            None => write!(f, "{}", humantime::format_duration(processed)),
+        }
+    }
+}
+
+/// Follows the progress of ffmpeg from the lines it writes on stderr. ffmpeg prints the
+/// duration of its input once, e.g. `  Duration: 00:03:12.45, start: 0.000000`, then
+/// periodically a status line such as `frame=  120 fps=30 ... time=00:00:04.00 bitrate=...`.
+#[derive(Debug, Default)]
+pub struct FfmpegProgress {
+    total: Option<Duration>,
+}
+
+impl FfmpegProgress {
+    /// Returns the updated progress if the line reports any
+    pub fn parse_line(&mut self, line: &str) -> Option<Progress> {
+        if let Some(duration) = line.trim_start().strip_prefix("Duration:") {
+            if self.total.is_none() {
+                self.total = duration.split(',').next().and_then(parse_timestamp);
+            }
+            return None;
+        }
+        let time = line
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("time="))?;
+        Some(Progress {
+            processed: parse_timestamp(time)?,
+            total: self.total,
+        })
+    }
+}
+
+/// Parses ffmpeg's `HH:MM:SS.ss` timestamps
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let mut parts = timestamp.trim().splitn(3, ':');
+    let hours = parts.next()?.parse::<u64>().ok()?;
+    let minutes = parts.next()?.parse::<u64>().ok()?;
+    let seconds = parts.next()?.parse::<f64>().ok()?;
+    if seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs(hours * 3600 + minutes * 60) + Duration::from_secs_f64(seconds))
+}
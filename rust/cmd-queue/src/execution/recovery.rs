@@ -0,0 +1,68 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    config::InterruptedPolicy,
+    error::CmdqError,
+    execution::outcome::{Attempt, RunOutcome},
+    history::{HistoryEntry, HistoryStore},
+    queue::InMemoryQueue,
+    upload, TaskRunResult,
+};
+
+/// Handles the tasks that were running when the server last stopped, before the scheduler
+/// starts. Their interrupted run is recorded as a failed attempt, of unknown duration since
+/// when the server stopped isn't known.
+pub fn recover_interrupted(
+    queue: &InMemoryQueue,
+    history: &HistoryStore,
+    policy: InterruptedPolicy,
+) -> Result<(), CmdqError> {
+    let interrupted = queue.interrupted();
+    if interrupted.is_empty() {
+        return Ok(());
+    }
+    println!(
+        "{} tasks were running when the server stopped",
+        interrupted.len()
+    );
+    for mut task in interrupted {
+        let started_at = task.started_at.unwrap_or_else(SystemTime::now);
+        let attempt = Attempt::new(started_at, Duration::ZERO, RunOutcome::interrupted(), &[]);
+        task.last_outcome = Some(attempt.outcome.clone());
+        task.attempts.push(attempt);
+        task.started_at = None;
+        task.progress = None;
+
+        let result = match policy {
+            InterruptedPolicy::Requeue => TaskRunResult::Failed,
+            InterruptedPolicy::MarkInterrupted => TaskRunResult::Interrupted,
+        };
+        history.record(&HistoryEntry {
+            task: task.clone(),
+            result: result.clone(),
+            started_at,
+            duration: Duration::ZERO,
+        })?;
+        let description = format!(
+            "{} {} {}",
+            task.id,
+            task.command.program,
+            task.command.args.join(" ")
+        );
+        match policy {
+            InterruptedPolicy::Requeue => {
+                task.tries += 1;
+                task.last_attempt = Some(started_at);
+                task.queued_at = Some(SystemTime::now());
+                queue.push(task)?;
+                println!("  requeued {}", description);
+            }
+            InterruptedPolicy::MarkInterrupted => {
+                queue.update(&task.id, result)?;
+                upload::cleanup(&task.id);
+                println!("  marked interrupted {}", description);
+            }
+        }
+    }
+    Ok(())
+}
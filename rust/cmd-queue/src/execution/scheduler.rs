@@ -1,63 +1,137 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    io::{self, Read},
     ops::Add,
-    process::{Child, Command},
-    sync::{Arc, Mutex},
-    time::{Duration, SystemTime},
+    path::{Path, PathBuf},
+    process::{Child, Output, Stdio},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
 };
 
 use crate::{
     error::CmdqError,
-    execution::{delay, MAX_RETRIES},
+    execution::{
+        delay,
+        executor::{executor_for, Executor},
+        outcome::{Attempt, RunOutcome},
+        progress::FfmpegProgress,
+        MAX_RETRIES,
+    },
+    history::{HistoryEntry, HistoryStore},
     queue::InMemoryQueue,
-    Task, TaskRunResult,
+    upload, Task, TaskRunResult,
 };
 
+/// Time between runs of the scheduler loop
+pub const SCHEDULER_INTERVAL: Duration = Duration::from_secs(10);
+
 pub struct TaskScheduler {
     queue: Arc<InMemoryQueue>,
+    history: Arc<HistoryStore>,
     num_workers: usize,
     //running_tasks: HashMap<String, Child>,
     num_running_tasks: Arc<Mutex<usize>>,
+    /// Whether tasks sharing a working directory are run one after the other
+    sequential_per_dir: bool,
+    /// Canonicalized working directories of the running tasks, when running them sequentially
+    locked_dirs: Arc<Mutex<HashSet<PathBuf>>>,
+    heartbeat: Mutex<SystemTime>,
+    /// Host tasks without their own run on over ssh, None to run them locally
+    ssh_host: Option<String>,
 }
 
 impl TaskScheduler {
-    pub fn new(queue: Arc<InMemoryQueue>, num_workers: usize) -> Self {
+    pub fn new(
+        queue: Arc<InMemoryQueue>,
+        history: Arc<HistoryStore>,
+        num_workers: usize,
+        sequential_per_dir: bool,
+        ssh_host: Option<String>,
+    ) -> Self {
         TaskScheduler {
             queue: queue.clone(),
+            history: history,
             num_workers: num_workers,
             //running_tasks:
             num_running_tasks: Arc::new(Mutex::new(0)),
+            sequential_per_dir,
+            locked_dirs: Arc::new(Mutex::new(HashSet::new())),
+            heartbeat: Mutex::new(SystemTime::now()),
+            ssh_host,
         }
     }
-    pub fn run(&self) {
-        let task_scheduler = Arc::new(self);
-
-        std::thread::scope(|s| {
-            let scheduler = task_scheduler.clone();
-            s.spawn(move || loop {
-                scheduler.run_loop();
-                std::thread::sleep(Duration::from_secs(10));
-            });
+    pub fn num_workers(&self) -> usize {
+        self.num_workers
+    }
+
+    /// Starts the scheduler loop on its own thread
+    pub fn run(self: Arc<Self>) {
+        std::thread::spawn(move || loop {
+            self.run_loop();
+            std::thread::sleep(SCHEDULER_INTERVAL);
         });
-        // std::thread::spawn(move || loop {
-        //     self.run_loop();
-        //     std::thread::sleep(Duration::from_secs(10));
-        // });
     }
+
+    /// When the scheduler loop last ran
+    pub fn last_heartbeat(&self) -> SystemTime {
+        *self.heartbeat.lock().unwrap()
+    }
+
     pub fn run_loop(&self) {
-        while *self.num_running_tasks.lock().unwrap() < self.num_workers {
+        *self.heartbeat.lock().unwrap() = SystemTime::now();
+        // Tasks put back because their directory is locked, to stop once every queued task was
+        // put back rather than cycling through them
+        let mut deferred = 0;
+        while *self.num_running_tasks.lock().unwrap() < self.num_workers
+            && deferred < self.queue.len()
+        {
             let task_opt = self.queue.pop_next();
             let queue = self.queue.clone();
+            let history = self.history.clone();
             let num_running_tasks = self.num_running_tasks.clone();
+            let locked_dirs = self.locked_dirs.clone();
+            let ssh_host = self.ssh_host.clone();
 
             if let Some(task) = task_opt {
+                if task.is_expired() {
+                    expire_task(task, &queue, &history);
+                    continue;
+                }
+
+                let locked_dir = if self.sequential_per_dir {
+                    let dir = std::fs::canonicalize(&task.command.path)
+                        .unwrap_or_else(|_| PathBuf::from(&task.command.path));
+                    if !locked_dirs.lock().unwrap().insert(dir.clone()) {
+                        if let Err(err) = queue.update(&task.id, TaskRunResult::Skipped) {
+                            println!("Error putting back task {} {}", task.id, err);
+                        }
+                        deferred += 1;
+                        continue;
+                    }
+                    Some(dir)
+                } else {
+                    None
+                };
+
                 std::thread::spawn(move || {
                     {
                         let mut num_running_tasks = num_running_tasks.lock().unwrap();
                         *num_running_tasks += 1;
                     }
-                    run_task(task, queue);
+                    let executor = executor_for(&task.command, ssh_host.as_deref());
+                    run_task(task, executor.as_ref(), queue, history);
 
+                    if let Some(dir) = locked_dir {
+                        locked_dirs.lock().unwrap().remove(&dir);
+                    }
                     {
                         let mut num_running_tasks = num_running_tasks.lock().unwrap();
                         *num_running_tasks -= 1;
@@ -70,7 +144,29 @@ impl TaskScheduler {
     }
 }
 
-fn run_task(task: Task, queue: Arc<InMemoryQueue>) {
+pub(crate) fn expire_task(task: Task, queue: &InMemoryQueue, history: &HistoryStore) {
+    println!("Task {} expired", task.id);
+    let entry = HistoryEntry {
+        task: task.clone(),
+        result: TaskRunResult::Expired,
+        started_at: SystemTime::now(),
+        duration: Duration::ZERO,
+    };
+    if let Err(err) = history.record(&entry) {
+        println!("Error writing task history {}", err);
+    }
+    if queue.update(&task.id, TaskRunResult::Expired).is_err() {
+        println!("Error writing task result");
+    }
+    upload::cleanup(&task.id);
+}
+
+fn run_task(
+    task: Task,
+    executor: &dyn Executor,
+    queue: Arc<InMemoryQueue>,
+    history: Arc<HistoryStore>,
+) {
     println!("Running task {:?}", task);
     if task.tries > 1
         && task
@@ -86,30 +182,153 @@ fn run_task(task: Task, queue: Arc<InMemoryQueue>) {
     }
 
     if task.tries > MAX_RETRIES {
-        println!("Task was retried more than {}, skipping", MAX_RETRIES);
+        println!("Task was retried more than {}, giving up", MAX_RETRIES);
+        if queue
+            .update(&task.id, TaskRunResult::FailedPermanently)
+            .is_err()
+        {
+            println!("Error writing task result");
+        }
+        upload::cleanup(&task.id);
         return;
     }
 
-    // TODO change to child and save child to enable killing tasks
-    // See https://doc.rust-lang.org/std/process/struct.Child.html#method.wait_with_output on how to capture the output piped while the process is running
-    let output_res = Command::new(&task.command.program)
-        .args(&task.command.args)
-        .current_dir(task.command.path)
-        .output();
-
-    // TODO write error to task
-    let write_res = match output_res {
-        Ok(output) => {
+    if let Err(err) = queue.mark_running(&task.id) {
+        println!("Error persisting running task {} {}", task.id, err);
+    }
+    let started_at = task.started_at.unwrap_or_else(SystemTime::now);
+    let start = Instant::now();
+    let (outcome, stderr) = match run_command(&task, executor, &queue) {
+        Ok((output, timed_out)) => {
             println!("{:?}", output);
-            if output.status.success() {
-                queue.update(&task.id, TaskRunResult::Completed)
-            } else {
-                queue.update(&task.id, TaskRunResult::Failed)
-            }
+            let outcome = RunOutcome::from_status(output.status, timed_out).with_policy(
+                &task.command.success,
+                &output.stdout,
+                &output.stderr,
+            );
+            (outcome, output.stderr)
         }
-        Err(_err) => queue.update(&task.id, TaskRunResult::Failed),
+        Err(err) => (RunOutcome::from_error(&err), Vec::new()),
+    };
+    let attempt = Attempt::new(started_at, start.elapsed(), outcome.clone(), &stderr);
+    let result = if outcome.is_success() {
+        TaskRunResult::Completed
+    } else if outcome.is_retryable() {
+        TaskRunResult::Failed
+    } else {
+        TaskRunResult::FailedPermanently
     };
-    if write_res.is_err() {
+    queue.record_attempt(&task.id, attempt.clone());
+
+    let mut finished_task = task.clone();
+    finished_task.last_outcome = Some(outcome);
+    finished_task.attempts.push(attempt.clone());
+    let entry = HistoryEntry {
+        task: finished_task,
+        result: result.clone(),
+        started_at,
+        duration: attempt.duration,
+    };
+    if let Err(err) = history.record(&entry) {
+        println!("Error writing task history {}", err);
+    }
+    let finished = matches!(
+        result,
+        TaskRunResult::Completed | TaskRunResult::FailedPermanently
+    );
+    if queue.update(&task.id, result).is_err() {
         println!("Error writing task result");
     }
+    if finished {
+        upload::cleanup(&task.id);
+    }
+}
+
+// TODO save child to enable killing tasks
+/// Runs the command of the task, reading its stderr as it is written to follow the progress
+/// of programs that report it. Returns whether the command was killed for running longer than
+/// the timeout of the task along with its output.
+fn run_command(
+    task: &Task,
+    executor: &dyn Executor,
+    queue: &InMemoryQueue,
+) -> io::Result<(Output, bool)> {
+    let mut child = executor
+        .command(&task.command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // The watchdog kills the child unless told that it exited before the timeout
+    let (exited_tx, exited_rx) = mpsc::channel::<()>();
+    let watchdog = task.command.timeout_secs.map(|timeout_secs| {
+        let pid = Pid::from_raw(child.id() as i32);
+        std::thread::spawn(move || {
+            match exited_rx.recv_timeout(Duration::from_secs(timeout_secs)) {
+                Err(RecvTimeoutError::Timeout) => kill(pid, Signal::SIGKILL).is_ok(),
+                _ => false,
+            }
+        })
+    });
+
+    // stdout is drained on its own thread so that the child never blocks on a full pipe
+    let mut child_stdout = child.stdout.take().expect("stdout is piped");
+    let stdout_reader = std::thread::spawn(move || {
+        let mut stdout = Vec::new();
+        child_stdout.read_to_end(&mut stdout).map(|_| stdout)
+    });
+
+    let mut ffmpeg_progress = if is_ffmpeg(&task.command.program) {
+        Some(FfmpegProgress::default())
+    } else {
+        None
+    };
+    let mut child_stderr = child.stderr.take().expect("stderr is piped");
+    let mut stderr = Vec::new();
+    let mut line_start = 0;
+    let mut buf = [0; 4096];
+    loop {
+        let read = child_stderr.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        stderr.extend_from_slice(&buf[..read]);
+        // ffmpeg rewrites its status line with carriage returns rather than new lines
+        while let Some(end) = stderr[line_start..]
+            .iter()
+            .position(|b| *b == b'\n' || *b == b'\r')
+        {
+            let line = String::from_utf8_lossy(&stderr[line_start..line_start + end]);
+            if let Some(progress) = ffmpeg_progress
+                .as_mut()
+                .and_then(|ffmpeg| ffmpeg.parse_line(&line))
+            {
+                queue.set_progress(&task.id, progress);
+            }
+            line_start += end + 1;
+        }
+    }
+
+    let status = child.wait()?;
+    let _ = exited_tx.send(());
+    let timed_out = watchdog
+        .map(|watchdog| watchdog.join().expect("watchdog panicked"))
+        .unwrap_or(false);
+    let stdout = stdout_reader.join().expect("stdout reader panicked")?;
+    Ok((
+        Output {
+            status,
+            stdout,
+            stderr,
+        },
+        timed_out,
+    ))
+}
+
+fn is_ffmpeg(program: &str) -> bool {
+    Path::new(program)
+        .file_name()
+        .map(|name| name == "ffmpeg")
+        .unwrap_or(false)
 }
@@ -0,0 +1,94 @@
+use std::{
+    path::Path,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use serde::{Deserialize, Serialize};
+
+use crate::{constants, error::CmdqError, Task, TaskRunResult};
+
+/// Number of most recent completed runs of a program averaged to estimate its duration
+const ROLLING_AVERAGE_RUNS: usize = 10;
+
+/// The last finished run of a task
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub task: Task,
+    pub result: TaskRunResult,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+pub struct HistoryStore {
+    pickledb: RwLock<PickleDb>,
+}
+
+impl HistoryStore {
+    pub fn new() -> Result<Self, CmdqError> {
+        Self::load(constants::HISTORY_DBFILE)
+    }
+
+    pub(crate) fn load(db_file_path: &str) -> Result<Self, CmdqError> {
+        let pickledb = if Path::new(db_file_path).exists() {
+            PickleDb::load(
+                db_file_path,
+                PickleDbDumpPolicy::AutoDump,
+                SerializationMethod::Bin,
+            )
+            .map_err(|e| CmdqError::PickleLoadDbError(db_file_path.to_string(), e))?
+        } else {
+            PickleDb::new(
+                db_file_path,
+                PickleDbDumpPolicy::AutoDump,
+                SerializationMethod::Bin,
+            )
+        };
+        Ok(HistoryStore {
+            pickledb: RwLock::new(pickledb),
+        })
+    }
+
+    pub fn record(&self, entry: &HistoryEntry) -> Result<(), CmdqError> {
+        let mut pickledb = self.pickledb.write().unwrap();
+        pickledb
+            .set(&entry.task.id, entry)
+            .map_err(CmdqError::PickleDbWriteError)?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Option<HistoryEntry> {
+        let pickledb = self.pickledb.read().unwrap();
+        pickledb.get::<HistoryEntry>(id)
+    }
+
+    /// Every entry, most recently started first
+    pub fn entries(&self) -> Vec<HistoryEntry> {
+        let pickledb = self.pickledb.read().unwrap();
+        let mut entries = pickledb
+            .iter()
+            .filter_map(|item| item.get_value::<HistoryEntry>())
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.started_at));
+        entries
+    }
+
+    /// Average duration of the most recent completed runs of `program`, None if it never completed
+    pub fn average_duration(&self, program: &str) -> Option<Duration> {
+        let completed = self
+            .entries()
+            .into_iter()
+            .filter(|entry| {
+                entry.task.command.program == program
+                    && matches!(entry.result, TaskRunResult::Completed)
+            })
+            .collect::<Vec<_>>();
+        if completed.is_empty() {
+            return None;
+        }
+        let recent = &completed[..completed.len().min(ROLLING_AVERAGE_RUNS)];
+        let total: Duration = recent.iter().map(|entry| entry.duration).sum();
+        Some(total / recent.len() as u32)
+    }
+}
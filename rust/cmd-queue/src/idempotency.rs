@@ -0,0 +1,212 @@
+use std::{
+    path::Path,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use serde::{Deserialize, Serialize};
+
+use crate::{constants, error::CmdqError, Task};
+
+/// How long an idempotency key is remembered after the submission that queued its task
+const KEY_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The task queued by the first submission with an idempotency key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub task_id: String,
+    pub submitted_at: SystemTime,
+}
+
+impl Receipt {
+    fn is_expired(&self) -> bool {
+        self.submitted_at
+            .elapsed()
+            .map(|elapsed| elapsed > KEY_TTL)
+            .unwrap_or(false)
+    }
+}
+
+/// Recent idempotency keys of submissions, so that clients retrying a submission don't queue
+/// its command twice
+pub struct IdempotencyStore {
+    pickledb: RwLock<PickleDb>,
+}
+
+impl IdempotencyStore {
+    pub fn new() -> Result<Self, CmdqError> {
+        Self::load(constants::IDEMPOTENCY_DBFILE)
+    }
+
+    fn load(db_file_path: &str) -> Result<Self, CmdqError> {
+        let pickledb = if Path::new(db_file_path).exists() {
+            PickleDb::load(
+                db_file_path,
+                PickleDbDumpPolicy::AutoDump,
+                SerializationMethod::Bin,
+            )
+            .map_err(|e| CmdqError::PickleLoadDbError(db_file_path.to_string(), e))?
+        } else {
+            PickleDb::new(
+                db_file_path,
+                PickleDbDumpPolicy::AutoDump,
+                SerializationMethod::Bin,
+            )
+        };
+        Ok(IdempotencyStore {
+            pickledb: RwLock::new(pickledb),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Receipt> {
+        let pickledb = self.pickledb.read().unwrap();
+        pickledb
+            .get::<Receipt>(key)
+            .filter(|receipt| !receipt.is_expired())
+    }
+
+    /// Queues the task with `push` unless the key was seen recently. Returns the receipt of the
+    /// task queued with the key along with whether it was queued by an earlier submission.
+    pub fn submit_once<F>(&self, key: &str, push: F) -> Result<(Receipt, bool), CmdqError>
+    where
+        F: FnOnce() -> Result<Task, CmdqError>,
+    {
+        // Held while queueing so that concurrent submissions with the same key queue it once
+        let mut pickledb = self.pickledb.write().unwrap();
+        if let Some(receipt) = pickledb
+            .get::<Receipt>(key)
+            .filter(|receipt| !receipt.is_expired())
+        {
+            return Ok((receipt, true));
+        }
+
+        let task = push()?;
+        let receipt = Receipt {
+            task_id: task.id().to_string(),
+            submitted_at: SystemTime::now(),
+        };
+        let expired = pickledb
+            .iter()
+            .filter(|item| {
+                item.get_value::<Receipt>()
+                    .is_none_or(|receipt| receipt.is_expired())
+            })
+            .map(|item| item.get_key().to_string())
+            .collect::<Vec<_>>();
+        for expired_key in expired {
+            pickledb
+                .rem(&expired_key)
+                .map_err(CmdqError::PickleDbWriteError)?;
+        }
+        pickledb
+            .set(key, &receipt)
+            .map_err(CmdqError::PickleDbWriteError)?;
+        Ok((receipt, false))
+    }
+}
+
+#[cfg(test)]
+fn test_store(name: &str) -> (IdempotencyStore, std::path::PathBuf) {
+    let db_file_path = std::env::temp_dir().join(format!(
+        "cmdq-test-idempotency-{}-{}.db",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&db_file_path);
+    let store = IdempotencyStore::load(db_file_path.to_str().unwrap()).unwrap();
+    (store, db_file_path)
+}
+
+#[cfg(test)]
+fn task(id: &str) -> Result<Task, CmdqError> {
+    Ok(Task {
+        id: id.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_submit_once_returns_first_task() {
+    let (store, db_file_path) = test_store("repeat");
+
+    let (first, duplicate) = store.submit_once("key", || task("first")).unwrap();
+    assert_eq!(first.task_id, "first");
+    assert!(!duplicate);
+
+    let (second, duplicate) = store
+        .submit_once("key", || panic!("the key was already used"))
+        .unwrap();
+    assert_eq!(second.task_id, "first");
+    assert!(duplicate);
+    assert_eq!(
+        store.get("key").map(|receipt| receipt.task_id),
+        Some("first".to_string())
+    );
+
+    let (other, duplicate) = store.submit_once("other key", || task("other")).unwrap();
+    assert_eq!(other.task_id, "other");
+    assert!(!duplicate);
+    let _ = std::fs::remove_file(db_file_path);
+}
+
+#[test]
+fn test_expired_keys_queue_again() {
+    let (store, db_file_path) = test_store("expiry");
+    let expired = Receipt {
+        task_id: "expired".to_string(),
+        submitted_at: SystemTime::now() - KEY_TTL - Duration::from_secs(1),
+    };
+    store
+        .pickledb
+        .write()
+        .unwrap()
+        .set("key", &expired)
+        .unwrap();
+    assert!(store.get("key").is_none());
+
+    let (receipt, duplicate) = store.submit_once("key", || task("new")).unwrap();
+    assert_eq!(receipt.task_id, "new");
+    assert!(!duplicate);
+    let _ = std::fs::remove_file(db_file_path);
+}
+
+#[test]
+fn test_concurrent_submissions_queue_once() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let (store, db_file_path) = test_store("concurrent");
+    let store = Arc::new(store);
+    let pushed = Arc::new(AtomicUsize::new(0));
+    let handles = (0..8)
+        .map(|i| {
+            let store = store.clone();
+            let pushed = pushed.clone();
+            std::thread::spawn(move || {
+                store
+                    .submit_once("key", || {
+                        pushed.fetch_add(1, Ordering::SeqCst);
+                        task(&i.to_string())
+                    })
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    let receipts = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(pushed.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        receipts.iter().filter(|(_, duplicate)| !duplicate).count(),
+        1
+    );
+    assert!(receipts
+        .iter()
+        .all(|(receipt, _)| receipt.task_id == receipts[0].0.task_id));
+    let _ = std::fs::remove_file(db_file_path);
+}
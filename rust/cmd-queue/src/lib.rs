@@ -1,9 +1,22 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 //use crate::task::TaskService;
+use config::ServerConfig;
 use constants::DEFAULT_CONCURRENCY_LEVEL;
 use error::CmdqError;
-use execution::scheduler::TaskScheduler;
+use execution::{
+    outcome::{Attempt, RunOutcome, SuccessPolicy},
+    progress::Progress,
+    recovery::recover_interrupted,
+    scheduler::TaskScheduler,
+};
+use history::{HistoryEntry, HistoryStore};
+use idempotency::IdempotencyStore;
 use queue::InMemoryQueue;
 use rayon::ThreadPoolBuilder;
 use serde::{Deserialize, Serialize};
@@ -11,10 +24,16 @@ use serde::{Deserialize, Serialize};
 
 pub mod cli_util;
 pub mod client;
+pub mod config;
 pub mod constants;
+pub mod daemon;
+pub mod disk;
 pub mod error;
 pub mod execution;
+pub mod history;
+pub mod idempotency;
 pub mod queue;
+pub mod upload;
 //pub mod task;
 pub mod web;
 //pub mod workerpool;
@@ -24,26 +43,308 @@ pub struct CommandRequest {
     pub path: String,
     pub program: String,
     pub args: Vec<String>,
+    /// Id of the task this command was resubmitted from
+    #[serde(default)]
+    pub resubmitted_from: Option<String>,
+    /// Free-form annotations of the task, e.g. project=holiday
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Seconds the task can stay queued before it expires instead of being run
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    /// Seconds the task can run before it is killed
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Host the task runs on over ssh, instead of the default of the server
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Environment variables of the submitting shell the command is run with
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// When runs exiting with a non-zero exit code are successes anyway
+    #[serde(default)]
+    pub success: SuccessPolicy,
+    /// Key chosen by the client so that submitting the command again, e.g. when retrying a
+    /// request that timed out, doesn't queue it twice. Can also be given as the
+    /// Idempotency-Key header
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+/// Queues the command of a finished task again, with the environment it captured kept on the
+/// server
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ResubmitRequest {
+    /// Replacements of the args of the command, as (old, new)
+    #[serde(default)]
+    pub arg_replace: Vec<(String, String)>,
+    /// Idempotency key of the command queued, the one of the finished task isn't reused
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+}
+
+const MASKED_ENV_VALUE: &str = "***";
+
+/// Args of yt-dlp downloading `url`, with `prefix` prepended to the downloaded file name
+pub fn ytdlp_args(url: String, prefix: Option<&str>) -> Vec<String> {
+    if let Some(prefix) = prefix {
+        vec![
+            "-o".to_string(),
+            format!("{} %(title)s [%(id)s].%(ext)s", prefix),
+            url,
+        ]
+    } else {
+        vec![url]
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CommandResponse {
     Success(CommandSuccess),
     Failed(CommandFailed),
+    /// The command wasn't queued, it can be submitted again with force to queue it anyway
+    Warning(CommandWarning),
+    /// The command can't be queued on this server
+    Rejected(CommandRejected),
 }
 
+/// Receipt of a submission
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CommandSuccess {}
+pub struct CommandSuccess {
+    /// Id of the queued task, the one queued first for repeated submissions
+    #[serde(default)]
+    pub task_id: Option<String>,
+    /// Whether the command was already queued by an earlier submission with the same
+    /// idempotency key, and wasn't queued again
+    #[serde(default)]
+    pub duplicate: bool,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandFailed {}
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandWarning {
+    LowDiskSpace {
+        path: String,
+        available_bytes: u64,
+        total_bytes: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CommandRejected {
+    ProgramNotAllowed { program: String },
+    EnvNotAllowed { key: String },
+    QueueFull { max: usize },
+    ClientQueueFull { client: String, max: usize },
+    LabelQueueFull { label: String, max: usize },
+    InvalidSuccessRegex { regex: String, error: String },
+}
+
+impl fmt::Display for CommandRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandRejected::ProgramNotAllowed { program } => {
+                write!(f, "Program {} is not allowed on this server", program)
+            }
+            CommandRejected::EnvNotAllowed { key } => {
+                write!(
+                    f,
+                    "Environment variable {} is not allowed on this server",
+                    key
+                )
+            }
+            CommandRejected::QueueFull { max } => {
+                write!(f, "Queue is full, it already has {} pending tasks", max)
+            }
+            CommandRejected::ClientQueueFull { client, max } => {
+                write!(f, "{} already has {} pending tasks", client, max)
+            }
+            CommandRejected::LabelQueueFull { label, max } => {
+                write!(f, "Label {} already has {} pending tasks", label, max)
+            }
+            CommandRejected::InvalidSuccessRegex { regex, error } => {
+                write!(f, "Invalid success regex {}. {}", regex, error)
+            }
+        }
+    }
+}
+
+impl fmt::Display for CommandWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandWarning::LowDiskSpace {
+                path,
+                available_bytes,
+                total_bytes,
+            } => {
+                let gib = |bytes: u64| bytes as f64 / (1024 * 1024 * 1024) as f64;
+                write!(
+                    f,
+                    "Only {:.1} GiB free of {:.1} GiB on the disk of {}",
+                    gib(*available_bytes),
+                    gib(*total_bytes),
+                    path
+                )
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Task {
     id: String,
     command: CommandRequest,
     tries: usize,
     last_attempt: Option<SystemTime>,
+    submitted_at: Option<SystemTime>,
+    /// Address of the client that queued the task
+    submitted_by: Option<String>,
+    /// When the task was last put at the back of the queue, or moved to its front
+    queued_at: Option<SystemTime>,
+    /// When the current run of the task started
+    started_at: Option<SystemTime>,
+    /// Progress of the current run, for programs that report it
+    progress: Option<Progress>,
+    /// How the last run ended
+    last_outcome: Option<RunOutcome>,
+    /// Every run of the task, oldest first
+    #[serde(default)]
+    attempts: Vec<Attempt>,
+    /// Moved to the front of the queue, ahead of the tasks that weren't
+    #[serde(default)]
+    bumped: bool,
+}
+
+impl Task {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn command(&self) -> &CommandRequest {
+        &self.command
+    }
+
+    pub fn attempts(&self) -> &[Attempt] {
+        &self.attempts
+    }
+
+    /// Whether the task has the label of `filter`, either `key:value` or only `key` to match
+    /// any value
+    pub fn has_label(&self, filter: &str) -> bool {
+        match filter.split_once(':') {
+            Some((key, value)) => self.command.labels.get(key).map(String::as_str) == Some(value),
+            None => self.command.labels.contains_key(filter),
+        }
+    }
+
+    /// Whether the task has been queued for longer than its TTL
+    pub fn is_expired(&self) -> bool {
+        match (self.command.ttl_secs, self.submitted_at) {
+            (Some(ttl_secs), Some(submitted_at)) => submitted_at
+                .elapsed()
+                .map(|elapsed| elapsed > Duration::from_secs(ttl_secs))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// The task with the values of its captured environment hidden, for responses to clients
+    pub fn masked(mut self) -> Self {
+        for value in self.command.env.values_mut() {
+            *value = MASKED_ENV_VALUE.to_string();
+        }
+        self
+    }
+
+    pub fn format_env(&self) -> String {
+        self.command
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn format_last_outcome(&self) -> String {
+        self.last_outcome
+            .as_ref()
+            .map(|outcome| outcome.to_string())
+            .unwrap_or("-".to_string())
+    }
+
+    pub fn format_labels(&self) -> String {
+        self.command
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Number of tasks that didn't finish yet
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueCounts {
+    pub queued: usize,
+    pub running: usize,
+    /// Queued and running tasks by address of the client that queued them
+    pub pending_by_client: BTreeMap<String, usize>,
+    /// Queued and running tasks by label, as key=value
+    pub pending_by_label: BTreeMap<String, usize>,
+}
+
+impl QueueCounts {
+    pub fn pending(&self) -> usize {
+        self.queued + self.running
+    }
+}
+
+/// A task as listed by the API, with its place in the queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListedTask {
+    #[serde(flatten)]
+    pub task: Task,
+    /// 1-based position in the queue, None for running tasks
+    pub position: Option<usize>,
+    /// Estimated seconds until the task finishes, None if a program ahead of it never completed
+    pub eta_secs: Option<u64>,
+}
+
+impl ListedTask {
+    pub fn format_eta(&self) -> String {
+        self.eta_secs
+            .map(|secs| format!("~{}", humantime::format_duration(Duration::from_secs(secs))))
+            .unwrap_or("unknown".to_string())
+    }
+}
+
+/// Where a task is, as shown by `GET /api/commands/{id}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    /// The task won't run again, with how its last run ended
+    Finished(TaskRunResult),
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskStatus::Queued => write!(f, "queued"),
+            TaskStatus::Running => write!(f, "running"),
+            TaskStatus::Finished(result) => write!(f, "finished, {:?}", result),
+        }
+    }
+}
+
+/// A task with its current status, including the history of its attempts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskDetail {
+    #[serde(flatten)]
+    pub task: Task,
+    pub status: TaskStatus,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +352,14 @@ pub enum TaskRunResult {
     Completed,
     Failed,
     Skipped,
+    /// The task was queued for longer than its TTL and won't be run
+    Expired,
+    /// The task failed in a way that retrying won't fix, or failed too many times
+    FailedPermanently,
+    /// The task was removed from the queue before it ran
+    Cancelled,
+    /// The server stopped while the task was running and it wasn't queued again on startup
+    Interrupted,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
@@ -68,14 +377,20 @@ pub enum QueueState {
 #[derive(Clone)]
 pub struct CommandQApp {
     pub queue: Arc<InMemoryQueue>,
+    pub history: Arc<HistoryStore>,
+    pub idempotency: Arc<IdempotencyStore>,
     pub task_scheduler: Arc<TaskScheduler>,
+    pub config: ServerConfig,
     // pub task_svc: Arc<TaskService>,
     // pub worker_pool: Arc<WorkerPool>,
 }
 
 impl CommandQApp {
-    pub fn new() -> Result<Self, CmdqError> {
+    pub fn new(config: ServerConfig) -> Result<Self, CmdqError> {
         let queue = Arc::new(InMemoryQueue::new()?);
+        let history = Arc::new(HistoryStore::new()?);
+        recover_interrupted(&queue, &history, config.on_interrupted)?;
+        let idempotency = Arc::new(IdempotencyStore::new()?);
         //let task_svc = Arc::new(TaskService::new(queue.clone()));
 
         let num_workers = DEFAULT_CONCURRENCY_LEVEL;
@@ -85,15 +400,226 @@ impl CommandQApp {
         //     .expect("failed building threadpool");
         // let worker_pool = Arc::new(WorkerPool::new(task_svc.clone(), num_workers, thread_pool));
         // worker_pool.spawn();
-        let task_scheduler = Arc::new(TaskScheduler::new(queue.clone(), num_workers));
+        let task_scheduler = Arc::new(TaskScheduler::new(
+            queue.clone(),
+            history.clone(),
+            num_workers,
+            config.sequential_per_dir,
+            config.ssh_host.clone(),
+        ));
         task_scheduler.clone().run();
 
         Ok(CommandQApp {
             queue: queue,
+            history: history,
+            idempotency: idempotency,
             task_scheduler: task_scheduler,
+            config: config,
             // task_svc: task_svc,
             // worker_pool: worker_pool,
+        })
+    }
+
+    pub fn counts(&self) -> QueueCounts {
+        let queued = self.queue.queued();
+        let running = self.queue.running();
+        let mut counts = QueueCounts {
+            queued: queued.len(),
+            running: running.len(),
+            ..Default::default()
+        };
+        for task in queued.iter().chain(running.iter()) {
+            if let Some(client) = &task.submitted_by {
+                *counts.pending_by_client.entry(client.clone()).or_default() += 1;
+            }
+            for (key, value) in &task.command.labels {
+                *counts
+                    .pending_by_label
+                    .entry(format!("{}={}", key, value))
+                    .or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Checks that queueing `command` from `client` stays within the limits of the config, and
+    /// that its environment doesn't change which program runs
+    pub fn check_limits(
+        &self,
+        command: &CommandRequest,
+        client: Option<&str>,
+    ) -> Result<(), CommandRejected> {
+        let config = &self.config;
+        config.check_env(&command.env)?;
+        if config.max_queue_len.is_none()
+            && config.max_pending_per_client.is_none()
+            && config.max_pending_per_label.is_none()
+        {
+            return Ok(());
+        }
+
+        let counts = self.counts();
+        if let Some(max) = config.max_queue_len {
+            if counts.pending() >= max {
+                return Err(CommandRejected::QueueFull { max });
+            }
+        }
+        if let (Some(max), Some(client)) = (config.max_pending_per_client, client) {
+            if counts.pending_by_client.get(client).copied().unwrap_or(0) >= max {
+                return Err(CommandRejected::ClientQueueFull {
+                    client: client.to_string(),
+                    max,
+                });
+            }
+        }
+        if let Some(max) = config.max_pending_per_label {
+            for (key, value) in &command.labels {
+                let label = format!("{}={}", key, value);
+                if counts.pending_by_label.get(&label).copied().unwrap_or(0) >= max {
+                    return Err(CommandRejected::LabelQueueFull { label, max });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The task, whether it is queued, running or finished. Tasks failing with retries left are
+    /// queued again so they are found in the queue before the history.
+    pub fn task_detail(&self, id: &str) -> Option<TaskDetail> {
+        if let Some((task, state)) = self.queue.get(id) {
+            let status = match state {
+                TaskState::Queued => TaskStatus::Queued,
+                TaskState::Running => TaskStatus::Running,
+            };
+            return Some(TaskDetail {
+                task: task.masked(),
+                status,
+            });
+        }
+        self.history.get(id).map(|entry| TaskDetail {
+            task: entry.task.masked(),
+            status: TaskStatus::Finished(entry.result),
+        })
+    }
+
+    /// Queues the command as the task `id`, unless it was already queued by a submission with
+    /// the same idempotency key
+    pub fn submit(
+        &self,
+        id: String,
+        command: &CommandRequest,
+        client: Option<String>,
+    ) -> Result<CommandSuccess, CmdqError> {
+        let push = || self.queue.push_cmd_with_id(id, command, client);
+        match &command.idempotency_key {
+            Some(key) => {
+                let (receipt, duplicate) = self.idempotency.submit_once(key, push)?;
+                Ok(CommandSuccess {
+                    task_id: Some(receipt.task_id),
+                    duplicate,
+                })
+            }
+            None => push().map(|task| CommandSuccess {
+                task_id: Some(task.id),
+                duplicate: false,
+            }),
+        }
+    }
 
+    /// Removes the queued task from the queue, recording it as cancelled in the history
+    pub fn cancel_task(&self, task: Task) -> Result<(), CmdqError> {
+        self.queue.update(&task.id, TaskRunResult::Cancelled)?;
+        upload::cleanup(&task.id);
+        self.history.record(&HistoryEntry {
+            task,
+            result: TaskRunResult::Cancelled,
+            started_at: SystemTime::now(),
+            duration: Duration::ZERO,
         })
     }
+
+    pub fn running_tasks(&self) -> Vec<ListedTask> {
+        let mut estimates = HashMap::new();
+        self.queue
+            .running()
+            .into_iter()
+            .map(|task| {
+                let eta = self.remaining(&task, &mut estimates);
+                ListedTask {
+                    task: task.masked(),
+                    position: None,
+                    eta_secs: eta.map(|eta| eta.as_secs()),
+                }
+            })
+            .collect()
+    }
+
+    /// Queued tasks in queue order. The ETA of each task is found by handing queued tasks, in
+    /// order, to whichever worker is expected to be free first, assuming every run of a program
+    /// takes as long as its recent average.
+    pub fn queued_tasks(&self) -> Vec<ListedTask> {
+        let mut estimates = HashMap::new();
+        let mut workers = self
+            .queue
+            .running()
+            .iter()
+            .map(|task| self.remaining(task, &mut estimates))
+            .collect::<Vec<_>>();
+        let num_workers = self.task_scheduler.num_workers();
+        if workers.len() < num_workers {
+            workers.resize(num_workers, Some(Duration::ZERO));
+        }
+
+        let mut queued = self.queue.queued();
+        queued
+            .sort_by(|a, b| (!a.bumped, a.queued_at, &a.id).cmp(&(!b.bumped, b.queued_at, &b.id)));
+        queued
+            .into_iter()
+            .enumerate()
+            .map(|(i, task)| {
+                let estimate = self.estimate(&task.command.program, &mut estimates);
+                // A worker whose ETA is unknown could be free at any time
+                let next_free = workers
+                    .iter_mut()
+                    .min_by_key(|free_in| free_in.map_or((0, Duration::ZERO), |d| (1, d)));
+                let eta = match next_free {
+                    Some(free_in) => {
+                        *free_in = free_in
+                            .zip(estimate)
+                            .map(|(free_in, estimate)| free_in + estimate);
+                        *free_in
+                    }
+                    None => None,
+                };
+                ListedTask {
+                    task: task.masked(),
+                    position: Some(i + 1),
+                    eta_secs: eta.map(|eta| eta.as_secs()),
+                }
+            })
+            .collect()
+    }
+
+    fn estimate(
+        &self,
+        program: &str,
+        estimates: &mut HashMap<String, Option<Duration>>,
+    ) -> Option<Duration> {
+        *estimates
+            .entry(program.to_string())
+            .or_insert_with(|| self.history.average_duration(program))
+    }
+
+    fn remaining(
+        &self,
+        task: &Task,
+        estimates: &mut HashMap<String, Option<Duration>>,
+    ) -> Option<Duration> {
+        let estimate = self.estimate(&task.command.program, estimates)?;
+        let elapsed = task
+            .started_at
+            .and_then(|started_at| started_at.elapsed().ok())
+            .unwrap_or_default();
+        Some(estimate.saturating_sub(elapsed))
+    }
 }
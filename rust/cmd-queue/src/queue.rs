@@ -1,11 +1,16 @@
 use std::{path::Path, sync::RwLock, time::SystemTime};
 
 use crossbeam::queue::SegQueue;
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use nanoid::nanoid;
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
 
-use crate::{constants, error::CmdqError, CommandRequest, Task, TaskRunResult};
+use crate::{
+    constants,
+    error::CmdqError,
+    execution::{outcome::Attempt, progress::Progress},
+    CommandRequest, Task, TaskRunResult, TaskState,
+};
 
 const NANOID_ALPHABET: [char; 16] = [
     '1', '2', '3', '4', '5', '6', '7', '8', '9', '0', 'a', 'b', 'c', 'd', 'e', 'f',
@@ -17,15 +22,26 @@ pub fn generate_task_id() -> String {
 
 pub struct InMemoryQueue {
     queue: SegQueue<Task>,
+    /// Bumped tasks, run before the ones of `queue`
+    front: SegQueue<Task>,
+    /// Ids of the tasks in `front`, so that bumping a task twice doesn't queue it twice
+    in_front: DashSet<String>,
+    /// Number of copies of each bumped task left behind in `queue`, skipped when popped
+    stale: DashMap<String, usize>,
     running: DashMap<String, Task>,
     pickledb: RwLock<PickleDb>,
 }
 
 impl InMemoryQueue {
     pub fn new() -> Result<Self, CmdqError> {
+        Self::load(constants::DBFILE)
+    }
+
+    fn load(db_file_path: &str) -> Result<Self, CmdqError> {
         let queue = SegQueue::new();
+        let front = SegQueue::new();
+        let in_front = DashSet::new();
 
-        let db_file_path = constants::DBFILE;
         let pickledb = if Path::new(db_file_path).exists() {
             let db = PickleDb::load(
                 db_file_path,
@@ -33,9 +49,19 @@ impl InMemoryQueue {
                 SerializationMethod::Bin,
             )
             .map_err(|e| CmdqError::PickleLoadDbError(db_file_path.to_string(), e))?;
+            // Tasks that were running when the server stopped are handled by
+            // `recovery::recover_interrupted`
             db.iter()
                 .filter_map(|item| item.get_value::<Task>())
-                .for_each(|task| queue.push(task));
+                .filter(|task| task.started_at.is_none())
+                .for_each(|task| {
+                    if task.bumped {
+                        in_front.insert(task.id.clone());
+                        front.push(task)
+                    } else {
+                        queue.push(task)
+                    }
+                });
             db
         } else {
             PickleDb::new(
@@ -46,22 +72,41 @@ impl InMemoryQueue {
         };
         Ok(InMemoryQueue {
             queue: queue,
+            front: front,
+            in_front,
+            stale: DashMap::new(),
             running: DashMap::new(),
             pickledb: RwLock::new(pickledb),
         })
     }
 
-    pub fn push_cmd(&self, command: &CommandRequest) -> Result<Task, CmdqError> {
+    pub fn push_cmd(
+        &self,
+        command: &CommandRequest,
+        submitted_by: Option<String>,
+    ) -> Result<Task, CmdqError> {
+        self.push_cmd_with_id(generate_task_id(), command, submitted_by)
+    }
+
+    pub fn push_cmd_with_id(
+        &self,
+        id: String,
+        command: &CommandRequest,
+        submitted_by: Option<String>,
+    ) -> Result<Task, CmdqError> {
         let task = Task {
-            id: generate_task_id(),
+            id: id,
             command: command.clone(),
+            submitted_by: submitted_by,
+            submitted_at: Some(SystemTime::now()),
+            queued_at: Some(SystemTime::now()),
             ..Default::default()
         };
         self.push(task.clone())?;
         Ok(task)
     }
 
-    fn push(&self, task: Task) -> Result<(), CmdqError> {
+    pub(crate) fn push(&self, task: Task) -> Result<(), CmdqError> {
         self.queue.push(task.clone());
         let mut pickledb = self.pickledb.write().unwrap();
         pickledb
@@ -70,18 +115,93 @@ impl InMemoryQueue {
         Ok(())
     }
 
+    /// Checks that the queue can still be persisted by writing it to its db file
+    pub fn check_writable(&self) -> Result<(), CmdqError> {
+        let mut pickledb = self.pickledb.write().unwrap();
+        pickledb.dump().map_err(CmdqError::PickleDbWriteError)
+    }
+
+    pub fn len(&self) -> usize {
+        self.front.len() + self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.front.is_empty() && self.queue.is_empty()
+    }
+
     pub fn pop_next(&self) -> Option<Task> {
-        if let Some(task) = self.queue.pop() {
+        loop {
+            let mut task = match self.front.pop() {
+                Some(task) => {
+                    self.in_front.remove(&task.id);
+                    task
+                }
+                None => {
+                    let task = self.queue.pop()?;
+                    if self.take_stale(&task.id) {
+                        continue;
+                    }
+                    task
+                }
+            };
+            // Cancelled tasks are only removed from the db
+            if !self.pickledb.read().unwrap().exists(&task.id) {
+                continue;
+            }
+            task.started_at = Some(SystemTime::now());
             self.running.insert(task.id.clone(), task.clone());
-            Some(task)
-        } else {
-            None
+            return Some(task);
         }
     }
 
+    /// Whether a copy of the task was left behind in the queue when it was bumped
+    fn take_stale(&self, id: &str) -> bool {
+        let left = match self.stale.get_mut(id) {
+            Some(mut count) => {
+                *count -= 1;
+                *count
+            }
+            None => return false,
+        };
+        if left == 0 {
+            self.stale.remove(id);
+        }
+        true
+    }
+
+    /// Moves the queued task to the front of the queue, behind the tasks bumped before it. Tasks
+    /// already at the front are left where they are.
+    pub fn bump(&self, id: &str) -> Result<(), CmdqError> {
+        if self.in_front.contains(id) {
+            return Ok(());
+        }
+        let mut task = {
+            let mut pickledb = self.pickledb.write().unwrap();
+            let mut task = match pickledb.get::<Task>(id) {
+                Some(task) if !self.running.contains_key(id) => task,
+                _ => return Ok(()),
+            };
+            task.bumped = true;
+            task.queued_at = Some(SystemTime::now());
+            pickledb
+                .set(id, &task)
+                .map_err(CmdqError::PickleDbWriteError)?;
+            task
+        };
+        task.started_at = None;
+        *self.stale.entry(id.to_string()).or_default() += 1;
+        self.in_front.insert(id.to_string());
+        self.front.push(task);
+        Ok(())
+    }
+
     pub fn update(&self, id: &str, state: TaskRunResult) -> Result<(), CmdqError> {
         match state {
-            TaskRunResult::Completed => {
+            TaskRunResult::Completed
+            | TaskRunResult::Expired
+            | TaskRunResult::FailedPermanently
+            | TaskRunResult::Cancelled
+            | TaskRunResult::Interrupted => {
                 self.running.remove(id);
                 {
                     let mut pickledb = self.pickledb.write().unwrap();
@@ -94,6 +214,10 @@ impl InMemoryQueue {
                 let (_id, mut task) = self.running.remove(id).expect("task does not exist");
                 task.tries += 1;
                 task.last_attempt = Some(SystemTime::now());
+                task.queued_at = task.last_attempt;
+                task.started_at = None;
+                task.progress = None;
+                task.bumped = false;
 
                 {
                     let mut pickledb = self.pickledb.write().unwrap();
@@ -104,21 +228,72 @@ impl InMemoryQueue {
                 self.queue.push(task);
             }
             TaskRunResult::Skipped => {
-                let (_id, task) = self.running.remove(id).expect("task does not exist");
+                let (_id, mut task) = self.running.remove(id).expect("task does not exist");
+                task.started_at = None;
+                task.progress = None;
                 self.queue.push(task);
             }
         }
         Ok(())
     }
 
+    /// Adds the attempt to the running task, persisted with it if it is queued again
+    pub fn record_attempt(&self, id: &str, attempt: Attempt) {
+        if let Some(mut task) = self.running.get_mut(id) {
+            task.last_outcome = Some(attempt.outcome.clone());
+            task.attempts.push(attempt);
+        }
+    }
+
+    /// Persists the running task, so that it is found on startup if the server stops before it
+    /// finishes
+    pub fn mark_running(&self, id: &str) -> Result<(), CmdqError> {
+        let task = match self.running.get(id) {
+            Some(task) => task.value().clone(),
+            None => return Ok(()),
+        };
+        let mut pickledb = self.pickledb.write().unwrap();
+        pickledb
+            .set(id, &task)
+            .map_err(CmdqError::PickleDbWriteError)
+    }
+
+    /// Tasks persisted as running that aren't, because the server stopped while they ran
+    pub fn interrupted(&self) -> Vec<Task> {
+        let pickledb = self.pickledb.read().unwrap();
+        pickledb
+            .iter()
+            .filter_map(|item| item.get_value::<Task>())
+            .filter(|task| task.started_at.is_some() && !self.running.contains_key(&task.id))
+            .collect::<Vec<_>>()
+    }
+
+    pub fn set_progress(&self, id: &str, progress: Progress) {
+        if let Some(mut task) = self.running.get_mut(id) {
+            task.progress = Some(progress);
+        }
+    }
+
     pub fn queued(&self) -> Vec<Task> {
         let pickledb = self.pickledb.read().unwrap();
         pickledb
             .iter()
             .filter_map(|item| item.get_value::<Task>())
+            .filter(|task| !self.running.contains_key(&task.id))
             .collect::<Vec<_>>()
     }
 
+    /// The task if it is running or queued
+    pub fn get(&self, id: &str) -> Option<(Task, TaskState)> {
+        if let Some(task) = self.running.get(id) {
+            return Some((task.value().clone(), TaskState::Running));
+        }
+        let pickledb = self.pickledb.read().unwrap();
+        pickledb
+            .get::<Task>(id)
+            .map(|task| (task, TaskState::Queued))
+    }
+
     pub fn running(&self) -> Vec<Task> {
         self.running
             .iter()
@@ -126,3 +301,79 @@ impl InMemoryQueue {
             .collect::<Vec<_>>()
     }
 }
+
+#[test]
+fn test_bump_twice() {
+    let db_file_path =
+        std::env::temp_dir().join(format!("cmdq-test-bump-{}.db", std::process::id()));
+    let queue = InMemoryQueue::load(db_file_path.to_str().unwrap()).unwrap();
+    let command = CommandRequest::default();
+    queue
+        .push_cmd_with_id("first".to_string(), &command, None)
+        .unwrap();
+    queue
+        .push_cmd_with_id("second".to_string(), &command, None)
+        .unwrap();
+
+    queue.bump("second").unwrap();
+    queue.bump("second").unwrap();
+
+    assert_eq!(
+        queue.pop_next().map(|task| task.id),
+        Some("second".to_string())
+    );
+    assert_eq!(
+        queue.pop_next().map(|task| task.id),
+        Some("first".to_string())
+    );
+    assert_eq!(queue.pop_next().map(|task| task.id), None);
+
+    // The retry of the bumped task is queued again rather than dropped as a stale copy
+    queue.update("second", TaskRunResult::Failed).unwrap();
+    assert_eq!(
+        queue.pop_next().map(|task| task.id),
+        Some("second".to_string())
+    );
+    let _ = std::fs::remove_file(db_file_path);
+}
+
+#[test]
+fn test_expired_task_recorded_in_history() {
+    use crate::{execution::scheduler::expire_task, history::HistoryStore};
+    use std::time::Duration;
+
+    let queue_db_file_path =
+        std::env::temp_dir().join(format!("cmdq-test-expire-{}.db", std::process::id()));
+    let history_db_file_path = std::env::temp_dir().join(format!(
+        "cmdq-test-expire-history-{}.db",
+        std::process::id()
+    ));
+    let queue = InMemoryQueue::load(queue_db_file_path.to_str().unwrap()).unwrap();
+    let history = HistoryStore::load(history_db_file_path.to_str().unwrap()).unwrap();
+    let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+    let task = |id: &str, ttl_secs: u64| Task {
+        id: id.to_string(),
+        command: CommandRequest {
+            ttl_secs: Some(ttl_secs),
+            ..Default::default()
+        },
+        submitted_at: Some(hour_ago),
+        queued_at: Some(hour_ago),
+        ..Default::default()
+    };
+    queue.push(task("expired", 60)).unwrap();
+    queue.push(task("fresh", 2 * 60 * 60)).unwrap();
+
+    let expired = queue.pop_next().unwrap();
+    assert!(expired.is_expired());
+    expire_task(expired, &queue, &history);
+    assert!(!queue.pop_next().unwrap().is_expired());
+
+    assert!(queue.get("expired").is_none());
+    assert!(matches!(
+        history.get("expired").map(|entry| entry.result),
+        Some(TaskRunResult::Expired)
+    ));
+    let _ = std::fs::remove_file(queue_db_file_path);
+    let _ = std::fs::remove_file(history_db_file_path);
+}
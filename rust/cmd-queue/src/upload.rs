@@ -0,0 +1,93 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use actix_multipart::Multipart;
+use actix_web::{error::BlockingError, web};
+use futures_util::{StreamExt, TryStreamExt};
+
+use crate::{constants, error::CmdqError, CommandRequest};
+
+/// Placeholder in the args of an uploaded command replaced by the path of the uploaded file
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+
+/// Directory the input file of the task is uploaded to
+pub fn staging_dir(task_id: &str) -> PathBuf {
+    Path::new(constants::UPLOADS_DIR).join(task_id)
+}
+
+/// Removes the uploaded input of the task, if it had one
+pub fn cleanup(task_id: &str) {
+    let dir = staging_dir(task_id);
+    if dir.exists() {
+        if let Err(err) = std::fs::remove_dir_all(&dir) {
+            println!("Error removing upload dir {} {}", dir.display(), err);
+        }
+    }
+}
+
+/// Reads a multipart submission made of a `command` field, the JSON of the CommandRequest, and
+/// a `file` field streamed into the staging directory of the task. Returns the command with
+/// its `{input}` args replaced by the path of the uploaded file.
+pub async fn receive(task_id: &str, mut payload: Multipart) -> Result<CommandRequest, CmdqError> {
+    let dir = staging_dir(task_id);
+    let mut command: Option<CommandRequest> = None;
+    let mut input: Option<PathBuf> = None;
+
+    while let Some(mut field) = payload.try_next().await.map_err(CmdqError::UploadRead)? {
+        let content_disposition = field.content_disposition();
+        let name = content_disposition
+            .as_ref()
+            .and_then(|cd| cd.get_name())
+            .unwrap_or("")
+            .to_string();
+        match name.as_str() {
+            "command" => {
+                let mut json = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    json.extend_from_slice(&chunk.map_err(CmdqError::UploadRead)?);
+                }
+                command =
+                    Some(serde_json::from_slice(&json).map_err(CmdqError::UploadInvalidCommand)?);
+            }
+            "file" => {
+                // Only the file name is kept so that the upload can't escape the staging dir
+                let file_name = content_disposition
+                    .as_ref()
+                    .and_then(|cd| cd.get_filename())
+                    .and_then(|file_name| Path::new(file_name).file_name())
+                    .map(|file_name| file_name.to_owned())
+                    .unwrap_or_else(|| "input".into());
+                let path = dir.join(file_name);
+                let write_err =
+                    |path: &Path, e| CmdqError::UploadWrite(path.display().to_string(), e);
+
+                std::fs::create_dir_all(&dir).map_err(|e| write_err(&dir, e))?;
+                let mut file = File::create(&path).map_err(|e| write_err(&path, e))?;
+                while let Some(chunk) = field.next().await {
+                    let data = chunk.map_err(CmdqError::UploadRead)?;
+                    file = web::block(move || file.write_all(&data).map(|_| file))
+                        .await
+                        .map_err(|e| match e {
+                            BlockingError::Error(e) => write_err(&path, e),
+                            BlockingError::Canceled => {
+                                write_err(&path, std::io::Error::other("write canceled"))
+                            }
+                        })?;
+                }
+                input = Some(path);
+            }
+            _ => {}
+        }
+    }
+
+    let mut command = command.ok_or(CmdqError::UploadMissingField("command"))?;
+    let input = input.ok_or(CmdqError::UploadMissingField("file"))?;
+    let input = input.to_string_lossy();
+    for arg in command.args.iter_mut() {
+        *arg = arg.replace(INPUT_PLACEHOLDER, &input);
+    }
+    Ok(command)
+}
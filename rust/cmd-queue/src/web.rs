@@ -1,2 +1,4 @@
 pub mod api;
+pub mod health;
 pub mod html;
+pub mod metrics;
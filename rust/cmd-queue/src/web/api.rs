@@ -1,28 +1,271 @@
 use std::sync::Arc;
 
-use actix_web::{get, post, web, Responder};
+use actix_multipart::Multipart;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::Deserialize;
 
-use crate::{CommandFailed, CommandQApp, CommandRequest, CommandResponse, CommandSuccess};
+use crate::{
+    disk, history::HistoryEntry, queue::generate_task_id, upload, CommandFailed, CommandQApp,
+    CommandRequest, CommandResponse, CommandSuccess, ListedTask, ResubmitRequest, TaskState,
+};
+
+/// Header of the idempotency key of a submission, taking precedence over the one of the command
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// IP address of the client the request comes from
+pub fn client_addr(req: &HttpRequest) -> Option<String> {
+    req.peer_addr().map(|addr| addr.ip().to_string())
+}
+
+/// Sets the idempotency key of the header on the command. Returns the receipt of the task
+/// queued first when the key was already used, in which case the command isn't queued again.
+fn check_idempotency_key(
+    app: &CommandQApp,
+    command: &mut CommandRequest,
+    req: &HttpRequest,
+) -> Option<CommandSuccess> {
+    if let Some(key) = req
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+    {
+        command.idempotency_key = Some(key.to_string());
+    }
+    let receipt = app.idempotency.get(command.idempotency_key.as_deref()?)?;
+    Some(CommandSuccess {
+        task_id: Some(receipt.task_id),
+        duplicate: true,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueueCommandQuery {
+    /// Queue the command even if there are warnings about it
+    #[serde(default)]
+    force: bool,
+}
 
 #[post("/api/commands")]
 async fn queue_command(
     app: web::Data<Arc<CommandQApp>>,
     command: web::Json<CommandRequest>,
+    query: web::Query<QueueCommandQuery>,
+    req: HttpRequest,
 ) -> impl Responder {
     println!("queue command {:?}", command);
+    queue_checked(&app, command.into_inner(), query.force, &req)
+}
+
+/// Queues the command unless it is a duplicate, is rejected or has warnings and isn't forced
+fn queue_checked(
+    app: &CommandQApp,
+    mut command: CommandRequest,
+    force: bool,
+    req: &HttpRequest,
+) -> HttpResponse {
+    if let Some(receipt) = check_idempotency_key(app, &mut command, req) {
+        return HttpResponse::Ok().json(CommandResponse::Success(receipt));
+    }
+    let client = client_addr(req);
+    if let Err(rejected) = command.success.check() {
+        return HttpResponse::BadRequest().json(CommandResponse::Rejected(rejected));
+    }
+    if let Err(rejected) = app.config.check_program(&command.program) {
+        return HttpResponse::Forbidden().json(CommandResponse::Rejected(rejected));
+    }
+    if let Err(rejected) = app.check_limits(&command, client.as_deref()) {
+        return HttpResponse::TooManyRequests().json(CommandResponse::Rejected(rejected));
+    }
+    // The free space of remote hosts isn't known
+    let runs_locally = command.host.is_none() && app.config.ssh_host.is_none();
+    if !force && runs_locally {
+        if let Some(warning) = disk::check_free_space(&command.path) {
+            return HttpResponse::Ok().json(CommandResponse::Warning(warning));
+        }
+    }
     // TODO better error handling
-    match app.queue.push_cmd(&command) {
-        Ok(_) => web::Json(CommandResponse::Success(CommandSuccess {})),
-        Err(_) => web::Json(CommandResponse::Failed(CommandFailed {})),
+    match app.submit(generate_task_id(), &command, client) {
+        Ok(receipt) => HttpResponse::Ok().json(CommandResponse::Success(receipt)),
+        Err(_) => HttpResponse::Ok().json(CommandResponse::Failed(CommandFailed {})),
+    }
+}
+
+/// Queues a command along with an input file uploaded from the client, see `upload::receive`
+#[post("/api/commands/upload")]
+async fn queue_command_with_upload(
+    app: web::Data<Arc<CommandQApp>>,
+    payload: Multipart,
+    req: HttpRequest,
+) -> impl Responder {
+    let id = generate_task_id();
+    let client = client_addr(&req);
+    let mut command = match upload::receive(&id, payload).await {
+        Ok(command) => command,
+        Err(err) => {
+            println!("Error receiving upload {}", err);
+            upload::cleanup(&id);
+            return HttpResponse::BadRequest().json(CommandResponse::Failed(CommandFailed {}));
+        }
+    };
+    println!("queue command with upload {:?}", command);
+    if let Some(receipt) = check_idempotency_key(&app, &mut command, &req) {
+        upload::cleanup(&id);
+        return HttpResponse::Ok().json(CommandResponse::Success(receipt));
+    }
+    if let Err(rejected) = command.success.check() {
+        upload::cleanup(&id);
+        return HttpResponse::BadRequest().json(CommandResponse::Rejected(rejected));
+    }
+    if let Err(rejected) = app.config.check_program(&command.program) {
+        upload::cleanup(&id);
+        return HttpResponse::Forbidden().json(CommandResponse::Rejected(rejected));
+    }
+    if let Err(rejected) = app.check_limits(&command, client.as_deref()) {
+        upload::cleanup(&id);
+        return HttpResponse::TooManyRequests().json(CommandResponse::Rejected(rejected));
+    }
+    match app.submit(id.clone(), &command, client) {
+        Ok(receipt) => {
+            if receipt.duplicate {
+                upload::cleanup(&id);
+            }
+            HttpResponse::Ok().json(CommandResponse::Success(receipt))
+        }
+        Err(_) => {
+            upload::cleanup(&id);
+            HttpResponse::Ok().json(CommandResponse::Failed(CommandFailed {}))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListTasksQuery {
+    /// Only list tasks with this label, as `key:value` or `key`
+    label: Option<String>,
+}
+
+impl ListTasksQuery {
+    fn filter(&self, tasks: Vec<ListedTask>) -> Vec<ListedTask> {
+        match &self.label {
+            Some(label) => tasks
+                .into_iter()
+                .filter(|listed| listed.task.has_label(label))
+                .collect(),
+            None => tasks,
+        }
     }
 }
 
 #[get("/api/commands/list/queued")]
-async fn list_queued_tasks(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
-    web::Json(app.queue.queued())
+async fn list_queued_tasks(
+    app: web::Data<Arc<CommandQApp>>,
+    query: web::Query<ListTasksQuery>,
+) -> impl Responder {
+    web::Json(query.filter(app.queued_tasks()))
 }
 
 #[get("/api/commands/list/running")]
-async fn list_running_tasks(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
-    web::Json(app.queue.running())
+async fn list_running_tasks(
+    app: web::Data<Arc<CommandQApp>>,
+    query: web::Query<ListTasksQuery>,
+) -> impl Responder {
+    web::Json(query.filter(app.running_tasks()))
+}
+
+/// The task with every attempt and its current status
+#[get("/api/commands/{id}")]
+async fn get_task(app: web::Data<Arc<CommandQApp>>, id: web::Path<String>) -> impl Responder {
+    match app.task_detail(&id) {
+        Some(detail) => HttpResponse::Ok().json(detail),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Removes the task from the queue, only queued tasks can be cancelled
+#[post("/api/commands/{id}/cancel")]
+async fn cancel_task(app: web::Data<Arc<CommandQApp>>, id: web::Path<String>) -> impl Responder {
+    match app.queue.get(&id) {
+        Some((task, TaskState::Queued)) => match app.cancel_task(task) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(err) => {
+                println!("Error cancelling task {} {}", id, err);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Some((_, TaskState::Running)) => HttpResponse::Conflict().finish(),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Moves the task to the front of the queue, only queued tasks can be bumped
+#[post("/api/commands/{id}/bump")]
+async fn bump_task(app: web::Data<Arc<CommandQApp>>, id: web::Path<String>) -> impl Responder {
+    match app.queue.get(&id) {
+        Some((_, TaskState::Queued)) => match app.queue.bump(&id) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(err) => {
+                println!("Error bumping task {} {}", id, err);
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+        Some((_, TaskState::Running)) => HttpResponse::Conflict().finish(),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/api/history")]
+async fn list_history(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
+    let entries = app
+        .history
+        .entries()
+        .into_iter()
+        .map(|entry| HistoryEntry {
+            task: entry.task.masked(),
+            ..entry
+        })
+        .collect::<Vec<_>>();
+    web::Json(entries)
+}
+
+/// The entry with the captured environment of the task masked, see `resubmit_history_entry`
+#[get("/api/history/{id}")]
+async fn get_history_entry(
+    app: web::Data<Arc<CommandQApp>>,
+    id: web::Path<String>,
+) -> impl Responder {
+    match app.history.get(&id) {
+        Some(entry) => HttpResponse::Ok().json(HistoryEntry {
+            task: entry.task.masked(),
+            ..entry
+        }),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Queues the command of the finished task again, with the environment it captured which is
+/// never sent to clients
+#[post("/api/history/{id}/resubmit")]
+async fn resubmit_history_entry(
+    app: web::Data<Arc<CommandQApp>>,
+    id: web::Path<String>,
+    resubmit: web::Json<ResubmitRequest>,
+    query: web::Query<QueueCommandQuery>,
+    req: HttpRequest,
+) -> impl Responder {
+    let entry = match app.history.get(&id) {
+        Some(entry) => entry,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let resubmit = resubmit.into_inner();
+    let mut command = entry.task.command;
+    for arg in command.args.iter_mut() {
+        for (old, new) in &resubmit.arg_replace {
+            *arg = arg.replace(old, new);
+        }
+    }
+    command.resubmitted_from = Some(id.to_string());
+    // The key of the finished task would return it rather than queueing it again
+    command.idempotency_key = resubmit.idempotency_key;
+    println!("resubmit command {:?}", command);
+    queue_checked(&app, command, query.force, &req)
 }
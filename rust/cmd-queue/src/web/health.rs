@@ -0,0 +1,49 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::{execution::scheduler::SCHEDULER_INTERVAL, CommandQApp};
+
+/// Runs of the scheduler loop that can be missed before the server isn't ready
+const MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    /// Error writing the queue to its db file, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_store_error: Option<String>,
+    /// Seconds since the scheduler loop last ran
+    scheduler_heartbeat_age_secs: u64,
+}
+
+/// The server is up
+#[get("/health/live")]
+async fn live() -> impl Responder {
+    "UP"
+}
+
+/// The server can queue and run tasks: the queue can be written to its db file and the
+/// scheduler loop ran recently
+#[get("/health/ready")]
+async fn ready(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
+    let queue_store_error = app.queue.check_writable().err().map(|e| e.to_string());
+    let heartbeat_age = app
+        .task_scheduler
+        .last_heartbeat()
+        .elapsed()
+        .unwrap_or(Duration::ZERO);
+    let heartbeat_fresh = heartbeat_age <= SCHEDULER_INTERVAL * MISSED_HEARTBEATS;
+
+    let readiness = Readiness {
+        ready: queue_store_error.is_none() && heartbeat_fresh,
+        queue_store_error,
+        scheduler_heartbeat_age_secs: heartbeat_age.as_secs(),
+    };
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
@@ -1,10 +1,28 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::SystemTime};
 
-use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
 use askama::Template;
 use serde::{Deserialize, Serialize};
 
-use crate::{CommandFailed, CommandQApp, CommandRequest, CommandResponse, CommandSuccess, Task};
+use crate::{
+    disk, execution::outcome::Attempt, history::HistoryEntry, web::api::client_addr, ytdlp_args,
+    CommandFailed, CommandQApp, CommandRequest, CommandResponse, CommandSuccess, ListedTask,
+    TaskState, TaskStatus,
+};
+
+/// Number of history entries per page of the history page
+const HISTORY_PAGE_SIZE: usize = 50;
+
+/// How long ago `time` was, as shown in the pages
+fn format_ago(time: SystemTime) -> String {
+    time.elapsed()
+        .map(|elapsed| {
+            // Sub-second precision only adds noise
+            let elapsed = std::time::Duration::from_secs(elapsed.as_secs());
+            format!("{} ago", humantime::format_duration(elapsed))
+        })
+        .unwrap_or("just now".to_string())
+}
 
 #[derive(Template)]
 #[template(path = "index.html")]
@@ -17,24 +35,46 @@ struct TaskTemplateObject {
     id: String,
     path: String,
     process: String,
+    labels: String,
+    env: String,
     tries: usize,
     last_attempt: String,
+    last_outcome: String,
+    position: String,
+    eta: String,
+    progress: String,
+    resubmitted_from: String,
 }
 
-impl From<Task> for TaskTemplateObject {
-    fn from(task: Task) -> Self {
+impl From<ListedTask> for TaskTemplateObject {
+    fn from(listed: ListedTask) -> Self {
+        let eta = listed.format_eta();
+        let labels = listed.task.format_labels();
+        let env = listed.task.format_env();
+        let last_outcome = listed.task.format_last_outcome();
+        let task = listed.task;
         TaskTemplateObject {
             id: task.id,
             path: task.command.path,
             process: format!("{} {:?}", task.command.program, task.command.args),
+            labels: labels,
+            env: env,
             tries: task.tries,
             last_attempt: task
                 .last_attempt
-                .and_then(|last_attempt| last_attempt.elapsed().ok())
-                .map(|last_attempt_elapsed| {
-                    format!("{} ago", humantime::format_duration(last_attempt_elapsed))
-                })
+                .map(format_ago)
                 .unwrap_or("None".to_string()),
+            last_outcome: last_outcome,
+            position: listed
+                .position
+                .map(|position| position.to_string())
+                .unwrap_or("-".to_string()),
+            eta: eta,
+            progress: task
+                .progress
+                .map(|progress| progress.to_string())
+                .unwrap_or("-".to_string()),
+            resubmitted_from: task.command.resubmitted_from.unwrap_or("-".to_string()),
         }
     }
 }
@@ -42,8 +82,8 @@ impl From<Task> for TaskTemplateObject {
 // TODO implement a more generic template to html trait https://github.com/djc/askama/blob/main/askama_actix/src/lib.rs#L33
 #[get("/")]
 async fn index(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
-    let queued_tasks = app.queue.queued().into_iter().map(|t| t.into()).collect();
-    let running_tasks = app.queue.running().into_iter().map(|t| t.into()).collect();
+    let queued_tasks = app.queued_tasks().into_iter().map(|t| t.into()).collect();
+    let running_tasks = app.running_tasks().into_iter().map(|t| t.into()).collect();
     let html_body = Index {
         queued_tasks,
         running_tasks,
@@ -53,6 +93,316 @@ async fn index(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
     HttpResponse::Ok().content_type("text/html").body(html_body)
 }
 
+#[derive(Template)]
+#[template(path = "task.html")]
+struct TaskPage {
+    id: String,
+    status: String,
+    path: String,
+    process: String,
+    labels: String,
+    env: String,
+    submitted_by: String,
+    resubmitted_from: String,
+    tries: usize,
+    attempts: Vec<AttemptTemplateObject>,
+    /// End of the stderr of the last attempt
+    output_tail: String,
+    /// Only queued tasks can be cancelled
+    can_cancel: bool,
+    /// Only finished tasks can be retried
+    can_retry: bool,
+}
+
+struct AttemptTemplateObject {
+    started_at: String,
+    duration: String,
+    outcome: String,
+}
+
+impl From<&Attempt> for AttemptTemplateObject {
+    fn from(attempt: &Attempt) -> Self {
+        AttemptTemplateObject {
+            started_at: format_ago(attempt.started_at),
+            duration: humantime::format_duration(attempt.duration).to_string(),
+            outcome: attempt.outcome.to_string(),
+        }
+    }
+}
+
+/// Result of an action on the task page, as an htmx fragment replacing its buttons
+#[derive(Template)]
+#[template(path = "task_action.html")]
+struct TaskActionResult {
+    message: String,
+    /// Task to link to, the one queued by a retry
+    task_id: Option<String>,
+}
+
+impl TaskActionResult {
+    fn message(message: impl Into<String>) -> Self {
+        TaskActionResult {
+            message: message.into(),
+            task_id: None,
+        }
+    }
+
+    fn respond(self) -> HttpResponse {
+        let html_body = self.render().unwrap();
+        HttpResponse::Ok().content_type("text/html").body(html_body)
+    }
+}
+
+#[get("/tasks/{id}")]
+async fn task_page(app: web::Data<Arc<CommandQApp>>, id: web::Path<String>) -> impl Responder {
+    let detail = match app.task_detail(&id) {
+        Some(detail) => detail,
+        None => return HttpResponse::NotFound().body(format!("Task {} not found", id)),
+    };
+    let task = detail.task;
+    let html_body = TaskPage {
+        status: detail.status.to_string(),
+        path: task.command.path.clone(),
+        process: format!("{} {:?}", task.command.program, task.command.args),
+        labels: task.format_labels(),
+        env: task.format_env(),
+        submitted_by: task.submitted_by.clone().unwrap_or("-".to_string()),
+        resubmitted_from: task
+            .command
+            .resubmitted_from
+            .clone()
+            .unwrap_or("-".to_string()),
+        tries: task.tries,
+        attempts: task.attempts.iter().map(|attempt| attempt.into()).collect(),
+        output_tail: task
+            .attempts
+            .last()
+            .map(|attempt| attempt.stderr.clone())
+            .unwrap_or_default(),
+        can_cancel: matches!(detail.status, TaskStatus::Queued),
+        can_retry: matches!(detail.status, TaskStatus::Finished(_)),
+        id: task.id,
+    }
+    .render()
+    .unwrap();
+    HttpResponse::Ok().content_type("text/html").body(html_body)
+}
+
+/// Cancels the queued task from its page
+#[post("/html/tasks/{id}/cancel")]
+async fn html_cancel_task(
+    app: web::Data<Arc<CommandQApp>>,
+    id: web::Path<String>,
+) -> impl Responder {
+    let result = match app.queue.get(&id) {
+        Some((task, TaskState::Queued)) => match app.cancel_task(task) {
+            Ok(()) => TaskActionResult::message("cancelled"),
+            Err(err) => TaskActionResult::message(format!("failed to cancel {}", err)),
+        },
+        Some((_, TaskState::Running)) => {
+            TaskActionResult::message("already running, only queued tasks can be cancelled")
+        }
+        None => TaskActionResult::message("not queued anymore"),
+    };
+    result.respond()
+}
+
+/// Queues the command of the finished task again from its page, like `cmdq resubmit`
+#[post("/html/tasks/{id}/retry")]
+async fn html_retry_task(
+    app: web::Data<Arc<CommandQApp>>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    if app.queue.get(&id).is_some() {
+        return TaskActionResult::message("not finished, only finished tasks can be retried")
+            .respond();
+    }
+    let entry = match app.history.get(&id) {
+        Some(entry) => entry,
+        None => return TaskActionResult::message("not found in history").respond(),
+    };
+    let client = client_addr(&req);
+    let mut command = entry.task.command;
+    command.resubmitted_from = Some(id.to_string());
+    command.idempotency_key = None;
+    let checked = app
+        .config
+        .check_program(&command.program)
+        .and_then(|_| app.check_limits(&command, client.as_deref()));
+    let result = match checked {
+        Err(rejected) => TaskActionResult::message(rejected.to_string()),
+        Ok(()) => match app.queue.push_cmd(&command, client) {
+            Ok(task) => TaskActionResult {
+                message: "queued as".to_string(),
+                task_id: Some(task.id),
+            },
+            Err(err) => TaskActionResult::message(format!("failed to queue {}", err)),
+        },
+    };
+    result.respond()
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryPage {
+    entries: Vec<HistoryTemplateObject>,
+    /// 1-based
+    page: usize,
+    pages: usize,
+    prev_page: Option<usize>,
+    next_page: Option<usize>,
+}
+
+struct HistoryTemplateObject {
+    id: String,
+    path: String,
+    process: String,
+    labels: String,
+    result: String,
+    started_at: String,
+    duration: String,
+}
+
+impl From<HistoryEntry> for HistoryTemplateObject {
+    fn from(entry: HistoryEntry) -> Self {
+        let labels = entry.task.format_labels();
+        let task = entry.task;
+        HistoryTemplateObject {
+            id: task.id,
+            path: task.command.path,
+            process: format!("{} {:?}", task.command.program, task.command.args),
+            labels,
+            result: format!("{:?}", entry.result),
+            started_at: format_ago(entry.started_at),
+            duration: humantime::format_duration(entry.duration).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// 1-based page of the history, most recent entries first
+    page: Option<usize>,
+}
+
+#[get("/history")]
+async fn history_page(
+    app: web::Data<Arc<CommandQApp>>,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let entries = app.history.entries();
+    let pages = entries.len().div_ceil(HISTORY_PAGE_SIZE).max(1);
+    let page = query.page.unwrap_or(1).clamp(1, pages);
+    let html_body = HistoryPage {
+        entries: entries
+            .into_iter()
+            .skip((page - 1) * HISTORY_PAGE_SIZE)
+            .take(HISTORY_PAGE_SIZE)
+            .map(|entry| entry.into())
+            .collect(),
+        page,
+        pages,
+        prev_page: Some(page - 1).filter(|prev_page| *prev_page >= 1),
+        next_page: Some(page + 1).filter(|next_page| *next_page <= pages),
+    }
+    .render()
+    .unwrap();
+    HttpResponse::Ok().content_type("text/html").body(html_body)
+}
+
+#[derive(Template)]
+#[template(path = "quick_add.html")]
+struct QuickAdd {
+    download_dir: String,
+}
+
+#[derive(Template)]
+#[template(path = "quick_add_results.html")]
+struct QuickAddResults {
+    results: Vec<QuickAddResult>,
+}
+
+struct QuickAddResult {
+    url: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuickAddForm {
+    /// yt-dlp urls, one per line
+    urls: String,
+    prefix: String,
+    path: String,
+}
+
+#[get("/quick-add")]
+async fn quick_add(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
+    let html_body = QuickAdd {
+        download_dir: app.config.download_dir(),
+    }
+    .render()
+    .unwrap();
+    HttpResponse::Ok().content_type("text/html").body(html_body)
+}
+
+/// Queues a yt-dlp task per url of the quick add form, responding with the htmx fragment of
+/// the result of each
+#[post("/html/quick-add")]
+async fn quick_add_urls(
+    app: web::Data<Arc<CommandQApp>>,
+    form: web::Form<QuickAddForm>,
+    req: HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let client = client_addr(&req);
+    let path = match form.path.trim() {
+        "" => app.config.download_dir(),
+        path => path.to_string(),
+    };
+    let prefix = Some(form.prefix.trim()).filter(|prefix| !prefix.is_empty());
+    let warning = if app.config.ssh_host.is_none() {
+        disk::check_free_space(&path)
+    } else {
+        None
+    };
+
+    let results = form
+        .urls
+        .lines()
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            let command = CommandRequest {
+                path: path.clone(),
+                program: "yt-dlp".to_string(),
+                args: ytdlp_args(url.to_string(), prefix),
+                ..Default::default()
+            };
+            let checked = app
+                .config
+                .check_program(&command.program)
+                .and_then(|_| app.check_limits(&command, client.as_deref()));
+            let message = match checked {
+                Err(rejected) => rejected.to_string(),
+                Ok(()) => match app.queue.push_cmd(&command, client.clone()) {
+                    Ok(task) => match &warning {
+                        Some(warning) => format!("queued as {}. {}", task.id(), warning),
+                        None => format!("queued as {}", task.id()),
+                    },
+                    Err(err) => format!("failed to queue {}", err),
+                },
+            };
+            QuickAddResult {
+                url: url.to_string(),
+                message,
+            }
+        })
+        .collect();
+    let html_body = QuickAddResults { results }.render().unwrap();
+    HttpResponse::Ok().content_type("text/html").body(html_body)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum HtmlFormCommandRequest {
     Ytdlp {
@@ -77,8 +427,8 @@ async fn html_queue_command(
     }
 
     // TODO replace with more specific component
-    let queued_tasks = app.queue.queued().into_iter().map(|t| t.into()).collect();
-    let running_tasks = app.queue.running().into_iter().map(|t| t.into()).collect();
+    let queued_tasks = app.queued_tasks().into_iter().map(|t| t.into()).collect();
+    let running_tasks = app.running_tasks().into_iter().map(|t| t.into()).collect();
     let html_body = Index {
         queued_tasks,
         running_tasks,
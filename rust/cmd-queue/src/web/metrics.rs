@@ -0,0 +1,84 @@
+use std::{fmt::Write, sync::Arc};
+
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::CommandQApp;
+
+/// Queue counts and limits in the Prometheus text format
+#[get("/metrics")]
+async fn metrics(app: web::Data<Arc<CommandQApp>>) -> impl Responder {
+    let counts = app.counts();
+    let config = &app.config;
+    let mut body = String::new();
+
+    gauge(&mut body, "cmdq_queued_tasks", "Tasks waiting to run");
+    writeln!(body, "cmdq_queued_tasks {}", counts.queued).unwrap();
+    gauge(&mut body, "cmdq_running_tasks", "Tasks running");
+    writeln!(body, "cmdq_running_tasks {}", counts.running).unwrap();
+
+    gauge(
+        &mut body,
+        "cmdq_pending_tasks_by_client",
+        "Queued and running tasks by address of the client that queued them",
+    );
+    for (client, count) in &counts.pending_by_client {
+        writeln!(
+            body,
+            "cmdq_pending_tasks_by_client{{client=\"{}\"}} {}",
+            escape(client),
+            count
+        )
+        .unwrap();
+    }
+    gauge(
+        &mut body,
+        "cmdq_pending_tasks_by_label",
+        "Queued and running tasks by label",
+    );
+    for (label, count) in &counts.pending_by_label {
+        writeln!(
+            body,
+            "cmdq_pending_tasks_by_label{{label=\"{}\"}} {}",
+            escape(label),
+            count
+        )
+        .unwrap();
+    }
+
+    let limits = [
+        ("queue", config.max_queue_len),
+        ("per_client", config.max_pending_per_client),
+        ("per_label", config.max_pending_per_label),
+    ];
+    gauge(
+        &mut body,
+        "cmdq_pending_tasks_limit",
+        "Most tasks that can be queued or running at once",
+    );
+    for (limit, max) in limits.iter() {
+        if let Some(max) = max {
+            writeln!(
+                body,
+                "cmdq_pending_tasks_limit{{limit=\"{}\"}} {}",
+                limit, max
+            )
+            .unwrap();
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+fn gauge(body: &mut String, name: &str, help: &str) {
+    writeln!(body, "# HELP {} {}", name, help).unwrap();
+    writeln!(body, "# TYPE {} gauge", name).unwrap();
+}
+
+fn escape(label_value: &str) -> String {
+    label_value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
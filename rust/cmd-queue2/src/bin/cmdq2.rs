@@ -1,14 +1,121 @@
 use clap::{Parser, Subcommand};
-use cmd_queue2::error::CmdqError;
-use std::path::PathBuf;
+use cmd_queue2::{
+    config::Config,
+    error::CmdqError,
+    fallback::Fallbacks,
+    interrupt,
+    notify::{self, NotifyOptions},
+    verify, ytdlp, RunOptions,
+};
+use std::{path::PathBuf, process, time::Duration};
 
 fn main() -> Result<(), CmdqError> {
     tracing_subscriber::fmt::init();
 
     let cli_args = CliArgs::parse();
+    let config = Config::load(cli_args.config.as_deref())?;
 
     match cli_args.commands {
-        CliSubCommands::Ytdlp { filepath } => cmd_queue2::run_ytdlp_file(PathBuf::from(filepath))?,
+        CliSubCommands::Ytdlp {
+            filepath,
+            target_dir,
+            concurrency,
+            ytdlp_path,
+            rate_limit,
+            proxy,
+            preset,
+            record_timeout,
+            total_budget,
+            write_subs,
+            sub_langs,
+            split_chapters,
+            embed_thumbnail,
+            env,
+            cookies_dir,
+            notify,
+            webhook_url,
+            estimate,
+            no_fallbacks,
+        } => {
+            let extra_args = match preset.as_ref().or(config.default_preset.as_ref()) {
+                Some(name) => config.preset(name)?.args.clone(),
+                None => Vec::new(),
+            };
+            let env = env
+                .iter()
+                .map(|var| {
+                    var.split_once('=')
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .ok_or_else(|| CmdqError::InvalidEnvError {
+                            var: var.to_string(),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut run_env = config.env;
+            run_env.extend(env);
+            let options = ytdlp::Options {
+                ytdlp_path: ytdlp_path
+                    .or(config.ytdlp_path)
+                    .unwrap_or_else(|| "yt-dlp".to_string()),
+                target_dir: target_dir.or(config.target_dir),
+                rate_limit: rate_limit.or(config.rate_limit),
+                proxy: proxy.or(config.proxy),
+                extra_args,
+                record_timeout,
+                write_subs,
+                sub_langs,
+                split_chapters,
+                embed_thumbnail,
+                env: run_env,
+                cookies_dir: cookies_dir.or(config.cookies_dir),
+                fallbacks: if no_fallbacks {
+                    Fallbacks::default()
+                } else {
+                    config.fallbacks
+                },
+            };
+            let run_options = RunOptions {
+                concurrency: concurrency.or(config.concurrency).unwrap_or(1),
+                total_budget,
+                estimate,
+            };
+            let notify_options = NotifyOptions {
+                desktop: notify || config.notify,
+                webhook_url: webhook_url.or(config.webhook_url),
+            };
+            interrupt::install()?;
+            let summary =
+                cmd_queue2::run_ytdlp_file(PathBuf::from(filepath), &options, &run_options)?;
+            notify::notify(&summary, &notify_options);
+            if summary.interrupted {
+                process::exit(interrupt::INTERRUPTED_EXIT_CODE);
+            }
+        }
+        CliSubCommands::Verify {
+            dir,
+            filepath,
+            redownload,
+        } => {
+            let statuses = verify::verify(&dir, &filepath)?;
+            for (record, status) in &statuses {
+                println!("{}\t{}", record.url, status);
+            }
+            let to_redownload = statuses
+                .iter()
+                .filter(|(_, status)| status.needs_redownload())
+                .map(|(record, _)| record)
+                .collect::<Vec<_>>();
+            println!(
+                "{} of {} records downloaded",
+                statuses.len() - to_redownload.len(),
+                statuses.len()
+            );
+            if let Some(redownload) = redownload {
+                if !to_redownload.is_empty() {
+                    verify::write_redownload(to_redownload.into_iter(), &redownload)?;
+                }
+            }
+        }
     }
     Ok(())
 }
@@ -17,11 +124,82 @@ fn main() -> Result<(), CmdqError> {
 #[command(name = "cmdq")]
 #[command(about = "A program to queue commands", long_about = None)]
 struct CliArgs {
+    /// Config file, defaults to ~/.config/cmdq2/config.toml
+    #[arg(long, short, global = true)]
+    config: Option<PathBuf>,
+
     #[command(subcommand)]
     commands: CliSubCommands,
 }
 
 #[derive(Debug, Subcommand)]
 enum CliSubCommands {
-    Ytdlp { filepath: String },
+    Ytdlp {
+        /// CSV file of the records to download, or - to read them from stdin as a CSV or as one
+        /// URL per line
+        filepath: String,
+        /// Directory to download into, defaults to the directory of FILEPATH
+        #[arg(long)]
+        target_dir: Option<PathBuf>,
+        /// Number of records downloaded at the same time
+        #[arg(long, short = 'j')]
+        concurrency: Option<usize>,
+        /// Path of the yt-dlp executable
+        #[arg(long)]
+        ytdlp_path: Option<String>,
+        /// Maximum download rate of each record, e.g. 2M
+        #[arg(long)]
+        rate_limit: Option<String>,
+        /// Proxy to download through, e.g. socks5://127.0.0.1:1080
+        #[arg(long)]
+        proxy: Option<String>,
+        /// Preset of extra yt-dlp args from the config
+        #[arg(long, short)]
+        preset: Option<String>,
+        /// Kill yt-dlp and fail the record once it ran for longer than this, e.g. 30m
+        #[arg(long, value_parser = humantime::parse_duration)]
+        record_timeout: Option<Duration>,
+        /// Stop starting records after this, writing the ones left to a remaining CSV, e.g. 6h
+        #[arg(long, value_parser = humantime::parse_duration)]
+        total_budget: Option<Duration>,
+        /// Write the subtitles of the videos, passed to yt-dlp
+        #[arg(long)]
+        write_subs: bool,
+        /// Languages of the subtitles to write, e.g. en.*,fr, passed to yt-dlp
+        #[arg(long)]
+        sub_langs: Option<String>,
+        /// Split the videos into a file per chapter, passed to yt-dlp
+        #[arg(long)]
+        split_chapters: bool,
+        /// Embed the thumbnail in the video files, passed to yt-dlp
+        #[arg(long)]
+        embed_thumbnail: bool,
+        /// Environment variable of every yt-dlp process, as KEY=VALUE
+        #[arg(long)]
+        env: Vec<String>,
+        /// Directory of the cookie jars named in the cookies column of the records
+        #[arg(long)]
+        cookies_dir: Option<PathBuf>,
+        /// Show a desktop notification when the run finishes
+        #[arg(long)]
+        notify: bool,
+        /// POST the summary of the run as JSON to this URL when it finishes
+        #[arg(long)]
+        webhook_url: Option<String>,
+        /// Estimate the size of the downloads first, aborting if there isn't enough free space
+        #[arg(long)]
+        estimate: bool,
+        /// Don't retry records failing because of a geo or age restriction with the fallbacks of
+        /// the config
+        #[arg(long)]
+        no_fallbacks: bool,
+    },
+    /// Check which records of FILEPATH were downloaded to DIR, matching the [id] in filenames
+    Verify {
+        dir: PathBuf,
+        filepath: PathBuf,
+        /// Write the records missing or with an empty file to this CSV, to download them again
+        #[arg(long)]
+        redownload: Option<PathBuf>,
+    },
 }
@@ -0,0 +1,126 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::CmdqError, fallback::Fallbacks};
+
+/// Defaults of cmdq2, read from `~/.config/cmdq2/config.toml`. Flags given on the command line
+/// take precedence over them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Directory records are downloaded into, instead of the directory of the input file
+    pub target_dir: Option<PathBuf>,
+    /// Number of records downloaded at the same time
+    pub concurrency: Option<usize>,
+    /// Path of the yt-dlp executable
+    pub ytdlp_path: Option<String>,
+    /// Maximum download rate of each yt-dlp process, e.g. `2M`
+    pub rate_limit: Option<String>,
+    /// Proxy passed to yt-dlp, e.g. `socks5://127.0.0.1:1080`
+    pub proxy: Option<String>,
+    /// Directory of the cookie jars named in the cookies column of records
+    pub cookies_dir: Option<PathBuf>,
+    /// Environment variables of every yt-dlp process, overridden by --env
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Show a desktop notification when a run finishes
+    #[serde(default)]
+    pub notify: bool,
+    /// URL the summary of finished runs is POSTed to as JSON
+    pub webhook_url: Option<String>,
+    /// Preset used when none is given with --preset
+    pub default_preset: Option<String>,
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    /// Args to retry records with when yt-dlp fails because of a geo or age restriction
+    #[serde(default)]
+    pub fallbacks: Fallbacks,
+}
+
+/// Named set of extra yt-dlp args, e.g. to only keep the audio
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub args: Vec<String>,
+}
+
+impl Config {
+    /// Loads the config at `path`, or at the default path if there is a file there
+    pub fn load(path: Option<&Path>) -> Result<Config, CmdqError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let content = fs::read_to_string(&path).map_err(|err| CmdqError::ConfigReadError {
+            source: err,
+            filepath: path.clone(),
+        })?;
+        toml::from_str(&content).map_err(|err| CmdqError::ConfigParseError {
+            source: err,
+            filepath: path,
+        })
+    }
+
+    pub fn preset(&self, name: &str) -> Result<&Preset, CmdqError> {
+        self.presets
+            .get(name)
+            .ok_or_else(|| CmdqError::UnknownPresetError {
+                name: name.to_string(),
+            })
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("cmdq2").join("config.toml"))
+}
+
+#[test]
+fn test_parse_fallbacks() {
+    let config: Config = toml::from_str(
+        r#"
+[[fallbacks.geo]]
+args = ["--proxy", "socks5://127.0.0.1:1080"]
+
+[[fallbacks.age]]
+args = ["--cookies-from-browser", "firefox"]
+
+[[fallbacks.age]]
+args = ["--extractor-args", "youtube:player_client=tv_embedded"]
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.fallbacks.geo.len(), 1);
+    assert_eq!(
+        config.fallbacks.geo[0].args,
+        ["--proxy", "socks5://127.0.0.1:1080"]
+    );
+    let age = config
+        .fallbacks
+        .age
+        .iter()
+        .map(|fallback| fallback.args.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        age,
+        [
+            ["--cookies-from-browser", "firefox"],
+            ["--extractor-args", "youtube:player_client=tv_embedded"]
+        ]
+    );
+
+    let config: Config = toml::from_str("").unwrap();
+    assert!(config.fallbacks.geo.is_empty() && config.fallbacks.age.is_empty());
+
+    assert!(toml::from_str::<Config>("[[fallbacks.network]]\nargs = []\n").is_err());
+    assert!(toml::from_str::<Config>("[[fallbacks.geo]]\nargz = []\n").is_err());
+}
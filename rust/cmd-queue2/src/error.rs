@@ -1,7 +1,9 @@
-use std::{io, path::PathBuf};
+use std::{io, path::PathBuf, time::Duration};
 
 use thiserror::Error;
 
+use crate::fallback::ErrorClass;
+
 #[derive(Error, Debug)]
 pub enum CmdqError {
     #[error("Error executing `{program}` with args {}", .args.join(" "))]
@@ -17,6 +19,9 @@ pub enum CmdqError {
     #[error("Process execution completed with error: {} {}", .stdout, .stderr)]
     ProcessExecuteOutputError { stdout: String, stderr: String },
 
+    #[error("Process killed after running for longer than {}", humantime::format_duration(*.timeout))]
+    RecordTimeoutError { timeout: Duration },
+
     #[error("Stdout or stderr cannot be processed as text")]
     ProcessExecuteOutputNotUtf8Error,
 
@@ -26,6 +31,9 @@ pub enum CmdqError {
         filepath: PathBuf,
     },
 
+    #[error("Could not read records from stdin: {}", source)]
+    StdinReadError { source: io::Error },
+
     #[error("Could not deserialize record with error = `{}`", source)]
     CsvDeserializeError { source: csv::Error },
 
@@ -58,4 +66,72 @@ pub enum CmdqError {
         source
     )]
     GetTargetDirFromCurrentDirError { source: io::Error },
+
+    #[error("Could not read config `{}`: {}", filepath.display(), source)]
+    ConfigReadError {
+        source: io::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("Invalid config `{}`: {}", filepath.display(), source)]
+    ConfigParseError {
+        source: toml::de::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("No preset named `{name}` in the config")]
+    UnknownPresetError { name: String },
+
+    #[error("Could not create remaining records file `{}`: {}", filepath.display(), source)]
+    CreateRemainingFileError {
+        source: io::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("Could not write record to remaining records file `{}`: {}", filepath.display(), source)]
+    WriteToRemainingFileError {
+        source: csv::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("Could not write remaining records file `{}`: {}", filepath.display(), source)]
+    WriteRemainingFileError {
+        source: io::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("Could not read download directory `{}`: {}", dir.display(), source)]
+    ReadDownloadDirError { source: io::Error, dir: PathBuf },
+
+    #[error("Could not write re-download file `{}`: {}", filepath.display(), source)]
+    WriteRedownloadFileError {
+        source: csv::Error,
+        filepath: PathBuf,
+    },
+
+    #[error("Invalid environment variable `{var}`, expected KEY=VALUE")]
+    InvalidEnvError { var: String },
+
+    #[error("Record uses cookies `{name}` but no cookies dir was given")]
+    NoCookiesDirError { name: String },
+
+    #[error("No cookies `{name}` in cookies dir `{}`", dir.display())]
+    CookiesNotFoundError { name: String, dir: PathBuf },
+
+    #[error("Could not install Ctrl-C handler: {}", source)]
+    InstallInterruptHandlerError { source: ctrlc::Error },
+
+    #[error("Not enough space for the downloads into `{}`, {needed} needed but {available} free", dir.display())]
+    NotEnoughSpaceError {
+        dir: PathBuf,
+        needed: String,
+        available: String,
+    },
+
+    #[error("Video is {class} and every fallback failed ({tried} tried), last with: {source}")]
+    FallbacksFailedError {
+        class: ErrorClass,
+        tried: usize,
+        source: Box<CmdqError>,
+    },
 }
@@ -0,0 +1,120 @@
+use nix::sys::statvfs::statvfs;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+use tracing::{event, Level};
+
+use crate::{error::CmdqError, ytdlp};
+
+/// Number of yt-dlp processes querying sizes at the same time
+pub const ESTIMATE_PARALLELISM: usize = 8;
+
+/// Approximate sizes of the downloads of the records of a run
+#[derive(Debug, Default)]
+pub struct Estimate {
+    pub total_bytes: u64,
+    /// Bytes downloaded into each target dir
+    pub per_dir: BTreeMap<PathBuf, u64>,
+    /// Records whose size yt-dlp doesn't know or failed to query, not counted in the total
+    pub unknown: usize,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (dir, bytes) in &self.per_dir {
+            writeln!(f, "{:>10}  {}", format_bytes(*bytes), dir.display())?;
+        }
+        write!(f, "{:>10}  total", format_bytes(self.total_bytes))?;
+        if self.unknown > 0 {
+            write!(f, ", {} records of unknown size", self.unknown)?;
+        }
+        Ok(())
+    }
+}
+
+/// Queries the approximate size of every record with yt-dlp
+pub fn estimate(
+    filepath: &Path,
+    records: &[ytdlp::Record],
+    options: &ytdlp::Options,
+) -> Result<Estimate, CmdqError> {
+    let targets = records
+        .iter()
+        .map(|record| ytdlp::record_target_dir(filepath, record, options).map(|dir| (record, dir)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let targets = Mutex::new(targets.into_iter());
+    let estimate = Mutex::new(Estimate::default());
+    thread::scope(|s| {
+        for _ in 0..ESTIMATE_PARALLELISM {
+            s.spawn(|| loop {
+                let (record, dir) = match targets.lock().unwrap().next() {
+                    Some(target) => target,
+                    None => break,
+                };
+                let size = ytdlp::estimate_size(filepath, record, options).unwrap_or_else(|err| {
+                    event!(
+                        Level::WARN,
+                        message = "could not estimate size",
+                        url = record.url,
+                        ?err
+                    );
+                    None
+                });
+
+                let mut estimate = estimate.lock().unwrap();
+                match size {
+                    Some(bytes) => {
+                        estimate.total_bytes += bytes;
+                        *estimate.per_dir.entry(dir).or_default() += bytes;
+                    }
+                    None => estimate.unknown += 1,
+                }
+            });
+        }
+    });
+    Ok(estimate.into_inner().unwrap())
+}
+
+/// Fails if a filesystem doesn't have enough free space for the downloads into its dirs. Dirs
+/// that can't be checked are skipped, their downloads will fail on their own.
+pub fn check_free_space(estimate: &Estimate) -> Result<(), CmdqError> {
+    // Bytes needed on each filesystem, along with a dir on it to report
+    let mut per_filesystem: BTreeMap<u64, (PathBuf, u64, u64)> = BTreeMap::new();
+    for (dir, bytes) in &estimate.per_dir {
+        // Dirs of records may not have been created yet
+        let existing = match dir.ancestors().find(|dir| dir.exists()) {
+            Some(existing) => existing,
+            None => continue,
+        };
+        let stat = match statvfs(existing) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        let available_bytes = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+        let needed = per_filesystem
+            .entry(stat.filesystem_id() as u64)
+            .or_insert_with(|| (dir.clone(), 0, available_bytes));
+        needed.1 += bytes;
+    }
+
+    for (_, (dir, needed_bytes, available_bytes)) in per_filesystem {
+        if needed_bytes > available_bytes {
+            return Err(CmdqError::NotEnoughSpaceError {
+                dir,
+                needed: format_bytes(needed_bytes),
+                available: format_bytes(available_bytes),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let gib = |bytes: u64| bytes as f64 / (1024 * 1024 * 1024) as f64;
+    format!("{:.1} GiB", gib(bytes))
+}
@@ -0,0 +1,160 @@
+use serde::Deserialize;
+use std::fmt;
+
+use crate::error::CmdqError;
+
+/// Messages of yt-dlp failing because the video isn't available in the region of the machine
+const GEO_MESSAGES: [&str; 6] = [
+    "not available in your country",
+    "not made this video available in your country",
+    "not available from your location",
+    "not available in your region",
+    "geo restricted",
+    "geo-restricted",
+];
+
+/// Messages of yt-dlp failing because the video requires confirming one's age
+const AGE_MESSAGES: [&str; 4] = [
+    "confirm your age",
+    "age restricted",
+    "age-restricted",
+    "inappropriate for some users",
+];
+
+/// Kind of failure of yt-dlp that other args can work around
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Geo,
+    Age,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorClass::Geo => write!(f, "geo-restricted"),
+            ErrorClass::Age => write!(f, "age-restricted"),
+        }
+    }
+}
+
+impl ErrorClass {
+    /// The class of the failure of yt-dlp from what it wrote to stderr, None if it isn't one
+    /// that fallbacks can work around
+    pub fn of(err: &CmdqError) -> Option<ErrorClass> {
+        let stderr = match err {
+            CmdqError::ProcessExecuteOutputError { stderr, .. } => stderr.to_lowercase(),
+            _ => return None,
+        };
+        if GEO_MESSAGES.iter().any(|message| stderr.contains(message)) {
+            Some(ErrorClass::Geo)
+        } else if AGE_MESSAGES.iter().any(|message| stderr.contains(message)) {
+            Some(ErrorClass::Age)
+        } else {
+            None
+        }
+    }
+}
+
+/// Chains of fallbacks tried in order when yt-dlp fails with an error of their class, e.g.
+///
+/// ```toml
+/// [[fallbacks.geo]]
+/// args = ["--proxy", "socks5://127.0.0.1:1080"]
+///
+/// [[fallbacks.age]]
+/// args = ["--cookies-from-browser", "firefox"]
+///
+/// [[fallbacks.age]]
+/// args = ["--extractor-args", "youtube:player_client=tv_embedded"]
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fallbacks {
+    #[serde(default)]
+    pub geo: Vec<Fallback>,
+    #[serde(default)]
+    pub age: Vec<Fallback>,
+}
+
+impl Fallbacks {
+    pub fn chain(&self, class: ErrorClass) -> &[Fallback] {
+        match class {
+            ErrorClass::Geo => &self.geo,
+            ErrorClass::Age => &self.age,
+        }
+    }
+}
+
+/// Args added to those of the record when retrying it, e.g. another format, --extractor-args
+/// or a proxy
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fallback {
+    pub args: Vec<String>,
+}
+
+#[test]
+fn test_error_class_of_stderr() {
+    let failed = |stderr: &str| CmdqError::ProcessExecuteOutputError {
+        stdout: String::new(),
+        stderr: stderr.to_string(),
+    };
+    let cases = [
+        (
+            "ERROR: [youtube] abc: The uploader has not made this video available in your country",
+            Some(ErrorClass::Geo),
+        ),
+        (
+            "ERROR: [vimeo] 123: This video is Geo-Restricted",
+            Some(ErrorClass::Geo),
+        ),
+        (
+            "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.",
+            Some(ErrorClass::Age),
+        ),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video is private",
+            None,
+        ),
+        ("", None),
+    ];
+    for (stderr, class) in cases {
+        assert_eq!(ErrorClass::of(&failed(stderr)), class, "{}", stderr);
+    }
+
+    // Only the output of yt-dlp is classified
+    let not_output = CmdqError::InvalidEnvError {
+        var: "not available in your country".to_string(),
+    };
+    assert_eq!(ErrorClass::of(&not_output), None);
+}
+
+#[test]
+fn test_fallbacks_chain_order() {
+    let fallback = |args: &[&str]| Fallback {
+        args: args.iter().map(|arg| arg.to_string()).collect(),
+    };
+    let fallbacks = Fallbacks {
+        geo: vec![fallback(&["--proxy", "socks5://127.0.0.1:1080"])],
+        age: vec![
+            fallback(&["--cookies-from-browser", "firefox"]),
+            fallback(&["--extractor-args", "youtube:player_client=tv_embedded"]),
+        ],
+    };
+    let args = |class| {
+        fallbacks
+            .chain(class)
+            .iter()
+            .map(|fallback| fallback.args.join(" "))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(args(ErrorClass::Geo), ["--proxy socks5://127.0.0.1:1080"]);
+    assert_eq!(
+        args(ErrorClass::Age),
+        [
+            "--cookies-from-browser firefox",
+            "--extractor-args youtube:player_client=tv_embedded"
+        ]
+    );
+    assert!(Fallbacks::default().chain(ErrorClass::Geo).is_empty());
+}
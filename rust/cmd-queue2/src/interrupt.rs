@@ -0,0 +1,34 @@
+use std::{
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::{event, Level};
+
+use crate::error::CmdqError;
+
+/// Exit code of a run stopped with Ctrl-C, as shells report a process killed by SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler. The first Ctrl-C stops records from being started so that the
+/// run can write its error and remaining files, a second one exits right away.
+///
+/// The yt-dlp processes started from a terminal receive the SIGINT of Ctrl-C as well and stop,
+/// their records are then written to the remaining file rather than the error file.
+pub fn install() -> Result<(), CmdqError> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        event!(
+            Level::WARN,
+            "interrupted, stopping after the running records, Ctrl-C again to exit now"
+        );
+    })
+    .map_err(|err| CmdqError::InstallInterruptHandlerError { source: err })
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
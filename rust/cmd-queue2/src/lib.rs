@@ -1,59 +1,268 @@
 use error::CmdqError;
+use serde::Serialize;
 use std::{
+    collections::HashMap,
     ffi::OsStr,
     fs::{self, File},
+    io::{self, Read},
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tracing::{event, span, Level};
 
+pub mod config;
 pub mod error;
+pub mod estimate;
+pub mod fallback;
+pub mod interrupt;
+pub mod notify;
+pub mod verify;
+pub mod video_id;
 pub mod ytdlp;
 
-pub fn run_ytdlp_file(filepath: PathBuf) -> Result<(), CmdqError> {
-    let csv_file = File::open(&filepath).map_err(|err| CmdqError::FileOpenError {
-        source: err,
-        filepath: filepath.clone(),
-    })?;
-    let mut rdr = csv::Reader::from_reader(csv_file);
-
-    let mut errored_records = Vec::new();
-
-    for result in rdr.deserialize() {
-        let record: ytdlp::Record =
-            result.map_err(|err| CmdqError::CsvDeserializeError { source: err })?;
-
-        let span = span!(
-            Level::INFO,
-            "yt-dlp execute",
-            url = record.url,
-            title = record.title
-        );
-        let _enter = span.enter();
-
-        event!(Level::INFO, "executing");
-        match ytdlp::execute(&filepath, &record) {
-            Ok(_) => {
-                event!(Level::INFO, "execution succeeded");
-            }
-            Err(err) => {
-                event!(Level::ERROR, message = "execution failed", ?err);
-                errored_records.push(ErroredRecord { record, err });
-            }
-        }
+/// Filepath reading the records from stdin instead of a file
+pub const STDIN_FILEPATH: &str = "-";
+
+/// Filepath the error and remaining files of records read from stdin are named after
+const STDIN_OUTPUT_FILEPATH: &str = "stdin.csv";
+
+/// Outcome of a run of `run_ytdlp_file`
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub input: PathBuf,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Records not started before the total budget was spent or the run was interrupted
+    pub remaining: usize,
+    /// Whether the run was stopped with Ctrl-C
+    pub interrupted: bool,
+    pub error_filepath: Option<PathBuf>,
+    pub remaining_filepath: Option<PathBuf>,
+}
+
+/// How the records of a file are run, as opposed to how each is downloaded
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Number of records downloaded at the same time
+    pub concurrency: usize,
+    /// Once spent no more records are started and the ones left are written to a remaining CSV
+    /// file for a later run
+    pub total_budget: Option<Duration>,
+    /// Estimate the size of the downloads first, aborting if there isn't enough free space
+    pub estimate: bool,
+}
+
+/// Downloads the records of the CSV file at `filepath`, or of stdin if it is `-`.
+pub fn run_ytdlp_file(
+    filepath: PathBuf,
+    options: &ytdlp::Options,
+    run_options: &RunOptions,
+) -> Result<RunSummary, CmdqError> {
+    let deadline = run_options
+        .total_budget
+        .map(|budget| Instant::now() + budget);
+    let from_stdin = filepath.as_os_str() == STDIN_FILEPATH;
+    let (headers, records) = if from_stdin {
+        read_stdin_records()?
+    } else {
+        read_records_file(&filepath)?
+    };
+    let records = dedupe_records(records);
+
+    if run_options.estimate {
+        let estimate = estimate::estimate(&filepath, &records, options)?;
+        println!("{}", estimate);
+        estimate::check_free_space(&estimate)?;
     }
 
+    // Each worker takes the next record until there are none left
+    let records = Mutex::new(records.into_iter());
+    let errored_records = Mutex::new(Vec::new());
+    let interrupted_records = Mutex::new(Vec::new());
+    let succeeded = AtomicUsize::new(0);
+    thread::scope(|s| {
+        for _ in 0..run_options.concurrency.max(1) {
+            s.spawn(|| loop {
+                if interrupt::is_interrupted()
+                    || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    break;
+                }
+                let record = match records.lock().unwrap().next() {
+                    Some(record) => record,
+                    None => break,
+                };
+
+                let span = span!(
+                    Level::INFO,
+                    "yt-dlp execute",
+                    url = record.url,
+                    title = record.title
+                );
+                let _enter = span.enter();
+
+                event!(Level::INFO, "executing");
+                match ytdlp::execute(&filepath, &record, options) {
+                    Ok(_) => {
+                        event!(Level::INFO, "execution succeeded");
+                        succeeded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    // yt-dlp stopped by the Ctrl-C, the record can be downloaded again later
+                    Err(_) if interrupt::is_interrupted() => {
+                        event!(Level::WARN, "execution interrupted");
+                        interrupted_records.lock().unwrap().push(record);
+                    }
+                    Err(err) => {
+                        event!(Level::ERROR, message = "execution failed", ?err);
+                        errored_records
+                            .lock()
+                            .unwrap()
+                            .push(ErroredRecord { record, err });
+                    }
+                }
+            });
+        }
+    });
+    let errored_records = errored_records.into_inner().unwrap();
+    let mut remaining_records = interrupted_records.into_inner().unwrap();
+    remaining_records.extend(records.into_inner().unwrap());
+
     // TODO re-run errored records
 
+    let output_filepath = if from_stdin {
+        PathBuf::from(STDIN_OUTPUT_FILEPATH)
+    } else {
+        filepath.clone()
+    };
+    let mut summary = RunSummary {
+        input: filepath.clone(),
+        succeeded: succeeded.into_inner(),
+        failed: errored_records.len(),
+        remaining: remaining_records.len(),
+        interrupted: interrupt::is_interrupted(),
+        error_filepath: None,
+        remaining_filepath: None,
+    };
     if errored_records.len() > 0 {
-        write_errors(errored_records, &filepath)?;
+        summary.error_filepath = Some(write_errors(
+            errored_records,
+            headers.as_ref(),
+            &output_filepath,
+        )?);
+    }
+    if !remaining_records.is_empty() {
+        summary.remaining_filepath = Some(write_remaining(remaining_records, &output_filepath)?);
+    }
+
+    if !from_stdin {
+        fs::remove_file(&filepath).map_err(|err| CmdqError::RemoveInputFileError {
+            source: err,
+            filepath: filepath.clone(),
+        })?;
     }
+    Ok(summary)
+}
+
+/// Records along with the header of the CSV they were read from, None if they weren't read from
+/// a CSV
+type Records = (Option<csv::StringRecord>, Vec<ytdlp::Record>);
 
-    fs::remove_file(&filepath).map_err(|err| CmdqError::RemoveInputFileError {
+pub(crate) fn read_records_file(filepath: &Path) -> Result<Records, CmdqError> {
+    let csv_file = File::open(filepath).map_err(|err| CmdqError::FileOpenError {
         source: err,
-        filepath: filepath.clone(),
+        filepath: filepath.to_path_buf(),
     })?;
-    Ok(())
+    read_csv_records(csv_file)
+}
+
+fn read_csv_records<R: Read>(reader: R) -> Result<Records, CmdqError> {
+    let deserialize_error = |err| CmdqError::CsvDeserializeError { source: err };
+    let mut rdr = csv::Reader::from_reader(reader);
+    let headers = rdr.headers().map_err(deserialize_error)?.clone();
+    let records = rdr
+        .records()
+        .map(|row| {
+            let row = row.map_err(deserialize_error)?;
+            let mut record: ytdlp::Record =
+                row.deserialize(Some(&headers)).map_err(deserialize_error)?;
+            record.line = row.position().map(|position| position.line());
+            record.row = Some(row);
+            Ok(record)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((Some(headers), records))
+}
+
+/// Reads the records piped to stdin, either as a CSV with a url column or as one URL per line
+fn read_stdin_records() -> Result<Records, CmdqError> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|err| CmdqError::StdinReadError { source: err })?;
+    parse_stdin_records(&input)
+}
+
+fn parse_stdin_records(input: &str) -> Result<Records, CmdqError> {
+    let is_csv = input
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .map(|header| header.split(',').any(|field| field.trim() == "url"))
+        .unwrap_or(false);
+    if is_csv {
+        return read_csv_records(input.as_bytes());
+    }
+    let records = input
+        .lines()
+        .zip(1..)
+        .map(|(line, number)| (line.trim(), number))
+        .filter(|(line, _)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(url, number)| ytdlp::Record {
+            url: url.to_string(),
+            title: String::new(),
+            dir: None,
+            cookies: None,
+            env: None,
+            row: None,
+            line: Some(number),
+        })
+        .collect();
+    Ok((None, records))
+}
+
+/// Drops the records of videos already in an earlier record, comparing the video IDs of
+/// supported sites and the raw URLs of others. The merges are reported with the lines of the
+/// input the records were read from.
+fn dedupe_records(records: Vec<ytdlp::Record>) -> Vec<ytdlp::Record> {
+    let mut first_lines: HashMap<String, Option<u64>> = HashMap::new();
+    let mut deduped = Vec::with_capacity(records.len());
+    for record in records {
+        let key = match video_id::extract(&record.url) {
+            Some(video_id) => video_id.to_string(),
+            None => record.url.trim().to_string(),
+        };
+        match first_lines.get(&key) {
+            Some(first_line) => {
+                event!(
+                    Level::WARN,
+                    message = "merged duplicate record",
+                    line = record.line,
+                    into_line = first_line,
+                    video = key,
+                    url = record.url
+                );
+            }
+            None => {
+                first_lines.insert(key, record.line);
+                deduped.push(record);
+            }
+        }
+    }
+    deduped
 }
 
 struct ErroredRecord {
@@ -62,10 +271,19 @@ struct ErroredRecord {
 }
 
 fn error_filepath<T: AsRef<Path>>(filepath: T) -> PathBuf {
+    output_filepath(filepath, "err")
+}
+
+fn remaining_filepath<T: AsRef<Path>>(filepath: T) -> PathBuf {
+    output_filepath(filepath, "remaining")
+}
+
+/// Path next to the input file, named after it with a timestamp and `suffix`
+fn output_filepath<T: AsRef<Path>>(filepath: T, suffix: &str) -> PathBuf {
     let mut path = filepath.as_ref().to_path_buf();
     let filename_without_ext = path
         .file_name()
-        .expect("error_filepath error: filepath does not end in file_name")
+        .expect("output_filepath error: filepath does not end in file_name")
         .to_str()
         .unwrap()
         .trim_end_matches(path.extension().unwrap_or(OsStr::new("")).to_str().unwrap());
@@ -74,13 +292,20 @@ fn error_filepath<T: AsRef<Path>>(filepath: T) -> PathBuf {
         .duration_since(UNIX_EPOCH)
         .expect("System time before UNIX EPOCH!")
         .as_secs();
-    let error_filename = format!("{}-{}-err.csv", filename_without_ext, timestamp);
+    let output_filename = format!("{}-{}-{}.csv", filename_without_ext, timestamp, suffix);
 
-    path.set_file_name(error_filename);
+    path.set_file_name(output_filename);
     path
 }
 
-fn write_errors<T: AsRef<Path>>(errors: Vec<ErroredRecord>, filepath: T) -> Result<(), CmdqError> {
+/// Writes the errored records with their error. Records read from a CSV are written with every
+/// column of their row in its original order, followed by the error column unless the CSV
+/// already had one, as when re-running an error file.
+fn write_errors<T: AsRef<Path>>(
+    errors: Vec<ErroredRecord>,
+    headers: Option<&csv::StringRecord>,
+    filepath: T,
+) -> Result<PathBuf, CmdqError> {
     let error_filepath = error_filepath(&filepath);
     event!(
         Level::WARN,
@@ -94,27 +319,177 @@ fn write_errors<T: AsRef<Path>>(errors: Vec<ErroredRecord>, filepath: T) -> Resu
             filepath: error_filepath.clone(),
         })?;
 
+    let write_error = |err| CmdqError::WriteToErrorFileError {
+        source: err,
+        filepath: error_filepath.clone(),
+    };
     let mut wtr = csv::Writer::from_writer(error_file);
-    wtr.write_record(&["url", "title", "dir", "error"])
-        .map_err(|err| CmdqError::WriteToErrorFileError {
-            source: err,
-            filepath: error_filepath.clone(),
-        })?;
-    for errored_record in errors {
-        wtr.serialize((
-            errored_record.record.url,
-            errored_record.record.title,
-            errored_record.record.dir,
-            errored_record.err.to_string(),
-        ))
-        .map_err(|err| CmdqError::WriteToErrorFileError {
-            source: err,
-            filepath: error_filepath.clone(),
-        })?;
+    match headers {
+        Some(headers) => {
+            let error_column = headers.iter().position(|header| header == "error");
+            if error_column.is_none() {
+                wtr.write_record(headers.iter().chain(["error"]))
+                    .map_err(write_error)?;
+            } else {
+                wtr.write_record(headers).map_err(write_error)?;
+            }
+            for errored_record in errors {
+                let row = errored_record
+                    .record
+                    .row
+                    .expect("records read from a CSV keep their row");
+                let err = errored_record.err.to_string();
+                let mut fields = row.iter().collect::<Vec<_>>();
+                match error_column {
+                    Some(error_column) => fields[error_column] = err.as_str(),
+                    None => fields.push(err.as_str()),
+                }
+                wtr.write_record(fields).map_err(write_error)?;
+            }
+        }
+        None => {
+            wtr.write_record(["url", "title", "dir", "cookies", "env", "error"])
+                .map_err(write_error)?;
+            for errored_record in errors {
+                wtr.serialize((
+                    errored_record.record.url,
+                    errored_record.record.title,
+                    errored_record.record.dir,
+                    errored_record.record.cookies,
+                    errored_record.record.env,
+                    errored_record.err.to_string(),
+                ))
+                .map_err(write_error)?;
+            }
+        }
     }
     wtr.flush().map_err(|err| CmdqError::WriteErrorFileError {
         source: err,
         filepath: error_filepath.clone(),
     })?;
-    Ok(())
+    Ok(error_filepath)
+}
+
+fn write_remaining<T: AsRef<Path>>(
+    records: Vec<ytdlp::Record>,
+    filepath: T,
+) -> Result<PathBuf, CmdqError> {
+    let remaining_filepath = remaining_filepath(&filepath);
+    event!(
+        Level::WARN,
+        message = "writing remaining records",
+        count = records.len(),
+        path = format!("{}", remaining_filepath.display())
+    );
+
+    let remaining_file =
+        File::create(&remaining_filepath).map_err(|err| CmdqError::CreateRemainingFileError {
+            source: err,
+            filepath: remaining_filepath.clone(),
+        })?;
+
+    let mut wtr = csv::Writer::from_writer(remaining_file);
+    for record in records {
+        wtr.serialize(record)
+            .map_err(|err| CmdqError::WriteToRemainingFileError {
+                source: err,
+                filepath: remaining_filepath.clone(),
+            })?;
+    }
+    wtr.flush()
+        .map_err(|err| CmdqError::WriteRemainingFileError {
+            source: err,
+            filepath: remaining_filepath.clone(),
+        })?;
+    Ok(remaining_filepath)
+}
+
+#[test]
+fn test_write_errors_keeps_extra_columns() {
+    let dir = std::env::temp_dir().join(format!("cmdq2-write-errors-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let filepath = dir.join("videos.csv");
+    fs::write(
+        &filepath,
+        "channel,url,title,notes\nsome channel,https://youtu.be/dQw4w9WgXcQ,a title,\"a, note\"\n",
+    )
+    .unwrap();
+
+    let (headers, records) = read_records_file(&filepath).unwrap();
+    let errors = records
+        .into_iter()
+        .map(|record| ErroredRecord {
+            record,
+            err: CmdqError::InvalidEnvError {
+                var: "BROKEN".to_string(),
+            },
+        })
+        .collect();
+    let error_filepath = write_errors(errors, headers.as_ref(), &filepath).unwrap();
+
+    let mut rdr = csv::Reader::from_path(&error_filepath).unwrap();
+    assert_eq!(
+        rdr.headers().unwrap().iter().collect::<Vec<_>>(),
+        vec!["channel", "url", "title", "notes", "error"]
+    );
+    let rows = rdr.records().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(
+        rows[0].iter().take(4).collect::<Vec<_>>(),
+        vec![
+            "some channel",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "a title",
+            "a, note"
+        ]
+    );
+    assert!(rows[0][4].contains("BROKEN"));
+
+    // The error file can be run again, keeping a single error column
+    let (headers, records) = read_records_file(&error_filepath).unwrap();
+    assert_eq!(records[0].url, "https://youtu.be/dQw4w9WgXcQ");
+    assert_eq!(headers.unwrap().iter().filter(|h| *h == "error").count(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_dedupe_records_reports_input_lines() {
+    let input = "\
+# videos to watch
+https://youtu.be/dQw4w9WgXcQ
+
+https://vimeo.com/76979871
+https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1
+https://www.youtube.com/shorts/dQw4w9WgXcQ
+https://player.vimeo.com/video/76979871
+";
+    let (headers, records) = parse_stdin_records(input).unwrap();
+    assert!(headers.is_none());
+    let lines = records.iter().map(|record| record.line).collect::<Vec<_>>();
+    assert_eq!(lines, [Some(2), Some(4), Some(5), Some(6), Some(7)]);
+
+    let records = dedupe_records(records);
+    let urls = records
+        .iter()
+        .map(|record| record.url.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        ["https://youtu.be/dQw4w9WgXcQ", "https://vimeo.com/76979871"]
+    );
+}
+
+#[test]
+fn test_csv_records_lines_after_multiline_fields() {
+    let input = "\
+url,title
+https://youtu.be/dQw4w9WgXcQ,\"a title
+over two lines\"
+https://example.com/video,other
+";
+    let (_, records) = parse_stdin_records(input).unwrap();
+    let lines = records.iter().map(|record| record.line).collect::<Vec<_>>();
+    assert_eq!(lines, [Some(2), Some(4)]);
+    assert_eq!(records[0].title, "a title\nover two lines");
 }
@@ -0,0 +1,56 @@
+use tracing::{event, Level};
+
+use crate::RunSummary;
+
+/// Where to notify that a run finished
+#[derive(Debug, Clone, Default)]
+pub struct NotifyOptions {
+    /// Show a desktop notification
+    pub desktop: bool,
+    /// URL the summary of the run is POSTed to as JSON
+    pub webhook_url: Option<String>,
+}
+
+/// Sends the notifications of the finished run. Failing to notify doesn't fail the run so errors
+/// are only logged.
+pub fn notify(summary: &RunSummary, options: &NotifyOptions) {
+    if options.desktop {
+        if let Err(err) = notify_rust::Notification::new()
+            .summary("cmdq2 run finished")
+            .body(&message(summary))
+            .show()
+        {
+            event!(Level::WARN, message = "desktop notification failed", ?err);
+        }
+    }
+
+    if let Some(webhook_url) = &options.webhook_url {
+        let result = reqwest::blocking::Client::new()
+            .post(webhook_url)
+            .json(summary)
+            .send()
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            event!(Level::WARN, message = "webhook notification failed", ?err);
+        }
+    }
+}
+
+fn message(summary: &RunSummary) -> String {
+    let mut message = format!(
+        "{}: {} succeeded, {} failed",
+        summary.input.display(),
+        summary.succeeded,
+        summary.failed
+    );
+    if summary.remaining > 0 {
+        message.push_str(&format!(", {} remaining", summary.remaining));
+    }
+    if let Some(error_filepath) = &summary.error_filepath {
+        message.push_str(&format!("\nerrors in {}", error_filepath.display()));
+    }
+    if let Some(remaining_filepath) = &summary.remaining_filepath {
+        message.push_str(&format!("\nremaining in {}", remaining_filepath.display()));
+    }
+    message
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+use tracing::{event, Level};
+
+use crate::{error::CmdqError, video_id, ytdlp};
+
+/// Whether a record was downloaded, as found in the files of the download directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordStatus {
+    Downloaded(PathBuf),
+    /// The file exists but is empty, as left by a download that failed
+    Empty(PathBuf),
+    Missing,
+}
+
+impl RecordStatus {
+    pub fn needs_redownload(&self) -> bool {
+        !matches!(self, RecordStatus::Downloaded(_))
+    }
+}
+
+impl fmt::Display for RecordStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordStatus::Downloaded(path) => write!(f, "downloaded {}", path.display()),
+            RecordStatus::Empty(path) => write!(f, "empty {}", path.display()),
+            RecordStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// A file downloaded by yt-dlp, named `{title} [{id}].{ext}`
+struct DownloadedFile {
+    path: PathBuf,
+    filename: String,
+    len: u64,
+}
+
+/// Checks which of the records of the CSV file at `filepath` have a downloaded file in `dir` or
+/// its subdirectories, matching them by the `[id]` in the filenames. Records of unsupported
+/// sites, whose ID is unknown, are matched by their title.
+pub fn verify(
+    dir: &Path,
+    filepath: &Path,
+) -> Result<Vec<(ytdlp::Record, RecordStatus)>, CmdqError> {
+    let (_, records) = crate::read_records_file(filepath)?;
+    let mut files = Vec::new();
+    list_files(dir, &mut files)?;
+
+    let mut files_by_id: HashMap<&str, Vec<&DownloadedFile>> = HashMap::new();
+    for file in &files {
+        if let Some(id) = filename_id(&file.filename) {
+            files_by_id.entry(id).or_default().push(file);
+        }
+    }
+
+    Ok(records
+        .into_iter()
+        .map(|record| {
+            let matching = match video_id::extract(&record.url) {
+                Some(video_id) => files_by_id
+                    .get(video_id.id.as_str())
+                    .cloned()
+                    .unwrap_or_default(),
+                None if !record.title.trim().is_empty() => {
+                    let prefix = format!("{} [", ytdlp::clean_title(&record.title));
+                    files
+                        .iter()
+                        .filter(|file| file.filename.starts_with(&prefix))
+                        .collect()
+                }
+                None => Vec::new(),
+            };
+            let status = match matching.iter().find(|file| file.len > 0) {
+                Some(file) => RecordStatus::Downloaded(file.path.clone()),
+                None => match matching.first() {
+                    Some(file) => RecordStatus::Empty(file.path.clone()),
+                    None => RecordStatus::Missing,
+                },
+            };
+            (record, status)
+        })
+        .collect())
+}
+
+fn list_files(dir: &Path, files: &mut Vec<DownloadedFile>) -> Result<(), CmdqError> {
+    let read_dir_error = |err| CmdqError::ReadDownloadDirError {
+        source: err,
+        dir: dir.to_path_buf(),
+    };
+    for entry in fs::read_dir(dir).map_err(read_dir_error)? {
+        let entry = entry.map_err(read_dir_error)?;
+        let metadata = entry.metadata().map_err(read_dir_error)?;
+        let path = entry.path();
+        if metadata.is_dir() {
+            list_files(&path, files)?;
+            continue;
+        }
+        let filename = entry.file_name().to_string_lossy().to_string();
+        // Downloads in progress or interrupted
+        if filename.ends_with(".part") || filename.ends_with(".ytdl") {
+            continue;
+        }
+        files.push(DownloadedFile {
+            path,
+            filename,
+            len: metadata.len(),
+        });
+    }
+    Ok(())
+}
+
+/// The ID between the last brackets of the filename, right before its extension
+fn filename_id(filename: &str) -> Option<&str> {
+    let start = filename.rfind(" [")? + 2;
+    let end = start + filename[start..].find("].")?;
+    Some(&filename[start..end])
+}
+
+/// Writes the records to a CSV file that can be given to `cmdq2 ytdlp`
+pub fn write_redownload<'a>(
+    records: impl Iterator<Item = &'a ytdlp::Record>,
+    filepath: &Path,
+) -> Result<(), CmdqError> {
+    event!(
+        Level::INFO,
+        message = "writing records to re-download",
+        path = format!("{}", filepath.display())
+    );
+    let write_error = |err| CmdqError::WriteRedownloadFileError {
+        source: err,
+        filepath: filepath.to_path_buf(),
+    };
+    let mut wtr = csv::Writer::from_path(filepath).map_err(write_error)?;
+    for record in records {
+        wtr.serialize(record).map_err(write_error)?;
+    }
+    wtr.flush()
+        .map_err(|err| write_error(csv::Error::from(err)))?;
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+/// Identifies a video independently of the form of its URL, e.g. youtu.be/ID and
+/// youtube.com/watch?v=ID are the same video
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VideoId {
+    pub site: &'static str,
+    pub id: String,
+}
+
+impl fmt::Display for VideoId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.site, self.id)
+    }
+}
+
+/// Extracts the ID of the video from the URL of a supported site, None for other URLs
+pub fn extract(url: &str) -> Option<VideoId> {
+    let (host, path, query) = split_url(url)?;
+    let host = host.to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let host = host.strip_prefix("m.").unwrap_or(host);
+
+    match host {
+        "youtu.be" => youtube(first_segment(path)?),
+        "youtube.com" | "music.youtube.com" | "youtube-nocookie.com" => {
+            match path.trim_start_matches('/').split_once('/') {
+                Some(("shorts", rest))
+                | Some(("embed", rest))
+                | Some(("live", rest))
+                | Some(("v", rest)) => youtube(first_segment(rest)?),
+                _ if path == "/watch" => youtube(query_param(query?, "v")?),
+                _ => None,
+            }
+        }
+        "vimeo.com" | "player.vimeo.com" => path
+            .split('/')
+            .find(|segment| !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()))
+            .map(|id| VideoId {
+                site: "vimeo",
+                id: id.to_string(),
+            }),
+        _ => None,
+    }
+}
+
+fn youtube(id: &str) -> Option<VideoId> {
+    // IDs of youtube videos are 11 characters of base64url
+    if id.len() == 11
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        Some(VideoId {
+            site: "youtube",
+            id: id.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Splits the URL into its host, its path and its query without the fragment
+fn split_url(url: &str) -> Option<(&str, &str, Option<&str>)> {
+    let url = url.trim();
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+    let rest = rest.split('#').next().unwrap_or(rest);
+    let (rest, query) = match rest.split_once('?') {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (rest, None),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, path, query))
+    }
+}
+
+fn first_segment(path: &str) -> Option<&str> {
+    path.trim_start_matches('/')
+        .split('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+#[test]
+fn test_extract_youtube_forms() {
+    let expected = Some(VideoId {
+        site: "youtube",
+        id: "dQw4w9WgXcQ".to_string(),
+    });
+    for url in [
+        "https://youtu.be/dQw4w9WgXcQ",
+        "youtu.be/dQw4w9WgXcQ?t=42",
+        "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=1",
+        "https://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+        "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+        "https://www.youtube.com/embed/dQw4w9WgXcQ",
+        "https://music.youtube.com/watch?v=dQw4w9WgXcQ#comments",
+    ] {
+        assert_eq!(extract(url), expected, "{}", url);
+    }
+}
+
+#[test]
+fn test_extract_vimeo_forms() {
+    let expected = Some(VideoId {
+        site: "vimeo",
+        id: "76979871".to_string(),
+    });
+    for url in [
+        "https://vimeo.com/76979871",
+        "https://www.vimeo.com/76979871?share=copy",
+        "https://player.vimeo.com/video/76979871",
+        "https://vimeo.com/channels/staffpicks/76979871",
+    ] {
+        assert_eq!(extract(url), expected, "{}", url);
+    }
+}
+
+#[test]
+fn test_extract_rejects_invalid_ids() {
+    for url in [
+        "https://youtu.be/dQw4w9WgXc",
+        "https://www.youtube.com/watch?v=dQw4w9WgXcQQ",
+        "https://www.youtube.com/shorts/dQw4w9W.XcQ",
+        "https://www.youtube.com/watch?t=1",
+        "https://www.youtube.com/@channel",
+        "https://vimeo.com/channels/staffpicks",
+        "https://example.com/watch?v=dQw4w9WgXcQ",
+        "",
+    ] {
+        assert_eq!(extract(url), None, "{}", url);
+    }
+}
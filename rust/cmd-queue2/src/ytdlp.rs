@@ -1,12 +1,23 @@
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     env,
+    io::{self, Read},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
 };
 use tracing::{event, Level};
 
-use crate::error::CmdqError;
+use crate::{
+    error::CmdqError,
+    fallback::{ErrorClass, Fallbacks},
+    interrupt,
+};
+
+/// How often a yt-dlp process running with a timeout is checked for having exited
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Record {
@@ -15,16 +26,243 @@ pub struct Record {
     #[serde(rename = "title")]
     pub title: String,
     pub dir: Option<String>,
+    /// Name of the cookie jar in the cookies dir used to download the record
+    pub cookies: Option<String>,
+    /// Environment variables of the yt-dlp process of the record, as `KEY=VALUE;KEY=VALUE`
+    pub env: Option<String>,
+    /// Every column of the CSV row the record was read from, in their original order
+    #[serde(skip)]
+    pub row: Option<csv::StringRecord>,
+    /// Line of the input the record starts on, counting from 1
+    #[serde(skip)]
+    pub line: Option<u64>,
+}
+
+impl Record {
+    /// Environment variables of the record, overriding the ones of the run
+    fn env(&self) -> Result<Vec<(String, String)>, CmdqError> {
+        self.env
+            .as_deref()
+            .unwrap_or("")
+            .split(';')
+            .map(str::trim)
+            .filter(|var| !var.is_empty())
+            .map(|var| {
+                var.split_once('=')
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .ok_or_else(|| CmdqError::InvalidEnvError {
+                        var: var.to_string(),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// How yt-dlp is run for every record
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub ytdlp_path: String,
+    /// Directory records are downloaded into, the directory of the input file if None
+    pub target_dir: Option<PathBuf>,
+    pub rate_limit: Option<String>,
+    pub proxy: Option<String>,
+    /// Args of the preset in use
+    pub extra_args: Vec<String>,
+    /// yt-dlp is killed and the record failed once it ran for longer than this
+    pub record_timeout: Option<Duration>,
+    pub write_subs: bool,
+    /// Languages of the subtitles written, e.g. `en.*,fr`
+    pub sub_langs: Option<String>,
+    pub split_chapters: bool,
+    pub embed_thumbnail: bool,
+    /// Environment variables of every yt-dlp process, e.g. tokens
+    pub env: BTreeMap<String, String>,
+    /// Directory of the cookie jars named in the cookies column of records
+    pub cookies_dir: Option<PathBuf>,
+    /// Args records are retried with when they fail because of a geo or age restriction
+    pub fallbacks: Fallbacks,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            ytdlp_path: "yt-dlp".to_string(),
+            target_dir: None,
+            rate_limit: None,
+            proxy: None,
+            extra_args: Vec::new(),
+            record_timeout: None,
+            write_subs: false,
+            sub_langs: None,
+            split_chapters: false,
+            embed_thumbnail: false,
+            env: BTreeMap::new(),
+            cookies_dir: None,
+            fallbacks: Fallbacks::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Args passed to yt-dlp for every record, after the url and output
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(rate_limit) = &self.rate_limit {
+            args.extend(["--limit-rate".to_string(), rate_limit.clone()]);
+        }
+        if let Some(proxy) = &self.proxy {
+            args.extend(["--proxy".to_string(), proxy.clone()]);
+        }
+        if self.write_subs {
+            args.push("--write-subs".to_string());
+        }
+        if let Some(sub_langs) = &self.sub_langs {
+            args.extend(["--sub-langs".to_string(), sub_langs.clone()]);
+        }
+        if self.split_chapters {
+            args.push("--split-chapters".to_string());
+        }
+        if self.embed_thumbnail {
+            args.push("--embed-thumbnail".to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    /// Path of the cookie jar named `name` in the cookies dir, with or without a .txt extension
+    fn cookies_path(&self, name: &str) -> Result<PathBuf, CmdqError> {
+        let cookies_dir =
+            self.cookies_dir
+                .as_ref()
+                .ok_or_else(|| CmdqError::NoCookiesDirError {
+                    name: name.to_string(),
+                })?;
+        [
+            cookies_dir.join(name),
+            cookies_dir.join(format!("{}.txt", name)),
+        ]
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| CmdqError::CookiesNotFoundError {
+            name: name.to_string(),
+            dir: cookies_dir.clone(),
+        })
+    }
+}
+
+/// Downloads the record, retrying it with the fallbacks of the class of the error if it failed
+/// with one of a geo or age restriction
+pub fn execute(filepath: &Path, record: &Record, options: &Options) -> Result<(), CmdqError> {
+    let result = execute_with(filepath, record, options, &[]);
+    let class = match result.as_ref().err().and_then(ErrorClass::of) {
+        Some(class) => class,
+        None => return result,
+    };
+    let chain = options.fallbacks.chain(class);
+    if chain.is_empty() {
+        return result;
+    }
+
+    let mut result = result;
+    for (i, fallback) in chain.iter().enumerate() {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        event!(
+            Level::WARN,
+            message = "retrying with fallback",
+            %class,
+            fallback = i + 1,
+            args = fallback.args.join(" ")
+        );
+        result = execute_with(filepath, record, options, &fallback.args);
+        if result.is_ok() {
+            return result;
+        }
+    }
+    result.map_err(|err| CmdqError::FallbacksFailedError {
+        class,
+        tried: chain.len(),
+        source: Box::new(err),
+    })
 }
 
-pub fn execute(filepath: &Path, record: &Record) -> Result<(), CmdqError> {
+/// Downloads the record with `extra_args` added to its args
+fn execute_with(
+    filepath: &Path,
+    record: &Record,
+    options: &Options,
+    extra_args: &[String],
+) -> Result<(), CmdqError> {
+    let (mut command, mut args) = record_command(filepath, record, options)?;
+    command.args(extra_args);
+    args.extend(extra_args.iter().cloned());
+    let output = match options.record_timeout {
+        Some(timeout) => output_with_timeout(&mut command, timeout),
+        None => command.output().map(Some),
+    }
+    .map_err(|err| CmdqError::ProcessExecuteError {
+        err,
+        program: options.ytdlp_path.clone(),
+        args,
+    })?;
+    let output = output.ok_or_else(|| CmdqError::RecordTimeoutError {
+        timeout: options.record_timeout.unwrap_or_default(),
+    })?;
+    check_success(output).map(|_| ())
+}
+
+/// Approximate size in bytes of the download of the record, None if yt-dlp doesn't know it
+pub fn estimate_size(
+    filepath: &Path,
+    record: &Record,
+    options: &Options,
+) -> Result<Option<u64>, CmdqError> {
+    let (mut command, mut args) = record_command(filepath, record, options)?;
+    let estimate_args = ["--simulate", "--print", "filesize_approx"];
+    command.args(estimate_args);
+    args.extend(estimate_args.map(str::to_string));
+    let output = command
+        .output()
+        .map_err(|err| CmdqError::ProcessExecuteError {
+            err: err,
+            program: options.ytdlp_path.clone(),
+            args: args,
+        })?;
+    let stdout = check_success(output)?;
+
+    // Playlists print a line per video, with NA for the ones of unknown size
+    let sizes = stdout
+        .lines()
+        .map(|line| line.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>();
+    Ok(sizes
+        .filter(|sizes| !sizes.is_empty())
+        .map(|sizes| sizes.into_iter().sum()))
+}
+
+/// Directory the record is downloaded into
+pub fn record_target_dir(
+    filepath: &Path,
+    record: &Record,
+    options: &Options,
+) -> Result<PathBuf, CmdqError> {
+    target_dir(filepath, options.target_dir.as_deref(), &record.dir)
+}
+
+/// The yt-dlp command downloading the record, along with its args
+fn record_command(
+    filepath: &Path,
+    record: &Record,
+    options: &Options,
+) -> Result<(Command, Vec<String>), CmdqError> {
     let title = &record.title;
     let url = record.url.clone();
 
-    let target_dir = target_dir(filepath, &record.dir)?;
+    let target_dir = target_dir(filepath, options.target_dir.as_deref(), &record.dir)?;
     event!(Level::INFO, target_dir = format!("{:?}", target_dir));
 
-    let args = if title.trim().len() == 0 {
+    let mut args = if title.trim().is_empty() {
         vec![url.to_string()]
     } else {
         let filename = format!("{} [%(id)s].%(ext)s", clean_title(title));
@@ -32,19 +270,29 @@ pub fn execute(filepath: &Path, record: &Record) -> Result<(), CmdqError> {
 
         vec![url.to_string(), "-o".to_string(), filename.clone()]
     };
+    args.extend(options.args());
+    if let Some(cookies) = &record.cookies {
+        let cookies_path = options.cookies_path(cookies)?;
+        args.extend([
+            "--cookies".to_string(),
+            cookies_path.to_string_lossy().to_string(),
+        ]);
+    }
 
-    let output = Command::new("yt-dlp")
+    let mut command = Command::new(&options.ytdlp_path);
+    // Each record gets the variables of the run and its own only, never those of other records
+    command
         .args(&args)
-        .current_dir(target_dir)
-        .output()
-        .map_err(|err| CmdqError::ProcessExecuteError {
-            err: err,
-            program: "yt-dlp".to_string(),
-            args: args,
-        })?;
+        .envs(&options.env)
+        .envs(record.env()?)
+        .current_dir(target_dir);
+    Ok((command, args))
+}
 
+/// The stdout of the process if it succeeded
+fn check_success(output: Output) -> Result<String, CmdqError> {
     if output.status.success() {
-        Ok(())
+        String::from_utf8(output.stdout).map_err(|_err| CmdqError::ProcessExecuteOutputNotUtf8Error)
     } else {
         let error = CmdqError::ProcessExecuteOutputError {
             stdout: String::from_utf8(output.stdout)
@@ -56,8 +304,61 @@ pub fn execute(filepath: &Path, record: &Record) -> Result<(), CmdqError> {
     }
 }
 
-fn target_dir(filepath: &Path, dir: &Option<String>) -> Result<PathBuf, CmdqError> {
-    let input_dir = if let Some(parent) = filepath.parent() {
+/// Runs the command like `Command::output`, killing it once it ran for longer than `timeout`
+/// in which case None is returned
+fn output_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Option<Output>> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // The pipes are drained on their own threads so that the child never blocks on a full one
+    let stdout_reader = read_to_end(child.stdout.take());
+    let stderr_reader = read_to_end(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(TIMEOUT_POLL_INTERVAL);
+    };
+
+    let stdout = stdout_reader.join().expect("stdout reader panicked")?;
+    let stderr = stderr_reader.join().expect("stderr reader panicked")?;
+    Ok(status.map(|status| Output {
+        status,
+        stdout,
+        stderr,
+    }))
+}
+
+fn read_to_end<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    })
+}
+
+fn target_dir(
+    filepath: &Path,
+    default_target_dir: Option<&Path>,
+    dir: &Option<String>,
+) -> Result<PathBuf, CmdqError> {
+    let input_dir = if let Some(default_target_dir) = default_target_dir {
+        default_target_dir.to_path_buf()
+    } else if let Some(parent) = filepath.parent() {
         if parent.is_absolute() {
             parent.to_path_buf()
         } else {
@@ -85,7 +386,7 @@ fn target_dir(filepath: &Path, dir: &Option<String>) -> Result<PathBuf, CmdqErro
         .unwrap_or_else(|| input_dir))
 }
 
-fn clean_title(title: &str) -> String {
+pub(crate) fn clean_title(title: &str) -> String {
     title
         .replace("/", "_")
         .replace("\\", "_")
@@ -0,0 +1,83 @@
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{Exif, In, Reader, Tag, Value};
+use log::debug;
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW";
+
+/// Returns the time the photo was taken according to its EXIF data
+pub fn capture_time(path: &Path) -> Option<NaiveDateTime> {
+    let exif = read_exif(path)?;
+    let field = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+
+    match field.value {
+        Value::Ascii(ref values) if !values.is_empty() => {
+            let datetime = exif::DateTime::from_ascii(&values[0]).ok()?;
+            NaiveDate::from_ymd_opt(
+                i32::from(datetime.year),
+                u32::from(datetime.month),
+                u32::from(datetime.day),
+            )?
+            .and_hms_opt(
+                u32::from(datetime.hour),
+                u32::from(datetime.minute),
+                u32::from(datetime.second),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Returns the camera model according to the EXIF data of the photo
+pub fn camera_model(path: &Path) -> Option<String> {
+    let exif = read_exif(path)?;
+    let field = exif.get_field(Tag::Model, In::PRIMARY)?;
+
+    match field.value {
+        Value::Ascii(ref values) if !values.is_empty() => {
+            let model = String::from_utf8_lossy(&values[0]);
+            Some(model.trim_end_matches('\0').trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+fn read_exif(path: &Path) -> Option<Exif> {
+    let file = File::open(path).ok()?;
+    let mut reader = BufReader::new(file);
+    match Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => Some(exif),
+        Err(e) => {
+            debug!("no exif container in {}: {}", path.display(), e);
+            read_raf_exif(&mut reader)
+        }
+    }
+}
+
+/// RAF files are not a format the exif reader understands, but they embed a JPEG preview
+/// carrying the EXIF data. Its offset and length are stored as big endian u32s at bytes 84 and 88.
+fn read_raf_exif<R: Read + Seek>(reader: &mut R) -> Option<Exif> {
+    let mut header = [0u8; 92];
+    reader.seek(SeekFrom::Start(0)).ok()?;
+    reader.read_exact(&mut header).ok()?;
+    if !header.starts_with(RAF_MAGIC) {
+        return None;
+    }
+
+    let offset = u32::from_be_bytes([header[84], header[85], header[86], header[87]]);
+    let length = u32::from_be_bytes([header[88], header[89], header[90], header[91]]);
+    let mut jpeg = vec![0u8; length as usize];
+    reader.seek(SeekFrom::Start(u64::from(offset))).ok()?;
+    reader.read_exact(&mut jpeg).ok()?;
+
+    Reader::new()
+        .read_from_container(&mut Cursor::new(jpeg))
+        .ok()
+}
@@ -0,0 +1,280 @@
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::capture::camera_model;
+use crate::error::RawPicsError;
+use crate::sidecar::find_companions;
+
+/// Extensions of the raw formats recognized by default
+const RAW_EXTENSIONS: &[&str] = &["raf", "cr2", "cr3", "nef", "arw", "orf", "dng", "rw2"];
+/// Extensions of the developed formats recognized by default
+const JPG_EXTENSIONS: &[&str] = &["jpg", "jpeg", "heic", "heif"];
+/// Names of the subdirectories of a shoot holding its jpgs by default
+const JPG_DIRS: &[&str] = &["jpg"];
+/// Names of the subdirectories of a shoot holding its raws by default
+const RAW_DIRS: &[&str] = &["raw"];
+/// Extensions of the files accompanying raws that are deleted along with them by default
+const COMPANION_EXTENSIONS: &[&str] = &["xmp", "pp3", "dop"];
+const DEFAULT_MIN_RATING: i32 = 1;
+
+/// Settings of a rule in the config file. Every setting is optional and falls back to
+/// the `[defaults]` section, then to the built-in defaults.
+///
+/// ```toml
+/// [defaults]
+/// respect_ratings = true
+///
+/// [[rules]]
+/// path = "Fuji"
+/// raw_extensions = ["raf"]
+/// jpg_dirs = ["JPG"]
+/// raw_dirs = ["RAF"]
+///
+/// [[rules]]
+/// camera = "ILCE-7M3"
+/// respect_ratings = false
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Directory the rule applies to, relative to the scanned directory unless absolute
+    pub path: Option<PathBuf>,
+    /// Camera model, as found in the EXIF data of the raws, the rule applies to
+    pub camera: Option<String>,
+    pub raw_extensions: Option<Vec<String>>,
+    pub jpg_extensions: Option<Vec<String>>,
+    pub jpg_dirs: Option<Vec<String>>,
+    pub raw_dirs: Option<Vec<String>>,
+    pub companion_extensions: Option<Vec<String>>,
+    pub respect_ratings: Option<bool>,
+    pub min_rating: Option<i32>,
+}
+
+impl RuleConfig {
+    /// Returns these settings with the unset ones taken from `base`
+    fn or(&self, base: &RuleConfig) -> RuleConfig {
+        RuleConfig {
+            path: self.path.clone(),
+            camera: self.camera.clone(),
+            raw_extensions: self
+                .raw_extensions
+                .clone()
+                .or_else(|| base.raw_extensions.clone()),
+            jpg_extensions: self
+                .jpg_extensions
+                .clone()
+                .or_else(|| base.jpg_extensions.clone()),
+            jpg_dirs: self.jpg_dirs.clone().or_else(|| base.jpg_dirs.clone()),
+            raw_dirs: self.raw_dirs.clone().or_else(|| base.raw_dirs.clone()),
+            companion_extensions: self
+                .companion_extensions
+                .clone()
+                .or_else(|| base.companion_extensions.clone()),
+            respect_ratings: self.respect_ratings.or(base.respect_ratings),
+            min_rating: self.min_rating.or(base.min_rating),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: RuleConfig,
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, RawPicsError> {
+        let contents = fs::read_to_string(path).map_err(|e| RawPicsError::ReadConfig {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        toml::from_str(&contents).map_err(|e| RawPicsError::ParseConfig {
+            source: e,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Loads the config at `path`, or at `~/.config/raw-pics-delete/config.toml` when
+    /// no path is given and that file exists
+    pub fn load_or_default(path: Option<&Path>) -> Result<Config, RawPicsError> {
+        match path {
+            Some(path) => Config::load(path),
+            None => match default_config_path().filter(|path| path.is_file()) {
+                Some(path) => Config::load(&path),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("raw-pics-delete")
+            .join("config.toml")
+    })
+}
+
+#[derive(Debug)]
+pub struct Formats {
+    raw_exts: HashSet<String>,
+    jpg_exts: HashSet<String>,
+    jpg_dirs: HashSet<String>,
+    raw_dirs: HashSet<String>,
+    companion_exts: HashSet<String>,
+}
+
+impl Formats {
+    pub fn is_jpg_file(&self, path: &Path) -> bool {
+        has_extension_in(path, &self.jpg_exts)
+    }
+
+    pub fn is_raw_file(&self, path: &Path) -> bool {
+        has_extension_in(path, &self.raw_exts)
+    }
+
+    pub fn is_jpg_dir(&self, path: &Path) -> bool {
+        has_name_in(path, &self.jpg_dirs)
+    }
+
+    pub fn is_raw_dir(&self, path: &Path) -> bool {
+        has_name_in(path, &self.raw_dirs)
+    }
+
+    /// Returns the existing companion files of the raw, such as sidecars
+    pub fn companions(&self, raw: &Path) -> Vec<PathBuf> {
+        find_companions(raw, &self.companion_exts)
+    }
+}
+
+/// The settings to use for a directory or camera
+#[derive(Debug)]
+pub struct Rule {
+    pub formats: Formats,
+    /// Minimum sidecar rating for raws without jpgs to be kept, when sidecars are respected
+    pub min_rating: Option<i32>,
+}
+
+impl Rule {
+    fn new(config: &RuleConfig) -> Rule {
+        Rule {
+            formats: Formats {
+                raw_exts: string_set(config.raw_extensions.as_deref(), RAW_EXTENSIONS),
+                jpg_exts: string_set(config.jpg_extensions.as_deref(), JPG_EXTENSIONS),
+                jpg_dirs: string_set(config.jpg_dirs.as_deref(), JPG_DIRS),
+                raw_dirs: string_set(config.raw_dirs.as_deref(), RAW_DIRS),
+                companion_exts: string_set(
+                    config.companion_extensions.as_deref(),
+                    COMPANION_EXTENSIONS,
+                ),
+            },
+            min_rating: if config.respect_ratings.unwrap_or(false) {
+                Some(config.min_rating.unwrap_or(DEFAULT_MIN_RATING))
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// The rules of the config resolved against the scanned directory and the command line flags.
+/// Command line flags take precedence over the matching rule, which takes precedence over
+/// the `[defaults]` section.
+#[derive(Debug)]
+pub struct Rules {
+    base: Rule,
+    dir_rules: Vec<(PathBuf, Rule)>,
+    camera_rules: Vec<(String, Option<PathBuf>, Rule)>,
+}
+
+impl Rules {
+    pub fn new(config: &Config, root: &Path, overrides: &RuleConfig) -> Rules {
+        let defaults = config.defaults.or(&RuleConfig::default());
+        let resolve = |rule: &RuleConfig| Rule::new(&overrides.or(&rule.or(&defaults)));
+        let rule_path = |path: &PathBuf| root.join(path);
+
+        let mut dir_rules = Vec::new();
+        let mut camera_rules = Vec::new();
+        for rule in &config.rules {
+            match (&rule.camera, &rule.path) {
+                (Some(camera), path) => {
+                    camera_rules.push((camera.clone(), path.as_ref().map(rule_path), resolve(rule)))
+                }
+                (None, Some(path)) => dir_rules.push((rule_path(path), resolve(rule))),
+                (None, None) => debug!("ignoring rule without path or camera {:?}", rule),
+            }
+        }
+        // the most specific directory comes first
+        dir_rules.sort_by_key(|(dir, _)| Reverse(dir.components().count()));
+
+        Rules {
+            base: resolve(&RuleConfig::default()),
+            dir_rules,
+            camera_rules,
+        }
+    }
+
+    /// Returns the rule of the most specific directory rule containing `dir`
+    pub fn for_dir(&self, dir: &Path) -> &Rule {
+        self.dir_rules
+            .iter()
+            .find(|(rule_dir, _)| dir.starts_with(rule_dir))
+            .map(|(_, rule)| rule)
+            .unwrap_or(&self.base)
+    }
+
+    /// Returns the rule for a raw, taking its camera model into account
+    pub fn for_raw(&self, raw: &Path) -> &Rule {
+        let dir = raw.parent().unwrap_or_else(|| Path::new(""));
+        if !self.camera_rules.is_empty() {
+            if let Some(model) = camera_model(raw) {
+                let camera_rule = self.camera_rules.iter().find(|(camera, rule_dir, _)| {
+                    camera.eq_ignore_ascii_case(&model)
+                        && rule_dir
+                            .as_ref()
+                            .map(|rule_dir| dir.starts_with(rule_dir))
+                            .unwrap_or(true)
+                });
+                if let Some((_, _, rule)) = camera_rule {
+                    return rule;
+                }
+            }
+        }
+        self.for_dir(dir)
+    }
+}
+
+fn string_set<S: AsRef<str>>(values: Option<&[S]>, defaults: &[&str]) -> HashSet<String> {
+    match values {
+        Some(values) => values
+            .iter()
+            .map(|value| value.as_ref().trim_start_matches('.').to_lowercase())
+            .collect(),
+        None => defaults.iter().map(|value| value.to_lowercase()).collect(),
+    }
+}
+
+fn has_extension_in(path: &Path, exts: &HashSet<String>) -> bool {
+    path.extension()
+        .and_then(|os_ext| os_ext.to_str())
+        .map(|ext| exts.contains(&ext.to_lowercase()))
+        .unwrap_or(false)
+}
+
+fn has_name_in(path: &Path, names: &HashSet<String>) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| names.contains(&name.to_lowercase()))
+        .unwrap_or(false)
+}
@@ -0,0 +1,103 @@
+use std::{io, num::ParseIntError, path::PathBuf};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RawPicsError {
+    #[error("Error reading directory `{}`: {}", path.display(), source)]
+    ReadDir { source: io::Error, path: PathBuf },
+
+    #[error("Error reading metadata of `{}`: {}", path.display(), source)]
+    Metadata { source: io::Error, path: PathBuf },
+
+    #[error("Error moving `{}` to the trash: {}", path.display(), source)]
+    Trash { source: trash::Error, path: PathBuf },
+
+    #[error("Error deleting `{}`: {}", path.display(), source)]
+    Remove { source: io::Error, path: PathBuf },
+
+    #[error("Error moving `{}` to `{}`: {}", from.display(), to.display(), source)]
+    Move {
+        source: io::Error,
+        from: PathBuf,
+        to: PathBuf,
+    },
+
+    #[error("Could not move `{}` since `{}` already exists", from.display(), to.display())]
+    MoveTargetExists { from: PathBuf, to: PathBuf },
+
+    #[error("Invalid rating in sidecar: {}", source)]
+    SidecarRating { source: ParseIntError },
+
+    #[error("Error hashing `{}`: {}", path.display(), source)]
+    Hash { source: io::Error, path: PathBuf },
+
+    #[error("Error writing journal `{}`: {}", path.display(), source)]
+    WriteJournal { source: io::Error, path: PathBuf },
+
+    #[error("Error reading journal `{}`: {}", path.display(), source)]
+    ReadJournal { source: io::Error, path: PathBuf },
+
+    #[error("Invalid journal entry in `{}`: {}", path.display(), source)]
+    ParseJournal {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[error("Journal entry `{}` does not match: {}", path.display(), reason)]
+    JournalMismatch { path: PathBuf, reason: String },
+
+    #[error("Error reading config `{}`: {}", path.display(), source)]
+    ReadConfig { source: io::Error, path: PathBuf },
+
+    #[error("Invalid config `{}`: {}", path.display(), source)]
+    ParseConfig {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+
+    #[error("Error watching `{}`: {}", path.display(), source)]
+    Watch {
+        source: notify::Error,
+        path: PathBuf,
+    },
+
+    #[error(
+        "Refusing to delete {} of {} raws, more than the maximum ratio of {}",
+        count,
+        total,
+        max_ratio
+    )]
+    DeleteRatioExceeded {
+        count: usize,
+        total: usize,
+        max_ratio: f64,
+    },
+
+    #[error("Invalid exclude pattern `{}`: {}", pattern, source)]
+    InvalidExclude {
+        source: globset::Error,
+        pattern: String,
+    },
+
+    #[error("Error writing report: {}", source)]
+    WriteReport { source: io::Error },
+
+    #[error("Error writing json report: {}", source)]
+    WriteJsonReport { source: serde_json::Error },
+
+    #[error("Error writing csv report: {}", source)]
+    WriteCsvReport { source: csv::Error },
+
+    #[error("Error writing export `{}`: {}", path.display(), source)]
+    WriteExport { source: io::Error, path: PathBuf },
+
+    #[error("Error writing json export `{}`: {}", path.display(), source)]
+    WriteJsonExport {
+        source: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[error("Error writing csv export `{}`: {}", path.display(), source)]
+    WriteCsvExport { source: csv::Error, path: PathBuf },
+}
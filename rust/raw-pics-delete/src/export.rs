@@ -0,0 +1,127 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::config::Rules;
+use crate::error::RawPicsError;
+use crate::scan::{match_key, Scan};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Paired,
+    OrphanRaw,
+    OrphanJpg,
+}
+
+/// A raw and jpg sharing a stem in the same shoot, or a file without a counterpart
+#[derive(Debug, Serialize)]
+pub struct Pair {
+    pub stem: String,
+    pub raw_path: Option<PathBuf>,
+    pub raw_size: Option<u64>,
+    pub jpg_path: Option<PathBuf>,
+    pub jpg_size: Option<u64>,
+    pub status: Status,
+}
+
+impl Pair {
+    fn new(stem: &OsString, raw: Option<&Path>, jpg: Option<&Path>) -> Pair {
+        let status = match (raw, jpg) {
+            (Some(_), None) => Status::OrphanRaw,
+            (None, Some(_)) => Status::OrphanJpg,
+            _ => Status::Paired,
+        };
+        Pair {
+            stem: stem.to_string_lossy().into_owned(),
+            raw_path: raw.map(Path::to_path_buf),
+            raw_size: raw.and_then(file_size),
+            jpg_path: jpg.map(Path::to_path_buf),
+            jpg_size: jpg.and_then(file_size),
+            status,
+        }
+    }
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    fs::metadata(path).map(|metadata| metadata.len()).ok()
+}
+
+/// Raws and jpgs sharing a match key
+type Group<'a> = (Vec<&'a Path>, Vec<&'a Path>);
+
+/// Pairs the raws and jpgs of the scan by shoot and stem, sorted by shoot and stem.
+/// A stem with several raws or jpgs, such as a jpg and a heic, gets a row per combination.
+pub fn pairs(scan: &Scan, rules: &Rules) -> Vec<Pair> {
+    let mut by_key: BTreeMap<(PathBuf, OsString), Group> = BTreeMap::new();
+    for raw in &scan.raws {
+        by_key.entry(match_key(raw, rules)).or_default().0.push(raw);
+    }
+    for jpg in &scan.jpgs {
+        by_key.entry(match_key(jpg, rules)).or_default().1.push(jpg);
+    }
+
+    let mut pairs = Vec::new();
+    for ((_, stem), (mut raws, mut jpgs)) in by_key {
+        raws.sort();
+        jpgs.sort();
+        match (raws.is_empty(), jpgs.is_empty()) {
+            (false, true) => {
+                pairs.extend(raws.iter().map(|raw| Pair::new(&stem, Some(*raw), None)))
+            }
+            (true, false) => {
+                pairs.extend(jpgs.iter().map(|jpg| Pair::new(&stem, None, Some(*jpg))))
+            }
+            _ => {
+                for raw in &raws {
+                    for jpg in &jpgs {
+                        pairs.push(Pair::new(&stem, Some(*raw), Some(*jpg)));
+                    }
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Writes the pairs to `path`, as JSON when its extension is `json` and as CSV otherwise
+pub fn write_pairs(pairs: &[Pair], path: &Path) -> Result<(), RawPicsError> {
+    let write_err = |e| RawPicsError::WriteExport {
+        source: e,
+        path: path.to_path_buf(),
+    };
+    let file = File::create(path).map_err(write_err)?;
+    let is_json = path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+
+    if is_json {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, pairs).map_err(|e| {
+            RawPicsError::WriteJsonExport {
+                source: e,
+                path: path.to_path_buf(),
+            }
+        })?;
+        writeln!(writer).map_err(write_err)?;
+        writer.flush().map_err(write_err)
+    } else {
+        let mut writer = csv::Writer::from_writer(file);
+        for pair in pairs {
+            writer
+                .serialize(pair)
+                .map_err(|e| RawPicsError::WriteCsvExport {
+                    source: e,
+                    path: path.to_path_buf(),
+                })?;
+        }
+        writer.flush().map_err(write_err)
+    }
+}
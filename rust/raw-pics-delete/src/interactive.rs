@@ -0,0 +1,53 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::capture::capture_time;
+use crate::report::format_size;
+
+#[derive(Debug, PartialEq)]
+pub enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Shows a preview of the raw and its companions and asks whether they should be deleted
+pub fn confirm(raw: &Path, companions: &[PathBuf]) -> Answer {
+    let size = fs::metadata(raw)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let captured = capture_time(raw)
+        .map(|time| time.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("{}", raw.display());
+    println!("  captured: {}", captured);
+    println!("  size:     {}", format_size(size));
+    for companion in companions {
+        println!("  with:     {}", companion.display());
+    }
+    loop {
+        print!("Delete? [y]es/[n]o/[a]ll/[q]uit: ");
+        io::stdout().flush().expect("failed flushing stdout");
+
+        let mut line = String::new();
+        let read = io::stdin()
+            .read_line(&mut line)
+            .expect("failed reading stdin");
+        if read == 0 {
+            return Answer::Quit;
+        }
+
+        match line.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Answer::Yes,
+            "n" | "no" => return Answer::No,
+            "a" | "all" => return Answer::All,
+            "q" | "quit" => return Answer::Quit,
+            _ => println!("Please answer y, n, a or q"),
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::RawPicsError;
+use crate::remove::move_file;
+
+/// A file removed by a run. Files moved with `--move-to` also record where they were moved.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    pub moved_to: Option<PathBuf>,
+}
+
+impl JournalEntry {
+    /// Reads the size and hash of a file about to be removed
+    pub fn for_file(path: &Path) -> Result<JournalEntry, RawPicsError> {
+        let size = fs::metadata(path)
+            .map_err(|e| RawPicsError::Metadata {
+                source: e,
+                path: path.to_path_buf(),
+            })?
+            .len();
+        Ok(JournalEntry {
+            path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            size,
+            sha256: hash_file(path)?,
+            moved_to: None,
+        })
+    }
+}
+
+/// Journal of removed files, written as one JSON entry per line and flushed after
+/// every entry so that it stays accurate if the run is interrupted
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    pub fn create(path: &Path) -> Result<Journal, RawPicsError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| RawPicsError::WriteJournal {
+                source: e,
+                path: path.to_path_buf(),
+            })?;
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), RawPicsError> {
+        let write_err = |e| RawPicsError::WriteJournal {
+            source: e,
+            path: self.path.clone(),
+        };
+        let line = serde_json::to_string(entry).map_err(|e| RawPicsError::ParseJournal {
+            source: e,
+            path: self.path.clone(),
+        })?;
+        writeln!(self.file, "{}", line).map_err(write_err)?;
+        self.file.flush().map_err(write_err)
+    }
+}
+
+pub fn read_journal(path: &Path) -> Result<Vec<JournalEntry>, RawPicsError> {
+    let file = File::open(path).map_err(|e| RawPicsError::ReadJournal {
+        source: e,
+        path: path.to_path_buf(),
+    })?;
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| RawPicsError::ReadJournal {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| RawPicsError::ParseJournal {
+            source: e,
+            path: path.to_path_buf(),
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Checks that every file in the journal is gone from its original location, and that moved
+/// files are still intact where they were moved to
+pub fn verify(journal_path: &Path) -> Vec<RawPicsError> {
+    let entries = match read_journal(journal_path) {
+        Ok(entries) => entries,
+        Err(e) => return vec![e],
+    };
+
+    let mut errors = Vec::new();
+    for entry in &entries {
+        match verify_entry(entry) {
+            Ok(()) => println!("ok       {}", entry.path.display()),
+            Err(e) => {
+                println!("FAILED   {}", entry.path.display());
+                errors.push(e);
+            }
+        }
+    }
+    println!(
+        "\n{} of {} entries verified",
+        entries.len() - errors.len(),
+        entries.len()
+    );
+    errors
+}
+
+fn verify_entry(entry: &JournalEntry) -> Result<(), RawPicsError> {
+    if entry.path.exists() {
+        return Err(journal_mismatch(entry, "file still exists"));
+    }
+    if let Some(moved_to) = &entry.moved_to {
+        verify_hash(entry, moved_to)?;
+    }
+    Ok(())
+}
+
+/// Moves the files recorded in the journal back to their original location
+pub fn undo(journal_path: &Path) -> Vec<RawPicsError> {
+    let entries = match read_journal(journal_path) {
+        Ok(entries) => entries,
+        Err(e) => return vec![e],
+    };
+
+    let mut errors = Vec::new();
+    for entry in &entries {
+        let result = match &entry.moved_to {
+            Some(moved_to) => verify_hash(entry, moved_to).and_then(|_| {
+                debug!(
+                    "Restoring {} to {}",
+                    moved_to.display(),
+                    entry.path.display()
+                );
+                move_file(moved_to, &entry.path)
+            }),
+            None => Err(journal_mismatch(
+                entry,
+                "file was permanently deleted and cannot be restored",
+            )),
+        };
+        match result {
+            Ok(()) => println!("restored {}", entry.path.display()),
+            Err(e) => errors.push(e),
+        }
+    }
+    errors
+}
+
+fn verify_hash(entry: &JournalEntry, path: &Path) -> Result<(), RawPicsError> {
+    if !path.exists() {
+        return Err(journal_mismatch(
+            entry,
+            &format!("moved file {} is missing", path.display()),
+        ));
+    }
+    if hash_file(path)? != entry.sha256 {
+        return Err(journal_mismatch(
+            entry,
+            &format!("moved file {} has a different hash", path.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn journal_mismatch(entry: &JournalEntry, reason: &str) -> RawPicsError {
+    RawPicsError::JournalMismatch {
+        path: entry.path.clone(),
+        reason: reason.to_string(),
+    }
+}
+
+pub fn hash_file(path: &Path) -> Result<String, RawPicsError> {
+    let hash_err = |e| RawPicsError::Hash {
+        source: e,
+        path: path.to_path_buf(),
+    };
+    let mut file = File::open(path).map_err(hash_err)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(hash_err)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Default location of the journal, in the current directory
+pub fn default_journal_path() -> PathBuf {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System time before UNIX EPOCH!")
+        .as_secs();
+    PathBuf::from(format!("raw-pics-delete-{}.journal", timestamp))
+}
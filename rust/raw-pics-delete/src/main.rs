@@ -1,158 +1,549 @@
-#[macro_use]
-extern crate log;
-extern crate env_logger;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
 
-#[macro_use]
-extern crate failure;
-extern crate clap;
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::debug;
+use rayon::ThreadPoolBuilder;
 
-use clap::{App, Arg, SubCommand};
-use failure::Error;
+mod capture;
+mod config;
+mod error;
+mod export;
+mod interactive;
+mod journal;
+mod quality;
+mod remove;
+mod report;
+mod scan;
+mod sidecar;
+mod watch;
 
-use std::collections::HashSet;
-use std::fs::{self, DirEntry};
-use std::path::{Path, PathBuf};
+use config::{Config, RuleConfig, Rules};
+use error::RawPicsError;
+use interactive::Answer;
+use journal::{Journal, JournalEntry};
+use remove::Removal;
+use report::{OutputFormat, Report, Section};
+use scan::{Filter, Scan};
+
+/// Exit code when there was nothing to delete, or everything was deleted
+const EXIT_OK: i32 = 0;
+/// Exit code when files without a counterpart were found and reported without deleting them
+const EXIT_FOUND: i32 = 1;
+/// Exit code when errors were encountered
+const EXIT_ERRORS: i32 = 2;
 
 fn main() {
     env_logger::init();
-    let matches = App::new("Delete raws of photos")
-                          .version("1.0")
-                          .author("Jonathan Fok kan <jfokkan@gmail.com>")
-                          .about("Deletes the .RAF files without corresponding JPG file")
-                          .arg(Arg::with_name("DIR")
-                               .help("Sets the input directory to use")
-                               .required(true)
-                               .index(1))
-                          .arg(Arg::with_name("delete")
-                               .short("d")
-                               .long("delete")
-                               .help("Sets whether to delete the raw files. Otherwise the default behaviour is to print the files without deleting"))
-                          .get_matches();
-
-    let dir = matches.value_of("DIR").unwrap();
-    debug!("Deleting raws from {}", dir);
-    let delete = matches.occurrences_of("delete") > 0;
-    debug!("Delete enabled {}", delete);
-
-    let extra_raws = read_dir_for_extra_raws(dir);
-
-    if delete {
-        for raw in extra_raws {
-            debug!("Deleting {}", raw.display());
-            fs::remove_file(raw).expect("failed to delete file");
-        }
+
+    let cli_args = CliArgs::parse();
+    if cli_args.delete && cli_args.mode != Mode::ExtraRaws {
+        CliArgs::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--delete can only be used with --mode extra-raws",
+            )
+            .exit();
+    }
+
+    ThreadPoolBuilder::new()
+        .num_threads(cli_args.jobs)
+        .build_global()
+        .expect("failed building thread pool");
+
+    let outcome = if let Some(Command::Undo {
+        journal: journal_path,
+    }) = &cli_args.command
+    {
+        Outcome::from_errors(journal::undo(journal_path))
+    } else if let Some(journal_path) = &cli_args.verify_log {
+        Outcome::from_errors(journal::verify(journal_path))
     } else {
-        for raw in extra_raws {
-            println!("{}", raw.display());
+        run(cli_args)
+    };
+    if !outcome.errors.is_empty() {
+        eprintln!("\nErrors encountered:");
+        for error in &outcome.errors {
+            eprintln!("{}", error);
         }
+        process::exit(EXIT_ERRORS);
+    }
+    if outcome.reported > 0 {
+        process::exit(EXIT_FOUND);
     }
+    process::exit(EXIT_OK);
 }
 
-fn read_dir_for_extra_raws<P: AsRef<Path>>(path: P) -> Vec<PathBuf> {
-    let mut jpgs = HashSet::new();
-    let mut raws = HashSet::new();
-    let entries_iter =
-        fs::read_dir(&path).expect(&format!("failed reading path: {}", path.as_ref().display()));
-
-    for entry in entries_iter {
-        let entry = entry.expect("failed entry");
-
-        let path = entry.path();
-        if path.is_dir() {
-            if is_jpg_dir(&path) {
-                add_jpg_files(&path, &mut jpgs);
-            } else if is_raw_dir(&path) {
-                add_raw_files(&path, &mut raws);
-            }
+/// The number of files reported without being deleted and the errors encountered by a run
+#[derive(Default)]
+struct Outcome {
+    reported: usize,
+    errors: Vec<RawPicsError>,
+}
+
+impl Outcome {
+    fn from_errors(errors: Vec<RawPicsError>) -> Outcome {
+        Outcome {
+            reported: 0,
+            errors,
         }
     }
+}
 
-    add_jpg_files(path.as_ref(), &mut jpgs);
-    add_raw_files(path.as_ref(), &mut raws);
+#[derive(Debug, Parser)]
+#[command(
+    name = "raw-pics-delete",
+    version,
+    author = "Jonathan Fok kan <jfokkan@gmail.com>"
+)]
+#[command(about = "Deletes the raw files without corresponding JPG file", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    find_extra_raw_files(&jpgs, &raws)
-}
+    /// Sets the input directory to use
+    #[arg(required_unless_present = "verify_log")]
+    dir: Option<PathBuf>,
 
-fn find_extra_raw_files(jpgs: &HashSet<PathBuf>, raws: &HashSet<PathBuf>) -> Vec<PathBuf> {
-    //let jpgs_stripped: HashSet<PathBuf> = jpgs.iter().map(|path| strip_extension(path)).collect();
-    let mut extra_raws = Vec::new();
+    /// Sets whether to delete the raw files. Otherwise the default behaviour is to print the files without deleting
+    #[arg(short, long)]
+    delete: bool,
 
-    for raw in raws.into_iter() {
-        if !does_raw_have_corresponding_jpg(raw, &jpgs) {
-            extra_raws.push(raw.clone());
-        }
-    }
+    /// Show a preview of each raw file and ask before deleting it
+    #[arg(short, long, requires = "delete")]
+    interactive: bool,
 
-    extra_raws
-}
+    /// Permanently delete the raw files instead of moving them to the trash
+    #[arg(long, requires = "delete", conflicts_with = "move_to")]
+    permanent: bool,
 
-fn does_raw_have_corresponding_jpg(raw: &PathBuf, jpgs: &HashSet<PathBuf>) -> bool {
-    jpgs.iter().any(|jpg| jpg.file_stem() == raw.file_stem())
-}
+    /// Move the raw files into this directory instead of the trash, preserving their path relative to DIR
+    #[arg(long, requires = "delete")]
+    move_to: Option<PathBuf>,
+
+    /// Never touch raws modified more recently than this, e.g. 7d or 12h
+    #[arg(long, value_parser = humantime::parse_duration)]
+    min_age: Option<Duration>,
+
+    /// Abort without deleting anything if more than this fraction of all raws would be deleted
+    #[arg(long, value_name = "RATIO", requires = "delete")]
+    max_delete_ratio: Option<f64>,
+
+    /// Where to write the journal of permanently deleted or moved files.
+    /// Defaults to raw-pics-delete-<timestamp>.journal in the current directory
+    #[arg(long, requires = "delete")]
+    journal: Option<PathBuf>,
+
+    /// Check that the files recorded in a journal were removed, and that moved files are intact
+    #[arg(long, value_name = "JOURNAL", conflicts_with = "delete")]
+    verify_log: Option<PathBuf>,
+
+    /// Keep raws without jpgs whose XMP sidecar has a pick flag or a rating of at least --min-rating
+    #[arg(long)]
+    respect_ratings: bool,
+
+    /// Minimum sidecar star rating for a raw to be kept. Defaults to 1
+    #[arg(long, allow_negative_numbers = true)]
+    min_rating: Option<i32>,
+
+    /// Config file with per-directory and per-camera rules.
+    /// Defaults to ~/.config/raw-pics-delete/config.toml when it exists
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Only pair a raw with a jpg of the same name if their EXIF capture times are within this many seconds,
+    /// to catch cameras reusing file names
+    #[arg(long, value_name = "SECONDS")]
+    match_capture_time: Option<i64>,
+
+    /// Whether to look for raws without jpgs, jpgs without raws, or both. Only extra raws can be deleted
+    #[arg(short, long, value_enum, default_value_t = Mode::ExtraRaws)]
+    mode: Mode,
+
+    /// Format of the report printed when not deleting
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Maximum depth of subdirectories to descend into. Unlimited by default
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Only look at the input directory and its immediate jpg and raw subdirectories
+    #[arg(long, conflicts_with = "max_depth")]
+    flat: bool,
+
+    /// Keep running and re-check the directory whenever new files appear in it
+    #[arg(short, long, conflicts_with = "interactive")]
+    watch: bool,
+
+    /// Seconds without new files to wait for before re-checking in watch mode
+    #[arg(long, default_value_t = 10, value_name = "SECONDS")]
+    debounce: u64,
+
+    /// Glob patterns of files and directories to skip, matched against their name
+    /// and their path relative to DIR
+    #[arg(short, long)]
+    exclude: Vec<String>,
+
+    /// Also scan hidden directories and NAS metadata directories such as @eaDir
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// Only print the number of files found, or deleted when deleting
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Number of threads used to scan directories. Defaults to the number of CPUs
+    #[arg(short, long, default_value_t = 0)]
+    jobs: usize,
+
+    /// Extensions to treat as raw files, replacing the defaults (raf,cr2,cr3,nef,arw,orf,dng,rw2)
+    #[arg(long, value_delimiter = ',')]
+    raw_ext: Option<Vec<String>>,
+
+    /// Extensions to treat as developed files, replacing the defaults (jpg,jpeg,heic,heif)
+    #[arg(long, value_delimiter = ',')]
+    jpg_ext: Option<Vec<String>>,
 
-fn strip_extension(path: &PathBuf) -> PathBuf {
-    let extension = path
-        .extension()
-        .expect("failed to get extension from path when stripping extension")
-        .to_str()
-        .expect("failed to str");
-    let str_path = path
-        .to_str()
-        .expect("failed path to str")
-        .replace(&format!(".{}", extension), "");
-    Path::new(&str_path).to_path_buf()
+    /// Extensions of the files deleted along with a raw, such as sidecars, replacing
+    /// the defaults (xmp,pp3,dop)
+    #[arg(long, value_delimiter = ',')]
+    companion_ext: Option<Vec<String>>,
+
+    /// Write the pairing status of every raw and jpg found to a file, as JSON when it ends
+    /// in .json and as CSV otherwise
+    #[arg(long, value_name = "FILE")]
+    export: Option<PathBuf>,
+
+    /// Also report the raws whose jpg looks saved at a low quality or resolution, which are
+    /// worth keeping even though they have a jpg
+    #[arg(long, conflicts_with = "delete")]
+    analyze_jpgs: bool,
+
+    /// Jpgs with fewer megapixels than this are considered low resolution when analyzing jpgs
+    #[arg(long, value_name = "MEGAPIXELS", default_value_t = quality::DEFAULT_MIN_JPG_MEGAPIXELS)]
+    min_jpg_megapixels: f64,
+
+    /// Jpgs taking fewer bytes per pixel than this are considered low quality when analyzing
+    /// jpgs. Fine quality usually takes 0.4 or more
+    #[arg(long, value_name = "BYTES", default_value_t = quality::DEFAULT_MIN_JPG_BYTES_PER_PIXEL)]
+    min_jpg_bytes_per_pixel: f64,
 }
 
-fn add_jpg_files(dir_path: &Path, jpgs: &mut HashSet<PathBuf>) {
-    debug!("adding jpg files from {}", dir_path.display());
-    let iter = fs::read_dir(&dir_path).expect("failed reading dir");
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Move the files recorded in a journal written with --move-to back to where they were
+    Undo { journal: PathBuf },
+}
 
-    for entry in iter {
-        let entry = entry.expect("failed entry");
-        let path = entry.path();
-        if !path.is_dir() && is_jpg_file(&path) {
-            jpgs.insert(path);
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    ExtraRaws,
+    ExtraJpgs,
+    Both,
 }
 
-fn add_raw_files(dir_path: &Path, raws: &mut HashSet<PathBuf>) {
-    debug!("adding raw files from {}", dir_path.display());
-    let iter = fs::read_dir(&dir_path).expect("failed reading dir");
+/// Runs the scan and the deletion or report
+fn run(cli_args: CliArgs) -> Outcome {
+    let watch_mode = cli_args.watch;
+    let debounce = Duration::from_secs(cli_args.debounce);
+    let mut run = match Run::new(cli_args) {
+        Ok(run) => run,
+        Err(e) => return Outcome::from_errors(vec![e]),
+    };
 
-    for entry in iter {
-        let entry = entry.expect("failed entry");
-        let path = entry.path();
-        if !path.is_dir() && is_raw_file(&path) {
-            raws.insert(path);
+    let outcome = if watch_mode {
+        let dir = run.dir.clone();
+        Outcome::from_errors(watch::watch(&dir, debounce, || run.execute().errors))
+    } else {
+        run.execute()
+    };
+    if let Some(journal) = &run.journal {
+        if !run.quiet {
+            println!("Journal written to {}", journal.path().display());
         }
     }
+    outcome
 }
 
-fn is_jpg_dir<P: AsRef<Path>>(path: P) -> bool {
-    path.as_ref().ends_with("jpg")
+/// Everything needed to scan the directory and act on the result, resolved from the command line
+struct Run {
+    dir: PathBuf,
+    rules: Rules,
+    filter: Filter,
+    removal: Removal,
+    journal: Option<Journal>,
+    delete: bool,
+    interactive: bool,
+    quiet: bool,
+    mode: Mode,
+    output: OutputFormat,
+    flat: bool,
+    max_depth: Option<usize>,
+    match_capture_time: Option<i64>,
+    min_age: Option<Duration>,
+    max_delete_ratio: Option<f64>,
+    export: Option<PathBuf>,
+    /// Thresholds of the jpgs of the raws to suggest keeping, None to not analyze jpgs
+    jpg_quality: Option<quality::Thresholds>,
 }
 
-fn is_raw_dir<P: AsRef<Path>>(path: P) -> bool {
-    path.as_ref().ends_with("raw")
-}
+impl Run {
+    fn new(cli_args: CliArgs) -> Result<Run, RawPicsError> {
+        let dir = cli_args.dir.expect("DIR is required");
+        debug!("Deleting raws from {}", dir.display());
+        debug!("Delete enabled {}", cli_args.delete);
+        let removal = if let Some(target) = cli_args.move_to {
+            Removal::MoveTo(target)
+        } else if cli_args.permanent {
+            Removal::Permanent
+        } else {
+            Removal::Trash
+        };
+        debug!("Removal mode {:?}", removal);
 
-fn is_jpg_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|os_ext| os_ext.to_str())
-        .map(|ext| {
-            let lower_ext = ext.to_lowercase();
-            lower_ext == "jpg" || lower_ext == "jpeg"
+        let config = Config::load_or_default(cli_args.config.as_deref())?;
+        let overrides = RuleConfig {
+            raw_extensions: cli_args.raw_ext,
+            jpg_extensions: cli_args.jpg_ext,
+            companion_extensions: cli_args.companion_ext,
+            respect_ratings: if cli_args.respect_ratings {
+                Some(true)
+            } else {
+                None
+            },
+            min_rating: cli_args.min_rating,
+            ..RuleConfig::default()
+        };
+        let rules = Rules::new(&config, &dir, &overrides);
+        debug!("Using rules {:?}", rules);
+        let move_to = match &removal {
+            Removal::MoveTo(target) => Some(target.as_path()),
+            _ => None,
+        };
+        let filter = Filter::new(&dir, &cli_args.exclude, !cli_args.include_hidden, move_to)?;
+
+        let journal = match removal {
+            Removal::Permanent | Removal::MoveTo(_) if cli_args.delete => {
+                let journal_path = cli_args
+                    .journal
+                    .unwrap_or_else(journal::default_journal_path);
+                Some(Journal::create(&journal_path)?)
+            }
+            _ => None,
+        };
+
+        Ok(Run {
+            dir,
+            rules,
+            filter,
+            removal,
+            journal,
+            delete: cli_args.delete,
+            interactive: cli_args.interactive,
+            quiet: cli_args.quiet,
+            mode: cli_args.mode,
+            output: cli_args.output,
+            flat: cli_args.flat,
+            max_depth: cli_args.max_depth,
+            match_capture_time: cli_args.match_capture_time,
+            min_age: cli_args.min_age,
+            max_delete_ratio: cli_args.max_delete_ratio,
+            export: cli_args.export,
+            jpg_quality: if cli_args.analyze_jpgs {
+                Some(quality::Thresholds {
+                    min_megapixels: cli_args.min_jpg_megapixels,
+                    min_bytes_per_pixel: cli_args.min_jpg_bytes_per_pixel,
+                })
+            } else {
+                None
+            },
         })
-        .unwrap_or(false)
+    }
+
+    fn execute(&mut self) -> Outcome {
+        let progress = ProgressBar::new_spinner();
+        progress.set_style(
+            ProgressStyle::with_template("{spinner} {pos} directories scanned {wide_msg}")
+                .expect("invalid progress template"),
+        );
+        progress.enable_steady_tick(Duration::from_millis(100));
+        let mut scan = if self.flat {
+            scan::read_dir_files(&self.dir, &self.rules, &self.filter)
+        } else {
+            scan::walk_dir_files(
+                &self.dir,
+                self.max_depth,
+                &self.rules,
+                &self.filter,
+                &progress,
+            )
+        };
+        progress.finish_and_clear();
+        let mut errors = std::mem::take(&mut scan.errors);
+
+        if let Some(export_path) = &self.export {
+            let pairs = export::pairs(&scan, &self.rules);
+            match export::write_pairs(&pairs, export_path) {
+                Ok(()) if !self.quiet && self.output == OutputFormat::Text => {
+                    println!("Pairing status written to {}", export_path.display())
+                }
+                Ok(()) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if self.delete {
+            let extra_raws = self.extra_raws(&scan);
+            if let Some(max_ratio) = self.max_delete_ratio {
+                let total = scan.raws.len();
+                if total > 0 && extra_raws.len() as f64 / total as f64 > max_ratio {
+                    errors.push(RawPicsError::DeleteRatioExceeded {
+                        count: extra_raws.len(),
+                        total,
+                        max_ratio,
+                    });
+                    return Outcome::from_errors(errors);
+                }
+            }
+
+            let mut confirm_all = !self.interactive;
+            let mut deleted = 0;
+            let mut deleted_companions = 0;
+            for raw in extra_raws {
+                let companions = self.companions(&raw);
+                if !confirm_all {
+                    match interactive::confirm(&raw, &companions) {
+                        Answer::Yes => {}
+                        Answer::No => continue,
+                        Answer::All => confirm_all = true,
+                        Answer::Quit => break,
+                    }
+                }
+                debug!("Deleting {}", raw.display());
+                match remove_and_record(&raw, &self.dir, &self.removal, self.journal.as_mut()) {
+                    Ok(()) => deleted += 1,
+                    Err(e) => {
+                        // keep the companions of a raw that could not be removed
+                        errors.push(e);
+                        continue;
+                    }
+                }
+                for companion in companions {
+                    debug!("Deleting companion {}", companion.display());
+                    match remove_and_record(
+                        &companion,
+                        &self.dir,
+                        &self.removal,
+                        self.journal.as_mut(),
+                    ) {
+                        Ok(()) => deleted_companions += 1,
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            if self.quiet {
+                println!("{}", deleted);
+            } else if deleted_companions > 0 {
+                println!(
+                    "Deleted {} raws and {} companion files",
+                    deleted, deleted_companions
+                );
+            }
+            Outcome::from_errors(errors)
+        } else {
+            let mut sections = Vec::new();
+            if self.mode != Mode::ExtraJpgs {
+                let extra_raws = self.extra_raws(&scan);
+                let companions: Vec<PathBuf> = extra_raws
+                    .iter()
+                    .flat_map(|raw| self.companions(raw))
+                    .collect();
+                sections.push(Section {
+                    name: "extra_raws",
+                    title: "Raws without jpgs",
+                    report: Report::new(&extra_raws, &mut errors),
+                });
+                sections.push(Section {
+                    name: "companions",
+                    title: "Companion files of raws without jpgs",
+                    report: Report::new(&companions, &mut errors),
+                });
+            }
+            if self.mode != Mode::ExtraRaws {
+                let mut extra_jpgs = scan::find_unmatched(
+                    &scan.jpgs,
+                    &scan.raws,
+                    &self.rules,
+                    self.match_capture_time,
+                );
+                if let Some(min_age) = self.min_age {
+                    extra_jpgs = scan::older_than(extra_jpgs, min_age);
+                }
+                sections.push(Section {
+                    name: "extra_jpgs",
+                    title: "Jpgs without raws",
+                    report: Report::new(&extra_jpgs, &mut errors),
+                });
+            }
+            if let Some(thresholds) = self.jpg_quality {
+                let pairs = export::pairs(&scan, &self.rules);
+                let keep_raws = quality::raws_with_low_quality_jpgs(&pairs, thresholds);
+                sections.push(Section {
+                    name: "low_quality_jpgs",
+                    title: "Raws to keep since their jpg looks low quality",
+                    report: Report::new(&keep_raws, &mut errors),
+                });
+            }
+            // companions are deleted along with their raw, so they are not counted separately,
+            // and raws with low quality jpgs are only suggestions
+            let reported = sections
+                .iter()
+                .filter(|section| !matches!(section.name, "companions" | "low_quality_jpgs"))
+                .map(|section| section.report.total_files)
+                .sum();
+            if self.quiet {
+                println!("{}", reported);
+            } else if let Err(e) = report::print_sections(&sections, self.output) {
+                errors.push(e);
+            }
+            Outcome { reported, errors }
+        }
+    }
+
+    fn companions(&self, raw: &Path) -> Vec<PathBuf> {
+        self.rules.for_raw(raw).formats.companions(raw)
+    }
+
+    fn extra_raws(&self, scan: &Scan) -> Vec<PathBuf> {
+        let extra_raws = scan::find_extra_raws(scan, &self.rules, self.match_capture_time);
+        match self.min_age {
+            Some(min_age) => scan::older_than(extra_raws, min_age),
+            None => extra_raws,
+        }
+    }
 }
 
-fn is_raw_file(path: &Path) -> bool {
-    path.extension()
-        .and_then(|os_ext| os_ext.to_str())
-        .map(|ext| {
-            let lower_ext = ext.to_lowercase();
-            lower_ext == "raf"
-        })
-        .unwrap_or(false)
+/// Removes the raw, first recording its size and hash in the journal when there is one
+fn remove_and_record(
+    raw: &Path,
+    dir: &Path,
+    removal: &Removal,
+    journal: Option<&mut Journal>,
+) -> Result<(), RawPicsError> {
+    match journal {
+        Some(journal) => {
+            let mut entry = JournalEntry::for_file(raw)?;
+            let moved_to = remove::remove_raw(raw, dir, removal)?;
+            entry.moved_to = moved_to.map(|path| fs::canonicalize(&path).unwrap_or(path));
+            journal.record(&entry)
+        }
+        None => remove::remove_raw(raw, dir, removal).map(|_| ()),
+    }
 }
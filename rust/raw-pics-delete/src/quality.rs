@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use rayon::prelude::*;
+
+use crate::export::{Pair, Status};
+
+/// Megapixels below which a jpg is likely a reduced size one, such as the S or M sizes of cameras
+pub const DEFAULT_MIN_JPG_MEGAPIXELS: f64 = 8.0;
+/// Bytes per pixel below which a jpg is likely compressed at a normal or basic quality rather
+/// than fine, which usually takes 0.4 or more
+pub const DEFAULT_MIN_JPG_BYTES_PER_PIXEL: f64 = 0.3;
+
+/// Below either of these, the jpg of a raw is too small to replace it
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub min_megapixels: f64,
+    pub min_bytes_per_pixel: f64,
+}
+
+/// Returns the raws of the pairs whose jpg looks saved at a low quality or resolution, which
+/// are worth keeping even though they have a jpg. Jpgs whose dimensions can't be read, such as
+/// heic files, are assumed to be fine.
+pub fn raws_with_low_quality_jpgs(pairs: &[Pair], thresholds: Thresholds) -> Vec<PathBuf> {
+    pairs
+        .par_iter()
+        .filter(|pair| pair.status == Status::Paired)
+        .filter_map(
+            |pair| match (&pair.raw_path, &pair.jpg_path, pair.jpg_size) {
+                (Some(raw), Some(jpg), Some(jpg_size))
+                    if is_low_quality(jpg, jpg_size, thresholds) =>
+                {
+                    Some(raw.clone())
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+fn is_low_quality(jpg: &Path, size: u64, thresholds: Thresholds) -> bool {
+    let (width, height) = match jpeg_dimensions(jpg) {
+        Some(dimensions) => dimensions,
+        None => return false,
+    };
+    let pixels = f64::from(width) * f64::from(height);
+    if pixels == 0.0 {
+        return false;
+    }
+    let megapixels = pixels / 1_000_000.0;
+    let bytes_per_pixel = size as f64 / pixels;
+    debug!(
+        "{} is {}x{} ({:.1}MP) at {:.2} bytes per pixel",
+        jpg.display(),
+        width,
+        height,
+        megapixels,
+        bytes_per_pixel
+    );
+    megapixels < thresholds.min_megapixels || bytes_per_pixel < thresholds.min_bytes_per_pixel
+}
+
+/// Returns the width and height of the JPEG from its frame header
+fn jpeg_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let file = File::open(path).ok()?;
+    read_jpeg_dimensions(&mut BufReader::new(file))
+}
+
+/// Reads the segments of the JPEG up to its frame header. The thumbnail embedded in the EXIF
+/// data is skipped along with the rest of its APP1 segment.
+fn read_jpeg_dimensions<R: Read>(reader: &mut R) -> Option<(u32, u32)> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker).ok()?;
+    if marker != [0xFF, 0xD8] {
+        return None;
+    }
+    loop {
+        reader.read_exact(&mut marker).ok()?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+        // Markers can be preceded by any number of fill bytes
+        let mut kind = marker[1];
+        while kind == 0xFF {
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte).ok()?;
+            kind = byte[0];
+        }
+        match kind {
+            // Markers without a segment
+            0x01 | 0xD0..=0xD8 => continue,
+            // The image ended or its data started without a frame header
+            0xD9 | 0xDA => return None,
+            _ => {}
+        }
+
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length).ok()?;
+        let length = usize::from(u16::from_be_bytes(length));
+        if length < 2 {
+            return None;
+        }
+        let mut segment = vec![0u8; length - 2];
+        reader.read_exact(&mut segment).ok()?;
+
+        // Start of frame markers, except DHT, JPG and DAC sharing their range
+        if matches!(kind, 0xC0..=0xCF) && !matches!(kind, 0xC4 | 0xC8 | 0xCC) {
+            if segment.len() < 5 {
+                return None;
+            }
+            let height = u16::from_be_bytes([segment[1], segment[2]]);
+            let width = u16::from_be_bytes([segment[3], segment[4]]);
+            return Some((u32::from(width), u32::from(height)));
+        }
+    }
+}
+
+#[test]
+fn test_read_jpeg_dimensions() {
+    let jpeg = [
+        0xFF, 0xD8, // start of image
+        0xFF, 0xE1, 0x00, 0x0B, // APP1 holding a thumbnail frame header to skip
+        0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0xA0, 0x00, 0x78, //
+        0xFF, 0xFF, 0xC2, 0x00, 0x0B, // progressive frame header after a fill byte
+        0x08, 0x0F, 0xA0, 0x17, 0x70, 0x03, 0x01, 0x22, 0x00, //
+        0xFF, 0xDA, // start of scan
+    ];
+
+    assert_eq!(read_jpeg_dimensions(&mut &jpeg[..]), Some((6000, 4000)));
+    assert_eq!(read_jpeg_dimensions(&mut &b"\x89PNG\r\n"[..]), None);
+}
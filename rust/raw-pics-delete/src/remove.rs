@@ -0,0 +1,66 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::RawPicsError;
+
+#[derive(Debug)]
+pub enum Removal {
+    Trash,
+    Permanent,
+    MoveTo(PathBuf),
+}
+
+/// Removes the raw, returning where it was moved to when moving it out of the way
+pub fn remove_raw(
+    raw: &Path,
+    root: &Path,
+    removal: &Removal,
+) -> Result<Option<PathBuf>, RawPicsError> {
+    match removal {
+        Removal::Trash => trash::delete(raw)
+            .map(|_| None)
+            .map_err(|e| RawPicsError::Trash {
+                source: e,
+                path: raw.to_path_buf(),
+            }),
+        Removal::Permanent => {
+            fs::remove_file(raw)
+                .map(|_| None)
+                .map_err(|e| RawPicsError::Remove {
+                    source: e,
+                    path: raw.to_path_buf(),
+                })
+        }
+        Removal::MoveTo(target_dir) => {
+            let relative = raw.strip_prefix(root).unwrap_or(raw);
+            let target = target_dir.join(relative);
+            move_file(raw, &target)?;
+            Ok(Some(target))
+        }
+    }
+}
+
+/// Renames the file, falling back to copy and delete when the target is on another filesystem
+pub fn move_file(from: &Path, to: &Path) -> Result<(), RawPicsError> {
+    let move_err = |e| RawPicsError::Move {
+        source: e,
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+    };
+    if to.exists() {
+        return Err(RawPicsError::MoveTargetExists {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        });
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).map_err(move_err)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).map_err(move_err)?;
+        fs::remove_file(from).map_err(move_err)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::RawPicsError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirSummary {
+    pub path: PathBuf,
+    pub count: usize,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub files: Vec<FileEntry>,
+    pub directories: Vec<DirSummary>,
+    pub total_files: usize,
+    pub total_bytes: u64,
+}
+
+impl Report {
+    /// Builds the report of the given files. Files whose size cannot be read are
+    /// left out of the report and their errors added to `errors`.
+    pub fn new(paths: &[PathBuf], errors: &mut Vec<RawPicsError>) -> Report {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            match fs::metadata(path) {
+                Ok(metadata) => files.push(FileEntry {
+                    path: path.clone(),
+                    size: metadata.len(),
+                }),
+                Err(e) => errors.push(RawPicsError::Metadata {
+                    source: e,
+                    path: path.clone(),
+                }),
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut by_dir: BTreeMap<PathBuf, (usize, u64)> = BTreeMap::new();
+        for file in &files {
+            let dir = file
+                .path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_path_buf();
+            let summary = by_dir.entry(dir).or_insert((0, 0));
+            summary.0 += 1;
+            summary.1 += file.size;
+        }
+        let directories = by_dir
+            .into_iter()
+            .map(|(path, (count, size))| DirSummary { path, count, size })
+            .collect();
+
+        Report {
+            total_files: files.len(),
+            total_bytes: files.iter().map(|file| file.size).sum(),
+            files,
+            directories,
+        }
+    }
+
+    fn print_text(&self) {
+        for file in &self.files {
+            println!("{:>10}  {}", format_size(file.size), file.path.display());
+        }
+        if self.files.is_empty() {
+            println!("No files found");
+            return;
+        }
+
+        println!();
+        println!("By directory:");
+        for dir in &self.directories {
+            println!(
+                "{:>10}  {:>5} files  {}",
+                format_size(dir.size),
+                dir.count,
+                dir.path.display()
+            );
+        }
+
+        println!();
+        println!(
+            "Total: {} files, {} reclaimable",
+            self.total_files,
+            format_size(self.total_bytes)
+        );
+    }
+}
+
+/// A titled report printed as part of a run
+pub struct Section {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub report: Report,
+}
+
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    section: &'a str,
+    path: &'a Path,
+    size: u64,
+}
+
+pub fn print_sections(sections: &[Section], format: OutputFormat) -> Result<(), RawPicsError> {
+    match format {
+        OutputFormat::Text => {
+            let with_titles = sections.len() > 1;
+            for (i, section) in sections.iter().enumerate() {
+                if with_titles {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}:", section.title);
+                }
+                section.report.print_text();
+            }
+        }
+        OutputFormat::Json => {
+            let mut map = Map::new();
+            for section in sections {
+                let report = serde_json::to_value(&section.report)
+                    .map_err(|e| RawPicsError::WriteJsonReport { source: e })?;
+                map.insert(section.name.to_string(), report);
+            }
+            serde_json::to_writer_pretty(io::stdout(), &Value::Object(map))
+                .map_err(|e| RawPicsError::WriteJsonReport { source: e })?;
+            println!();
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            for section in sections {
+                for file in &section.report.files {
+                    writer
+                        .serialize(CsvRow {
+                            section: section.name,
+                            path: &file.path,
+                            size: file.size,
+                        })
+                        .map_err(|e| RawPicsError::WriteCsvReport { source: e })?;
+                }
+            }
+            writer
+                .flush()
+                .map_err(|e| RawPicsError::WriteReport { source: e })?;
+        }
+    }
+    Ok(())
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
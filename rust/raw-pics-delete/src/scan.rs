@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use rayon::prelude::*;
+
+use crate::capture::capture_time;
+use crate::config::Rules;
+use crate::error::RawPicsError;
+use crate::sidecar;
+
+/// The jpg and raw files found while scanning a directory, along with the errors
+/// encountered on the way
+#[derive(Default)]
+pub struct Scan {
+    pub jpgs: Vec<PathBuf>,
+    pub raws: Vec<PathBuf>,
+    pub errors: Vec<RawPicsError>,
+}
+
+/// Names of the metadata directories created by NAS and photo management software
+const SYSTEM_DIRS: &[&str] = &[
+    "@eaDir",
+    "#recycle",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+];
+
+/// Decides which files and directories are skipped while scanning
+pub struct Filter {
+    root: PathBuf,
+    excludes: GlobSet,
+    skip_hidden: bool,
+    /// Directory raws are moved to when it is inside the scanned directory, as found while
+    /// scanning, so that the raws moved there are not scanned again
+    move_to: Option<PathBuf>,
+}
+
+impl Filter {
+    pub fn new(
+        root: &Path,
+        excludes: &[String],
+        skip_hidden: bool,
+        move_to: Option<&Path>,
+    ) -> Result<Filter, RawPicsError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in excludes {
+            let glob = Glob::new(pattern).map_err(|e| RawPicsError::InvalidExclude {
+                source: e,
+                pattern: pattern.clone(),
+            })?;
+            builder.add(glob);
+        }
+        let excludes = builder.build().map_err(|e| RawPicsError::InvalidExclude {
+            source: e,
+            pattern: excludes.join(","),
+        })?;
+        Ok(Filter {
+            root: root.to_path_buf(),
+            excludes,
+            skip_hidden,
+            move_to: move_to.and_then(|move_to| inside_dir(move_to, root)),
+        })
+    }
+
+    /// Returns whether the path is hidden, a system directory, or matches an exclude pattern,
+    /// either by name or by its path relative to the scanned directory
+    fn is_excluded(&self, path: &Path) -> bool {
+        if self.move_to.as_deref() == Some(path) {
+            debug!("skipping move target {}", path.display());
+            return true;
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if self.skip_hidden && (name.starts_with('.') || SYSTEM_DIRS.contains(&name)) {
+            debug!("skipping hidden {}", path.display());
+            return true;
+        }
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let excluded = self.excludes.is_match(name) || self.excludes.is_match(relative);
+        if excluded {
+            debug!("skipping excluded {}", path.display());
+        }
+        excluded
+    }
+}
+
+/// Returns `dir` as it is found while scanning `root` when it is inside it, comparing the
+/// paths with symlinks resolved. `dir` doesn't need to exist yet.
+fn inside_dir(dir: &Path, root: &Path) -> Option<PathBuf> {
+    let root_canonical = fs::canonicalize(root).ok()?;
+    let relative = canonicalize_existing(dir)?
+        .strip_prefix(&root_canonical)
+        .ok()?
+        .to_path_buf();
+    Some(root.join(relative))
+}
+
+/// Canonicalizes the part of the path that exists, appending the rest of it as it is
+fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            return Some(
+                missing
+                    .iter()
+                    .rev()
+                    .fold(canonical, |path, name| path.join(name)),
+            );
+        }
+        missing.push(existing.file_name()?);
+        existing = existing.parent()?;
+        if existing.as_os_str().is_empty() {
+            existing = Path::new(".");
+        }
+    }
+}
+
+impl Scan {
+    fn merge(mut self, mut other: Scan) -> Scan {
+        self.jpgs.append(&mut other.jpgs);
+        self.raws.append(&mut other.raws);
+        self.errors.append(&mut other.errors);
+        self
+    }
+}
+
+pub fn read_dir_files(path: &Path, rules: &Rules, filter: &Filter) -> Scan {
+    let mut scan = Scan::default();
+    let formats = &rules.for_dir(path).formats;
+
+    match fs::read_dir(path) {
+        Ok(entries) => {
+            for entry in entries {
+                match entry {
+                    Ok(entry) => {
+                        let entry_path = entry.path();
+                        let is_shoot_subdir =
+                            formats.is_jpg_dir(&entry_path) || formats.is_raw_dir(&entry_path);
+                        if entry_path.is_dir()
+                            && is_shoot_subdir
+                            && !filter.is_excluded(&entry_path)
+                        {
+                            add_files(&entry_path, rules, filter, &mut scan);
+                        }
+                    }
+                    Err(e) => scan.errors.push(RawPicsError::ReadDir {
+                        source: e,
+                        path: path.to_path_buf(),
+                    }),
+                }
+            }
+        }
+        Err(e) => {
+            scan.errors.push(RawPicsError::ReadDir {
+                source: e,
+                path: path.to_path_buf(),
+            });
+            return scan;
+        }
+    }
+
+    add_files(path, rules, filter, &mut scan);
+
+    scan
+}
+
+/// Recursively scans the directory, reading subdirectories in parallel
+pub fn walk_dir_files(
+    path: &Path,
+    max_depth: Option<usize>,
+    rules: &Rules,
+    filter: &Filter,
+    progress: &ProgressBar,
+) -> Scan {
+    let scan = walk_dir(path, 1, max_depth, rules, filter, progress);
+    debug!(
+        "found {} jpgs and {} raws",
+        scan.jpgs.len(),
+        scan.raws.len()
+    );
+    scan
+}
+
+/// Scans `path` whose entries are at `depth`, where the entries of the root directory are at depth 1
+fn walk_dir(
+    path: &Path,
+    depth: usize,
+    max_depth: Option<usize>,
+    rules: &Rules,
+    filter: &Filter,
+    progress: &ProgressBar,
+) -> Scan {
+    let mut scan = Scan::default();
+    let formats = &rules.for_dir(path).formats;
+    if max_depth
+        .map(|max_depth| depth > max_depth)
+        .unwrap_or(false)
+    {
+        return scan;
+    }
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            scan.errors.push(RawPicsError::ReadDir {
+                source: e,
+                path: path.to_path_buf(),
+            });
+            return scan;
+        }
+    };
+    progress.inc(1);
+    progress.set_message(format!("{}", path.display()));
+
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                scan.errors.push(RawPicsError::ReadDir {
+                    source: e,
+                    path: path.to_path_buf(),
+                });
+                continue;
+            }
+        };
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                scan.errors.push(RawPicsError::Metadata {
+                    source: e,
+                    path: entry.path(),
+                });
+                continue;
+            }
+        };
+
+        let entry_path = entry.path();
+        if filter.is_excluded(&entry_path) {
+            continue;
+        }
+        if file_type.is_dir() {
+            subdirs.push(entry_path);
+        } else if file_type.is_file() {
+            if formats.is_jpg_file(&entry_path) {
+                scan.jpgs.push(entry_path);
+            } else if formats.is_raw_file(&entry_path) {
+                scan.raws.push(entry_path);
+            }
+        }
+    }
+
+    let subdirs_scan = subdirs
+        .par_iter()
+        .map(|subdir| walk_dir(subdir, depth + 1, max_depth, rules, filter, progress))
+        .reduce(Scan::default, Scan::merge);
+    scan.merge(subdirs_scan)
+}
+
+/// Returns the raws without jpgs, leaving out those marked as keepers in their sidecar
+/// when their rule respects ratings
+pub fn find_extra_raws(scan: &Scan, rules: &Rules, capture_tolerance: Option<i64>) -> Vec<PathBuf> {
+    find_unmatched(&scan.raws, &scan.jpgs, rules, capture_tolerance)
+        .into_par_iter()
+        .filter(|raw| match rules.for_raw(raw).min_rating {
+            Some(min_rating) => !sidecar::is_kept(raw, min_rating),
+            None => true,
+        })
+        .collect()
+}
+
+/// Returns the files last modified at least `min_age` ago. Files whose age cannot be
+/// determined are left out so that they are never touched.
+pub fn older_than(files: Vec<PathBuf>, min_age: Duration) -> Vec<PathBuf> {
+    let now = SystemTime::now();
+    files
+        .into_par_iter()
+        .filter(|file| {
+            let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+            match modified {
+                Ok(modified) => now
+                    .duration_since(modified)
+                    .map(|age| age >= min_age)
+                    .unwrap_or(false),
+                Err(e) => {
+                    warn!("failed reading modified time of {}: {}", file.display(), e);
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+/// Returns the files that have no counterpart with the same stem in the same shoot.
+/// When a capture tolerance in seconds is given, a counterpart also needs to have been
+/// captured within that many seconds of the file to count.
+pub fn find_unmatched(
+    files: &[PathBuf],
+    counterparts: &[PathBuf],
+    rules: &Rules,
+    capture_tolerance: Option<i64>,
+) -> Vec<PathBuf> {
+    let mut counterparts_by_key: HashMap<(PathBuf, OsString), Vec<&Path>> = HashMap::new();
+    for counterpart in counterparts {
+        counterparts_by_key
+            .entry(match_key(counterpart, rules))
+            .or_default()
+            .push(counterpart);
+    }
+
+    files
+        .par_iter()
+        .filter(
+            |file| match counterparts_by_key.get(&match_key(file, rules)) {
+                Some(candidates) => match capture_tolerance {
+                    Some(tolerance) => !candidates
+                        .iter()
+                        .any(|candidate| captured_together(file, candidate, tolerance)),
+                    None => false,
+                },
+                None => true,
+            },
+        )
+        .cloned()
+        .collect()
+}
+
+/// Returns whether both files were captured within `tolerance` seconds of each other.
+/// Files without a capture time are assumed to be captured together so that they are kept.
+fn captured_together(file: &Path, other: &Path, tolerance: i64) -> bool {
+    match (capture_time(file), capture_time(other)) {
+        (Some(file_time), Some(other_time)) => {
+            let diff = (file_time - other_time).num_seconds().abs();
+            debug!(
+                "{} and {} captured {}s apart",
+                file.display(),
+                other.display(),
+                diff
+            );
+            diff <= tolerance
+        }
+        _ => true,
+    }
+}
+
+/// Files are keyed by shoot directory and stem so that raws are only
+/// matched against jpgs from the same shoot
+pub fn match_key(path: &Path, rules: &Rules) -> (PathBuf, OsString) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_os_string())
+        .unwrap_or_default();
+    (shoot_dir(path, rules), stem)
+}
+
+/// Returns the directory of the shoot a file belongs to. Files inside `jpg` or `raw`
+/// subdirectories belong to the parent of that subdirectory.
+fn shoot_dir(path: &Path, rules: &Rules) -> PathBuf {
+    let parent = path.parent().unwrap_or_else(|| Path::new(""));
+    let formats = &rules.for_dir(parent).formats;
+    if formats.is_jpg_dir(parent) || formats.is_raw_dir(parent) {
+        parent.parent().unwrap_or(parent).to_path_buf()
+    } else {
+        parent.to_path_buf()
+    }
+}
+
+fn add_files(dir_path: &Path, rules: &Rules, filter: &Filter, scan: &mut Scan) {
+    debug!("adding files from {}", dir_path.display());
+    let formats = &rules.for_dir(dir_path).formats;
+    let entries = match fs::read_dir(dir_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            scan.errors.push(RawPicsError::ReadDir {
+                source: e,
+                path: dir_path.to_path_buf(),
+            });
+            return;
+        }
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                scan.errors.push(RawPicsError::ReadDir {
+                    source: e,
+                    path: dir_path.to_path_buf(),
+                });
+                continue;
+            }
+        };
+        if path.is_dir() || filter.is_excluded(&path) {
+            continue;
+        }
+        if formats.is_jpg_file(&path) {
+            scan.jpgs.push(path);
+        } else if formats.is_raw_file(&path) {
+            scan.raws.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_filter_skips_move_target_inside_root() {
+    let root = std::env::temp_dir().join(format!("raw-pics-delete-filter-{}", std::process::id()));
+    fs::create_dir_all(root.join("shoot")).unwrap();
+
+    let filter = Filter::new(&root, &[], true, Some(&root.join("shoot/../deleted/raws"))).unwrap();
+    assert!(filter.is_excluded(&root.join("deleted/raws")));
+    assert!(!filter.is_excluded(&root.join("deleted")));
+    assert!(!filter.is_excluded(&root.join("shoot")));
+
+    let outside = root.join("..").join("raw-pics-delete-elsewhere");
+    let filter = Filter::new(&root, &[], true, Some(&outside)).unwrap();
+    assert_eq!(filter.move_to, None);
+
+    fs::remove_dir_all(&root).unwrap();
+}
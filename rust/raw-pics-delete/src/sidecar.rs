@@ -0,0 +1,137 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::Regex;
+
+use crate::error::RawPicsError;
+
+lazy_static! {
+    static ref RATING_RE: Regex = Regex::new(r#"xmp:Rating(?:="|>)\s*(-?\d+)"#).unwrap();
+    static ref PICK_RE: Regex = Regex::new(r#"xmpDM:pick(?:="|>)\s*1"#).unwrap();
+}
+
+/// The parts of an XMP sidecar written by Lightroom or Darktable that mark a photo as a keeper
+#[derive(Debug, Default, PartialEq)]
+pub struct Sidecar {
+    pub rating: Option<i32>,
+    pub pick: bool,
+}
+
+impl FromStr for Sidecar {
+    type Err = RawPicsError;
+
+    fn from_str(contents: &str) -> Result<Sidecar, RawPicsError> {
+        let rating = match RATING_RE.captures(contents) {
+            Some(caps) => Some(
+                caps[1]
+                    .parse::<i32>()
+                    .map_err(|e| RawPicsError::SidecarRating { source: e })?,
+            ),
+            None => None,
+        };
+        Ok(Sidecar {
+            rating,
+            pick: PICK_RE.is_match(contents),
+        })
+    }
+}
+
+impl Sidecar {
+    pub fn is_keeper(&self, min_rating: i32) -> bool {
+        self.pick
+            || self
+                .rating
+                .map(|rating| rating >= min_rating)
+                .unwrap_or(false)
+    }
+}
+
+#[test]
+fn test_sidecar_from_str() {
+    let darktable = r#"<rdf:Description xmp:Rating="3" darktable:xmp_version="5"/>"#;
+    let lightroom = r#"<xmp:Rating>-1</xmp:Rating><xmpDM:pick>1</xmpDM:pick>"#;
+
+    assert_eq!(
+        Sidecar::from_str(darktable).unwrap(),
+        Sidecar {
+            rating: Some(3),
+            pick: false
+        }
+    );
+    assert_eq!(
+        Sidecar::from_str(lightroom).unwrap(),
+        Sidecar {
+            rating: Some(-1),
+            pick: true
+        }
+    );
+    assert_eq!(
+        Sidecar::from_str("<x:xmpmeta/>").unwrap(),
+        Sidecar::default()
+    );
+}
+
+/// Finds the sidecar of a raw, either named `<file>.<ext>.xmp` (Darktable) or `<file>.xmp` (Lightroom)
+pub fn find_sidecar(raw: &Path) -> Option<PathBuf> {
+    let mut with_ext = raw.as_os_str().to_os_string();
+    with_ext.push(".xmp");
+    let candidates = vec![
+        PathBuf::from(with_ext),
+        raw.with_extension("xmp"),
+        raw.with_extension("XMP"),
+    ];
+    candidates.into_iter().find(|candidate| candidate.is_file())
+}
+
+/// Finds the files accompanying a raw, named either `<file>.<ext>.<companion ext>` or
+/// `<file>.<companion ext>`
+pub fn find_companions(raw: &Path, exts: &HashSet<String>) -> Vec<PathBuf> {
+    let mut companions = Vec::new();
+    for ext in exts {
+        for ext in [ext.to_lowercase(), ext.to_uppercase()] {
+            let mut with_raw_ext = raw.as_os_str().to_os_string();
+            with_raw_ext.push(".");
+            with_raw_ext.push(&ext);
+            for candidate in [PathBuf::from(with_raw_ext), raw.with_extension(&ext)] {
+                if candidate.is_file() && !companions.contains(&candidate) {
+                    companions.push(candidate);
+                }
+            }
+        }
+    }
+    companions.sort();
+    companions
+}
+
+/// Returns whether the raw has a sidecar marking it as a keeper. Unreadable sidecars are
+/// treated as keepers so that nothing is deleted by mistake.
+pub fn is_kept(raw: &Path, min_rating: i32) -> bool {
+    let sidecar_path = match find_sidecar(raw) {
+        Some(path) => path,
+        None => return false,
+    };
+
+    let contents = match fs::read_to_string(&sidecar_path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            warn!("failed reading sidecar {}: {}", sidecar_path.display(), e);
+            return true;
+        }
+    };
+    match Sidecar::from_str(&contents) {
+        Ok(sidecar) => {
+            debug!("{} has sidecar {:?}", raw.display(), sidecar);
+            sidecar.is_keeper(min_rating)
+        }
+        Err(e) => {
+            warn!("failed reading sidecar {}: {}", sidecar_path.display(), e);
+            true
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+
+use crate::error::RawPicsError;
+
+/// Runs `on_change` once, then again every time new files show up in `dir` and no
+/// further changes happened for the `debounce` duration. Only returns when the watcher fails.
+pub fn watch<F>(dir: &Path, debounce: Duration, mut on_change: F) -> Vec<RawPicsError>
+where
+    F: FnMut() -> Vec<RawPicsError>,
+{
+    let watch_err = |e| RawPicsError::Watch {
+        source: e,
+        path: dir.to_path_buf(),
+    };
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => return vec![watch_err(e)],
+    };
+    if let Err(e) = watcher.watch(dir, RecursiveMode::Recursive) {
+        return vec![watch_err(e)];
+    }
+
+    print_errors(on_change());
+    eprintln!("Watching {} for new files", dir.display());
+    loop {
+        match rx.recv() {
+            Ok(Ok(event)) if is_new_file(&event) => {
+                debug!("new files {:?}", event.paths);
+            }
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => {
+                warn!("watch error: {}", e);
+                continue;
+            }
+            Err(_) => return Vec::new(),
+        }
+
+        // wait for the directory to settle, e.g. while an import is still copying files
+        loop {
+            match rx.recv_timeout(debounce) {
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Vec::new(),
+            }
+        }
+        print_errors(on_change());
+    }
+}
+
+fn is_new_file(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Name(
+                RenameMode::To | RenameMode::Both | RenameMode::Any
+            ))
+    )
+}
+
+fn print_errors(errors: Vec<RawPicsError>) {
+    for error in errors {
+        eprintln!("{}", error);
+    }
+}
@@ -1,11 +1,25 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, SubCommand};
 use walkdir::{DirEntry, WalkDir};
 
+use crate::ParseMode;
+
+mod access;
 mod check;
 mod decrypt;
+mod doctor;
+mod edit;
 mod encrypt;
+mod env;
+mod inventory;
+mod lock;
+mod report;
+mod rotate;
+mod stale;
 
 pub fn run() {
     // TODO: Upgrade to clap 3 to get bash completion generation
@@ -18,6 +32,15 @@ pub fn run() {
                 .short("v")
                 .help("Sets the level of verbosity"),
         )
+        .arg(
+            Arg::with_name("parse-mode")
+                .long("parse-mode")
+                .env("TEXT_CRYPT_PARSE_MODE")
+                .global(true)
+                .possible_values(&["plain", "markdown"])
+                .default_value("plain")
+                .help("How to find the crypt blocks. markdown ignores delimiters in inline code spans, but not in fenced code blocks"),
+        )
         .subcommand(
             SubCommand::with_name("encrypt")
                 .aliases(&["e", "enc"])
@@ -40,6 +63,25 @@ pub fn run() {
                         .short("w")
                         .help("Write the result to the input file")
                         .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("summary")
+                        .long("summary")
+                        .help("Print the counts of files encrypted, skipped and failed. Default with -w, listed with -v")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("note")
+                        .long("note")
+                        .help("Note stored in the clear with the newly encrypted blocks, e.g. who to ask to rotate them")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("lock-timeout")
+                        .long("lock-timeout")
+                        .help("Seconds to wait for files locked by another run before skipping them")
+                        .takes_value(true)
+                        .default_value("5"),
                 ),
         )
         .subcommand(
@@ -62,13 +104,261 @@ pub fn run() {
                 .arg(
                     Arg::with_name("files")
                         .help("Path to the files or directory to encrypt").min_values(0),
+                )
+                .arg(
+                    Arg::with_name("summary")
+                        .long("summary")
+                        .help("Print the counts of files decrypted, skipped and failed. Default with -w, listed with -v")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("force-stdout")
+                        .long("force-stdout")
+                        .help("Print the decrypted files even when stdout is not a terminal")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("raw")
+                        .long("raw")
+                        .help("Print only the decrypted blocks, without file names, to pipe them to another program")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("lock-timeout")
+                        .long("lock-timeout")
+                        .help("Seconds to wait for files locked by another run before skipping them")
+                        .takes_value(true)
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rotate")
+                .about("Re-encrypt the encrypted blocks of files with a new password, writing them back")
+                .arg(
+                    Arg::with_name("password")
+                        .env("PASS")
+                        .short("p")
+                        .required(true)
+                        .help("password the blocks are encrypted with"),
+                )
+                .arg(
+                    Arg::with_name("new-password")
+                        .env("NEW_PASS")
+                        .long("new-password")
+                        .required(true)
+                        .help("password to encrypt the blocks with"),
+                )
+                .arg(
+                    Arg::with_name("files")
+                        .help("Path to the files or directory to rotate").min_values(0),
+                )
+                .arg(
+                    Arg::with_name("lock-timeout")
+                        .long("lock-timeout")
+                        .help("Seconds to wait for files locked by another run before skipping them")
+                        .takes_value(true)
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("env")
+                .about("Print the KEY=value lines of the crypt blocks of a file as export statements")
+                .arg(
+                    Arg::with_name("password")
+                        .env("PASS")
+                        .short("p")
+                        .required(true)
+                        .help("password to be used"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("Path to the file of KEY=value lines")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Run a command with the KEY=value lines of the crypt blocks of a file in its environment")
+                .arg(
+                    Arg::with_name("password")
+                        .env("PASS")
+                        .short("p")
+                        .required(true)
+                        .help("password to be used"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("Path to the file of KEY=value lines")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .help("Command to run, after --")
+                        .multiple(true)
+                        .last(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("access")
+                .about("List, grant or revoke the recipients that can decrypt the encrypted blocks of a file, each with their own password")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Print the recipients that can decrypt each encrypted block, without needing a password")
+                        .arg(
+                            Arg::with_name("file")
+                                .help("Path to the crypt file")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("grant")
+                        .about("Wrap the data keys of the encrypted blocks for a recipient, with a password that can already decrypt them")
+                        .arg(
+                            Arg::with_name("password")
+                                .env("PASS")
+                                .short("p")
+                                .required(true)
+                                .help("password that can decrypt the blocks"),
+                        )
+                        .arg(
+                            Arg::with_name("recipient")
+                                .long("recipient")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Name of the recipient, replacing their key if they already have one"),
+                        )
+                        .arg(
+                            Arg::with_name("recipient-password")
+                                .env("RECIPIENT_PASS")
+                                .long("recipient-password")
+                                .required(true)
+                                .help("password of the recipient"),
+                        )
+                        .arg(
+                            Arg::with_name("block")
+                                .long("block")
+                                .takes_value(true)
+                                .help("Only grant access to this encrypted block, numbered as by access list"),
+                        )
+                        .arg(
+                            Arg::with_name("lock-timeout")
+                                .long("lock-timeout")
+                                .help("Seconds to wait for the file if locked by another run")
+                                .takes_value(true)
+                                .default_value("5"),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .help("Path to the crypt file")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("revoke")
+                        .about("Remove the data keys of a recipient from the encrypted blocks. Rotate the secrets too if the recipient may have kept them")
+                        .arg(
+                            Arg::with_name("recipient")
+                                .long("recipient")
+                                .takes_value(true)
+                                .required(true)
+                                .help("Name of the recipient"),
+                        )
+                        .arg(
+                            Arg::with_name("block")
+                                .long("block")
+                                .takes_value(true)
+                                .help("Only revoke access to this encrypted block, numbered as by access list"),
+                        )
+                        .arg(
+                            Arg::with_name("lock-timeout")
+                                .long("lock-timeout")
+                                .help("Seconds to wait for the file if locked by another run")
+                                .takes_value(true)
+                                .default_value("5"),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .help("Path to the crypt file")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Open an encrypted block of a file in $VISUAL or $EDITOR and encrypt it again once saved")
+                .arg(
+                    Arg::with_name("password")
+                        .env("PASS")
+                        .short("p")
+                        .required(true)
+                        .help("password that can decrypt the block"),
+                )
+                .arg(
+                    Arg::with_name("block")
+                        .long("block")
+                        .takes_value(true)
+                        .help("Encrypted block to edit, numbered as by access list. Defaults to the first one"),
+                )
+                .arg(
+                    Arg::with_name("shred")
+                        .long("shred")
+                        .help("Overwrite the temporary plaintext with random bytes before removing it")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("lock-timeout")
+                        .long("lock-timeout")
+                        .help("Seconds to wait for the file if locked by another run")
+                        .takes_value(true)
+                        .default_value("5"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .help("Path to the crypt file")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the crypto, the environment and the crypt files for problems preventing decryption")
+                .arg(Arg::with_name("files").help("Path to the files or directory to check. Defaults to current directory if none is supplied").min_values(0)),
+        )
+        .subcommand(
+            SubCommand::with_name("inventory")
+                .about("Print the counts of encrypted and unencrypted blocks of crypt files, with the algorithms, nonce sizes and format versions of the encrypted ones")
+                .arg(Arg::with_name("files").help("Path to the files or directory to list. Defaults to current directory if none is supplied").min_values(0))
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the inventory as JSON instead of a table")
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stale")
+                .about("List the encrypted blocks that weren't encrypted or rotated recently, exiting with 1 if there are any")
+                .arg(Arg::with_name("files").help("Path to the files or directory to check. Defaults to current directory if none is supplied").min_values(0))
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .help("Age of the blocks to list, e.g. 90d. Blocks that don't record when they were encrypted are always listed")
+                        .takes_value(true)
+                        .required(true),
                 ),
         )
         .subcommand(
             SubCommand::with_name("check")
                 .aliases(&["c"])
                 .about("Check that no files containing \"BEGIN CRYPT\" are unencrypted")
-                .arg(Arg::with_name("files").help("Path to the files or directory to encrypt. Defaults to current directory if none is supplied").min_values(0)),
+                .arg(Arg::with_name("files").help("Path to the files or directory to encrypt. Defaults to current directory if none is supplied").min_values(0))
+                .arg(
+                    Arg::with_name("summary")
+                        .long("summary")
+                        .help("Print the counts of files checked, skipped and failed. Listed with -v")
+                        .takes_value(false),
+                ),
         );
     let matches = app.clone().get_matches();
 
@@ -80,27 +370,217 @@ pub fn run() {
             .expect("password is required");
         let paths: Vec<_> = enc_matches.values_of("INPUT").unwrap_or_default().collect();
         let write_file = enc_matches.is_present("write");
+        let summary = enc_matches.is_present("summary");
+        let note = enc_matches.value_of("note");
+        let lock_timeout = lock_timeout(enc_matches);
+        let parse_mode = parse_mode(enc_matches);
 
-        encrypt::encrypt_cmd(verbose, password, write_file, paths).expect("encrypt");
+        encrypt::encrypt_cmd(
+            verbose,
+            password,
+            note,
+            write_file,
+            summary,
+            lock_timeout,
+            parse_mode,
+            paths,
+        )
+        .expect("encrypt");
     } else if let Some(dec_matches) = matches.subcommand_matches("decrypt") {
         let password = dec_matches
             .value_of("password")
             .expect("password is required");
         let paths: Vec<_> = dec_matches.values_of("files").unwrap_or_default().collect();
         let write_file = dec_matches.is_present("write");
+        let summary = dec_matches.is_present("summary");
+        let force_stdout = dec_matches.is_present("force-stdout");
+        let raw = dec_matches.is_present("raw");
+        let lock_timeout = lock_timeout(dec_matches);
+        let parse_mode = parse_mode(dec_matches);
 
-        decrypt::decrypt_cmd(verbose, write_file, password, paths).expect("decrypt");
+        decrypt::decrypt_cmd(
+            verbose,
+            write_file,
+            summary,
+            force_stdout,
+            raw,
+            lock_timeout,
+            parse_mode,
+            password,
+            paths,
+        )
+        .expect("decrypt");
+    } else if let Some(rotate_matches) = matches.subcommand_matches("rotate") {
+        let password = rotate_matches
+            .value_of("password")
+            .expect("password is required");
+        let new_password = rotate_matches
+            .value_of("new-password")
+            .expect("new password is required");
+        let paths: Vec<_> = rotate_matches
+            .values_of("files")
+            .unwrap_or_default()
+            .collect();
+        let lock_timeout = lock_timeout(rotate_matches);
+        let parse_mode = parse_mode(rotate_matches);
+
+        rotate::rotate_cmd(
+            verbose,
+            password,
+            new_password,
+            lock_timeout,
+            parse_mode,
+            paths,
+        )
+        .expect("rotate");
+    } else if let Some(env_matches) = matches.subcommand_matches("env") {
+        let password = env_matches
+            .value_of("password")
+            .expect("password is required");
+        let file = env_matches.value_of("file").expect("file is required");
+
+        let parse_mode = parse_mode(env_matches);
+
+        env::env_cmd(password, parse_mode, file).expect("env");
+    } else if let Some(exec_matches) = matches.subcommand_matches("exec") {
+        let password = exec_matches
+            .value_of("password")
+            .expect("password is required");
+        let file = exec_matches.value_of("file").expect("file is required");
+        let command: Vec<_> = exec_matches
+            .values_of("command")
+            .unwrap_or_default()
+            .collect();
+
+        let parse_mode = parse_mode(exec_matches);
+
+        env::exec_cmd(password, parse_mode, file, command).expect("exec");
+    } else if let Some(access_matches) = matches.subcommand_matches("access") {
+        if let Some(list_matches) = access_matches.subcommand_matches("list") {
+            let file = list_matches.value_of("file").expect("file is required");
+            let parse_mode = parse_mode(list_matches);
+            access::list_cmd(parse_mode, file).expect("access list");
+        } else if let Some(grant_matches) = access_matches.subcommand_matches("grant") {
+            let password = grant_matches
+                .value_of("password")
+                .expect("password is required");
+            let recipient = grant_matches
+                .value_of("recipient")
+                .expect("recipient is required");
+            let recipient_password = grant_matches
+                .value_of("recipient-password")
+                .expect("recipient password is required");
+            let block = block_number(grant_matches);
+            let file = grant_matches.value_of("file").expect("file is required");
+            let lock_timeout = lock_timeout(grant_matches);
+            let parse_mode = parse_mode(grant_matches);
+            access::grant_cmd(
+                password,
+                recipient,
+                recipient_password,
+                block,
+                lock_timeout,
+                parse_mode,
+                file,
+            )
+            .expect("access grant");
+        } else if let Some(revoke_matches) = access_matches.subcommand_matches("revoke") {
+            let recipient = revoke_matches
+                .value_of("recipient")
+                .expect("recipient is required");
+            let block = block_number(revoke_matches);
+            let file = revoke_matches.value_of("file").expect("file is required");
+            let lock_timeout = lock_timeout(revoke_matches);
+            let parse_mode = parse_mode(revoke_matches);
+            access::revoke_cmd(recipient, block, lock_timeout, parse_mode, file)
+                .expect("access revoke");
+        }
+    } else if let Some(edit_matches) = matches.subcommand_matches("edit") {
+        let password = edit_matches
+            .value_of("password")
+            .expect("password is required");
+        let block = block_number(edit_matches).unwrap_or(1);
+        let shred = edit_matches.is_present("shred");
+        let file = edit_matches.value_of("file").expect("file is required");
+        let lock_timeout = lock_timeout(edit_matches);
+        let parse_mode = parse_mode(edit_matches);
+        edit::edit_cmd(password, block, shred, lock_timeout, parse_mode, file).expect("edit");
+    } else if let Some(doctor_matches) = matches.subcommand_matches("doctor") {
+        let files: Vec<_> = doctor_matches
+            .values_of("files")
+            .unwrap_or_default()
+            .collect();
+        let parse_mode = parse_mode(doctor_matches);
+        if !doctor::doctor_cmd(parse_mode, files) {
+            std::process::exit(1);
+        }
+    } else if let Some(inventory_matches) = matches.subcommand_matches("inventory") {
+        let files: Vec<_> = inventory_matches
+            .values_of("files")
+            .unwrap_or_default()
+            .collect();
+        let json = inventory_matches.is_present("json");
+        let parse_mode = parse_mode(inventory_matches);
+        inventory::inventory_cmd(json, parse_mode, files).expect("inventory");
+    } else if let Some(stale_matches) = matches.subcommand_matches("stale") {
+        let files: Vec<_> = stale_matches
+            .values_of("files")
+            .unwrap_or_default()
+            .collect();
+        let older_than = humantime::parse_duration(
+            stale_matches
+                .value_of("older-than")
+                .expect("older-than is required"),
+        )
+        .expect("older-than should be a duration like 90d");
+        let parse_mode = parse_mode(stale_matches);
+        if !stale::stale_cmd(older_than, parse_mode, files).expect("stale") {
+            std::process::exit(1);
+        }
     } else if let Some(check_matches) = matches.subcommand_matches("check") {
         let files: Vec<_> = check_matches
             .values_of("files")
             .unwrap_or_default()
             .collect();
-        check::check_cmd(files).expect("check_files");
+        let summary = check_matches.is_present("summary");
+        let parse_mode = parse_mode(check_matches);
+        check::check_cmd(verbose, summary, parse_mode, files).expect("check_files");
     } else {
         app.clone().print_help().expect("print help");
         std::process::exit(1);
     }
 }
+
+fn lock_timeout(matches: &clap::ArgMatches) -> Duration {
+    let seconds = matches
+        .value_of("lock-timeout")
+        .expect("lock-timeout has a default")
+        .parse()
+        .expect("lock-timeout should be a number of seconds");
+    Duration::from_secs(seconds)
+}
+
+fn block_number(matches: &clap::ArgMatches) -> Option<usize> {
+    matches.value_of("block").map(|block| {
+        block
+            .parse()
+            .expect("block should be the number of an encrypted block")
+    })
+}
+
+/// Formats seconds since the unix epoch as an RFC 3339 date in UTC
+fn format_timestamp(seconds: u64) -> String {
+    let time = UNIX_EPOCH + Duration::from_secs(seconds);
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn parse_mode(matches: &clap::ArgMatches) -> ParseMode {
+    match matches.value_of("parse-mode") {
+        Some("markdown") => ParseMode::Markdown,
+        _ => ParseMode::Plain,
+    }
+}
+
 fn walk_dir<P: AsRef<Path>>(
     path: P,
 ) -> walkdir::FilterEntry<walkdir::IntoIter, fn(&DirEntry) -> bool> {
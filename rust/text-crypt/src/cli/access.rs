@@ -0,0 +1,170 @@
+use std::{fs::File, io::Write, path::Path, time::Duration};
+
+use crate::{
+    crypto::{grant, PASSWORD_LEN},
+    error::AccessError,
+    read::read_crypt_file,
+    Block, CryptFile, EncryptedCryptBlock, ParseMode,
+};
+
+use super::lock::FileLock;
+
+/// Name the key of the password blocks were encrypted or rotated with is listed as
+const PASSWORD_RECIPIENT: &str = "default";
+
+/// Prints the recipients that can decrypt each encrypted block of the file, numbered from 1
+pub(crate) fn list_cmd(parse_mode: ParseMode, path: &str) -> Result<(), AccessError> {
+    let path = Path::new(path);
+    let filename = format!("{}", path.display());
+    let contents = read_crypt_file(path)
+        .map_err(|e| AccessError::ReadFile(filename.clone(), e))?
+        .ok_or_else(|| AccessError::NotCryptFile(filename.clone()))?;
+    let crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| AccessError::ParseCryptFile(filename.clone(), e))?;
+
+    for (number, block) in encrypted_blocks(&crypt_file.blocks) {
+        let recipients = match block.wrapped_key {
+            Some(_) => std::iter::once(PASSWORD_RECIPIENT)
+                .chain(
+                    block
+                        .recipients
+                        .iter()
+                        .map(|recipient| recipient.name.as_str()),
+                )
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => format!("{} (format 1, no data key)", PASSWORD_RECIPIENT),
+        };
+        match &block.note {
+            Some(note) => println!("block {} ({}): {}", number, note, recipients),
+            None => println!("block {}: {}", number, recipients),
+        }
+    }
+    Ok(())
+}
+
+/// Wraps the data keys of the encrypted blocks of the file, or only of block `only_block`, for
+/// the recipient. The password only has to decrypt the blocks, whoever it belongs to.
+pub(crate) fn grant_cmd(
+    password: &str,
+    recipient: &str,
+    recipient_password: &str,
+    only_block: Option<usize>,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &str,
+) -> Result<(), AccessError> {
+    if recipient == PASSWORD_RECIPIENT {
+        return Err(AccessError::ReservedRecipient(recipient.to_string()));
+    }
+    if recipient_password.len() != PASSWORD_LEN {
+        return Err(AccessError::RecipientPasswordLength(
+            recipient_password.len(),
+        ));
+    }
+    let granted = modify_blocks(
+        lock_timeout,
+        parse_mode,
+        Path::new(path),
+        only_block,
+        |block| {
+            grant(password, recipient, recipient_password, block)?;
+            Ok(true)
+        },
+    )?;
+    println!(
+        "granted {} access to {} blocks of {}",
+        recipient, granted, path
+    );
+    Ok(())
+}
+
+/// Removes the data keys of the recipient from the encrypted blocks of the file, or only from
+/// block `only_block`
+pub(crate) fn revoke_cmd(
+    recipient: &str,
+    only_block: Option<usize>,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &str,
+) -> Result<(), AccessError> {
+    if recipient == PASSWORD_RECIPIENT {
+        return Err(AccessError::ReservedRecipient(recipient.to_string()));
+    }
+    let revoked = modify_blocks(
+        lock_timeout,
+        parse_mode,
+        Path::new(path),
+        only_block,
+        |block| {
+            let before = block.recipients.len();
+            block
+                .recipients
+                .retain(|existing| existing.name != recipient);
+            Ok(block.recipients.len() < before)
+        },
+    )?;
+    println!(
+        "revoked {} access to {} blocks of {}",
+        recipient, revoked, path
+    );
+    Ok(())
+}
+
+/// The encrypted blocks, numbered from 1 in the order of the file
+fn encrypted_blocks(blocks: &[Block]) -> impl Iterator<Item = (usize, &EncryptedCryptBlock)> {
+    blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::EncryptedCryptBlock(crypt_block) => Some(crypt_block),
+            _ => None,
+        })
+        .enumerate()
+        .map(|(i, crypt_block)| (i + 1, crypt_block))
+}
+
+/// Applies `modify` to the encrypted blocks of the file, or only to block `only_block`, writing
+/// the file back if it changed any. Returns the number of blocks it changed.
+fn modify_blocks<F>(
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &Path,
+    only_block: Option<usize>,
+    mut modify: F,
+) -> Result<usize, AccessError>
+where
+    F: FnMut(&mut EncryptedCryptBlock) -> Result<bool, AccessError>,
+{
+    let filename = format!("{}", path.display());
+    // Held until the file is written
+    let _lock = FileLock::acquire(path, lock_timeout)
+        .map_err(|e| AccessError::LockFile(filename.clone(), e))?
+        .ok_or_else(|| AccessError::Locked(filename.clone()))?;
+    let contents = read_crypt_file(path)
+        .map_err(|e| AccessError::ReadFile(filename.clone(), e))?
+        .ok_or_else(|| AccessError::NotCryptFile(filename.clone()))?;
+    let mut crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| AccessError::ParseCryptFile(filename.clone(), e))?;
+
+    let mut number = 0;
+    let mut changed = 0;
+    for block in crypt_file.blocks.iter_mut() {
+        if let Block::EncryptedCryptBlock(crypt_block) = block {
+            number += 1;
+            if only_block.is_none_or(|only_block| only_block == number) && modify(crypt_block)? {
+                changed += 1;
+            }
+        }
+    }
+    if let Some(only_block) = only_block.filter(|only_block| *only_block > number) {
+        return Err(AccessError::NoSuchBlock(filename, only_block));
+    }
+
+    // Files are left untouched when no block changed
+    if changed > 0 {
+        let mut file =
+            File::create(path).map_err(|e| AccessError::WriteFile(filename.clone(), e))?;
+        write!(file, "{}", crypt_file).map_err(|e| AccessError::WriteFile(filename.clone(), e))?;
+    }
+    Ok(changed)
+}
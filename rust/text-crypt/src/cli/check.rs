@@ -1,12 +1,21 @@
 use std::path::Path;
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 use crate::error::{CheckError, CheckErrors};
-use crate::CryptFile;
+use crate::read::read_crypt_file;
+use crate::{CryptFile, ParseMode};
 
-use super::walk_dir;
+use super::{
+    report::{FileStatus, Report},
+    walk_dir,
+};
 
-pub fn check_cmd(files: Vec<&str>) -> Result<(), CheckErrors> {
+pub fn check_cmd(
+    verbose: bool,
+    summary: bool,
+    parse_mode: ParseMode,
+    files: Vec<&str>,
+) -> Result<(), CheckErrors> {
     let files = if files.is_empty() {
         vec![std::env::current_dir().map_err(|e| {
             CheckErrors::new(vec![CheckError::ReadFile(
@@ -17,32 +26,34 @@ pub fn check_cmd(files: Vec<&str>) -> Result<(), CheckErrors> {
     } else {
         files.into_iter().map(|s| PathBuf::from(s)).collect()
     };
+    let mut report = Report::new("checked");
     let errors: Vec<CheckError> = files
         .iter()
         .flat_map(|input_path| {
             let path = Path::new(&input_path);
             if path.is_dir() {
                 walk_dir(path)
-                    .map(|entry_res| {
-                        let entry = entry_res
-                            .map_err(|e| CheckError::WalkDir(format!("{}", path.display()), e))?;
-
-                        if entry.path().is_file() {
-                            check_file(entry.path())
-                        } else {
-                            Ok(())
+                    .filter_map(|entry_res| match entry_res {
+                        Ok(entry) if entry.path().is_file() => {
+                            let result = check_file(entry.path(), parse_mode);
+                            Some((entry.into_path(), result))
                         }
+                        Ok(_) => None,
+                        Err(e) => Some((
+                            e.path().unwrap_or(path).to_path_buf(),
+                            Err(CheckError::WalkDir(format!("{}", path.display()), e)),
+                        )),
                     })
                     .collect()
             } else {
-                vec![check_file(path)]
+                vec![(path.to_path_buf(), check_file(path, parse_mode))]
             }
         })
-        .filter_map(|result| match result {
-            Ok(_) => None,
-            Err(err) => Some(err),
-        })
+        .filter_map(|(path, result)| report.add(path, result))
         .collect();
+    if summary {
+        report.print(verbose);
+    }
     if errors.is_empty() {
         Ok(())
     } else {
@@ -50,14 +61,14 @@ pub fn check_cmd(files: Vec<&str>) -> Result<(), CheckErrors> {
     }
 }
 
-fn check_file(file_path: &Path) -> Result<(), CheckError> {
-    let contents = fs::read_to_string(file_path)
-        .map_err(|e| CheckError::ReadFile(format!("{}", file_path.display()), e))?;
-
-    if !CryptFile::is_crypt_file(&contents) {
-        return Ok(());
-    }
-    let crypt_file = CryptFile::from_str(&contents)
+fn check_file(file_path: &Path, parse_mode: ParseMode) -> Result<FileStatus, CheckError> {
+    let contents = match read_crypt_file(file_path)
+        .map_err(|e| CheckError::ReadFile(format!("{}", file_path.display()), e))?
+    {
+        Some(contents) => contents,
+        None => return Ok(FileStatus::Skipped),
+    };
+    let crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
         .map_err(|e| CheckError::ParseCryptFile(format!("{}", file_path.display()), e))?;
     if crypt_file.has_unencrypted_crypt_blocks() {
         return Err(CheckError::UnencryptedFile(format!(
@@ -65,5 +76,5 @@ fn check_file(file_path: &Path) -> Result<(), CheckError> {
             file_path.display()
         )));
     }
-    Ok(())
+    Ok(FileStatus::Processed)
 }
@@ -1,23 +1,38 @@
 use std::{
-    fs::{self, File},
-    io::Write,
+    fs::File,
+    io::{self, IsTerminal, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use crate::{
     crypto::decrypt,
     error::{DecryptError, DecryptErrors},
-    Block, CryptFile,
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
 };
 
-use super::walk_dir;
+use super::{
+    lock::FileLock,
+    report::{FileStatus, Report},
+    walk_dir,
+};
 
 pub(crate) fn decrypt_cmd(
     verbose: bool,
     write_file: bool,
+    summary: bool,
+    force_stdout: bool,
+    raw: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     password: &str,
     paths: Vec<&str>,
 ) -> Result<(), DecryptErrors> {
+    // Decrypted contents redirected to a file or a log by mistake would leak the secrets
+    if !write_file && !force_stdout && !raw && !io::stdout().is_terminal() {
+        return Err(DecryptErrors::new(vec![DecryptError::StdoutNotTerminal]));
+    }
     let paths = if paths.is_empty() {
         vec![std::env::current_dir().map_err(|e| {
             DecryptErrors::new(vec![DecryptError::ReadFile(
@@ -28,28 +43,45 @@ pub(crate) fn decrypt_cmd(
     } else {
         paths.into_iter().map(|s| PathBuf::from(s)).collect()
     };
-    let should_print_filename = paths.len() > 1 || paths[0].is_dir();
+    let should_print_filename = !raw && (paths.len() > 1 || paths[0].is_dir());
 
+    let mut report = Report::new("decrypted");
     let errors: Vec<_> = paths
         .into_iter()
         .flat_map(|path| {
             if path.is_dir() {
-                decrypt_dir(verbose, write_file, password, &path)
+                decrypt_dir(
+                    verbose,
+                    write_file,
+                    raw,
+                    lock_timeout,
+                    parse_mode,
+                    password,
+                    &path,
+                )
             } else {
-                vec![decrypt_file(
+                let result = decrypt_file(
                     verbose,
                     write_file,
+                    raw,
+                    lock_timeout,
+                    parse_mode,
                     password,
                     &path,
                     should_print_filename,
-                )]
+                );
+                vec![(path, result)]
             }
         })
-        .filter_map(|res| match res {
-            Ok(_) => None,
-            Err(e) => Some(e),
-        })
+        .filter_map(|(path, res)| report.add(path, res))
         .collect();
+    if summary || write_file {
+        report.print(verbose);
+    }
+    if write_file && !report.has_changes() {
+        eprintln!("no changes");
+    }
+
     if errors.len() > 0 {
         Err(DecryptErrors::new(errors))
     } else {
@@ -60,40 +92,85 @@ pub(crate) fn decrypt_cmd(
 fn decrypt_dir(
     verbose: bool,
     write_file: bool,
+    raw: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     password: &str,
     path: &Path,
-) -> Vec<Result<(), DecryptError>> {
+) -> Vec<(PathBuf, Result<FileStatus, DecryptError>)> {
     walk_dir(path)
-        .map(|dir_entry| {
-            let entry =
-                dir_entry.map_err(|e| DecryptError::WalkDir(format!("{}", path.display()), e))?;
-            if entry.path().is_file() {
-                decrypt_file(verbose, write_file, password, entry.path(), true)?;
+        .filter_map(|dir_entry| match dir_entry {
+            Ok(entry) if entry.path().is_file() => {
+                let result = decrypt_file(
+                    verbose,
+                    write_file,
+                    raw,
+                    lock_timeout,
+                    parse_mode,
+                    password,
+                    entry.path(),
+                    !raw,
+                );
+                Some((entry.into_path(), result))
             }
-            Ok(())
+            Ok(_) => None,
+            Err(e) => Some((
+                e.path().unwrap_or(path).to_path_buf(),
+                Err(DecryptError::WalkDir(format!("{}", path.display()), e)),
+            )),
         })
         .collect()
 }
 
+/// Decrypts the file, writing it back or printing it. Raw printing only prints the contents of
+/// the crypt blocks, a line each.
 fn decrypt_file(
     verbose: bool,
     write: bool,
+    raw: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     password: &str,
     filepath: &Path,
     should_print_filename: bool,
-) -> Result<(), DecryptError> {
+) -> Result<FileStatus, DecryptError> {
     let filename = format!("{}", filepath.display());
+    // Held until the file is written
+    let _lock = if write {
+        match FileLock::acquire(filepath, lock_timeout)
+            .map_err(|e| DecryptError::LockFile(filename.clone(), e))?
+        {
+            Some(lock) => Some(lock),
+            None => {
+                eprintln!(
+                    "Skipping decrypting {} since it is locked by another process",
+                    filename
+                );
+                return Ok(FileStatus::Skipped);
+            }
+        }
+    } else {
+        None
+    };
     let contents =
-        fs::read_to_string(filepath).map_err(|e| DecryptError::ReadFile(filename.clone(), e))?;
-
-    if !CryptFile::is_crypt_file(&contents) {
+        match read_crypt_file(filepath).map_err(|e| DecryptError::ReadFile(filename.clone(), e))? {
+            Some(contents) => contents,
+            None => {
+                if verbose {
+                    eprintln!("Skipping decrypting {} since not a Crypt File", filename);
+                }
+                return Ok(FileStatus::Skipped);
+            }
+        };
+    let mut crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| DecryptError::ParseCryptFile(filename.clone(), e))?;
+    let changed = crypt_file.blocks.iter().any(Block::is_encrypted);
+    if write && !changed {
         if verbose {
-            eprintln!("Skipping decrypting {} since not a Crypt File", filename);
+            eprintln!("No changes to {}", filename);
         }
-        return Ok(());
+        return Ok(FileStatus::Unchanged);
     }
-    let mut crypt_file = CryptFile::from_str(&contents)
-        .map_err(|e| DecryptError::ParseCryptFile(filename.clone(), e))?;
 
     let unencrypted_blocks: Result<Vec<_>, DecryptError> = crypt_file
         .blocks
@@ -113,11 +190,21 @@ fn decrypt_file(
         let mut file =
             File::create(filepath).map_err(|e| DecryptError::WriteFile(filename.clone(), e))?;
         write!(file, "{}", crypt_file).map_err(|e| DecryptError::WriteFile(filename.clone(), e))?;
+    } else if raw {
+        for block in &crypt_file.blocks {
+            if let Block::UnencryptedCryptBlock(text) = block {
+                println!("{}", text);
+            }
+        }
     } else {
         if should_print_filename {
             println!("{}", filepath.display());
         }
         println!("{}", crypt_file);
     }
-    Ok(())
+    if changed {
+        Ok(FileStatus::Processed)
+    } else {
+        Ok(FileStatus::Unchanged)
+    }
 }
@@ -0,0 +1,192 @@
+use std::{env, fs, path::PathBuf};
+
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+use crate::{
+    crypto::{decrypt, encrypt},
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
+};
+
+use super::walk_dir;
+
+/// ChaCha20-Poly1305 test vector of RFC 8439 section 2.8.2
+const KAT_NONCE: [u8; 12] = [
+    0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47,
+];
+const KAT_AAD: [u8; 12] = [
+    0x50, 0x51, 0x52, 0x53, 0xc0, 0xc1, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7,
+];
+const KAT_PLAINTEXT: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+const KAT_CIPHERTEXT_AND_TAG: &str = "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b61161ae10b594f09e26a7e902ecbd0600691";
+
+/// Length in bytes the password must have, as it is used as the key
+const PASSWORD_LEN: usize = 32;
+
+#[derive(Default)]
+struct Diagnosis {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Diagnosis {
+    fn ok(&mut self, message: &str) {
+        println!("[ok]   {}", message);
+    }
+
+    fn warn(&mut self, message: &str) {
+        self.warnings += 1;
+        println!("[warn] {}", message);
+    }
+
+    fn fail(&mut self, message: &str) {
+        self.failures += 1;
+        println!("[fail] {}", message);
+    }
+}
+
+/// Checks the crypto, the environment and the files under the paths for the usual reasons files
+/// don't decrypt. Returns whether nothing failed.
+pub(crate) fn doctor_cmd(parse_mode: ParseMode, paths: Vec<&str>) -> bool {
+    let mut diagnosis = Diagnosis::default();
+    check_crypto(&mut diagnosis);
+    check_env(&mut diagnosis);
+
+    let paths = if paths.is_empty() {
+        match env::current_dir() {
+            Ok(dir) => vec![dir],
+            Err(e) => {
+                diagnosis.fail(&format!("Could not get the current directory: {}", e));
+                vec![]
+            }
+        }
+    } else {
+        paths.into_iter().map(PathBuf::from).collect()
+    };
+    for path in paths {
+        for entry in walk_dir(&path) {
+            match entry {
+                Ok(entry) if entry.path().is_file() => {
+                    check_file(&mut diagnosis, parse_mode, entry.into_path())
+                }
+                Ok(_) => {}
+                Err(e) => diagnosis.fail(&format!("Could not walk {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    println!(
+        "{} failures, {} warnings",
+        diagnosis.failures, diagnosis.warnings
+    );
+    diagnosis.failures == 0
+}
+
+fn check_crypto(diagnosis: &mut Diagnosis) {
+    let key_bytes: Vec<u8> = (0x80..=0x9f).collect();
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key_bytes));
+    let payload = Payload {
+        msg: KAT_PLAINTEXT,
+        aad: &KAT_AAD,
+    };
+    match cipher.encrypt(Nonce::from_slice(&KAT_NONCE), payload) {
+        Ok(ciphertext) if to_hex(&ciphertext) == KAT_CIPHERTEXT_AND_TAG => {
+            diagnosis.ok("ChaCha20Poly1305 matches the RFC 8439 test vector")
+        }
+        Ok(_) => diagnosis.fail("ChaCha20Poly1305 doesn't match the RFC 8439 test vector"),
+        Err(e) => diagnosis.fail(&format!("ChaCha20Poly1305 failed encrypting: {}", e)),
+    }
+
+    let password = "doctor-round-trip-password-32byt";
+    let round_trip = encrypt(password, "round trip")
+        .map_err(|e| e.to_string())
+        .and_then(|block| decrypt(password, &block).map_err(|e| e.to_string()));
+    match round_trip {
+        Ok(text) if text == "round trip" => diagnosis.ok("Blocks encrypt and decrypt back"),
+        Ok(_) => diagnosis.fail("Blocks decrypt to something else than what was encrypted"),
+        Err(e) => diagnosis.fail(&format!("Blocks fail to encrypt and decrypt back: {}", e)),
+    }
+}
+
+fn check_env(diagnosis: &mut Diagnosis) {
+    match env::var("PASS") {
+        Ok(pass) if pass.is_empty() => diagnosis.fail("PASS is set but empty"),
+        Ok(pass) if pass.len() != PASSWORD_LEN => diagnosis.fail(&format!(
+            "PASS is {} bytes long but the password must be {} bytes",
+            pass.len(),
+            PASSWORD_LEN
+        )),
+        Ok(_) => diagnosis.ok("PASS is set"),
+        Err(env::VarError::NotUnicode(_)) => diagnosis.fail("PASS is not valid UTF-8"),
+        Err(env::VarError::NotPresent) => {
+            diagnosis.ok("PASS is not set, the password has to be given with -p")
+        }
+    }
+
+    // The first of these that is set decides the encoding of the terminal
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()));
+    match locale {
+        Some(locale) => {
+            let normalized = locale.to_lowercase().replace('-', "");
+            if normalized.contains("utf8") {
+                diagnosis.ok(&format!("Locale {} is UTF-8", locale))
+            } else {
+                diagnosis.warn(&format!(
+                    "Locale {} is not UTF-8, decrypted text may not display correctly",
+                    locale
+                ))
+            }
+        }
+        None => diagnosis.warn("No locale set with LC_ALL, LC_CTYPE or LANG"),
+    }
+}
+
+fn check_file(diagnosis: &mut Diagnosis, parse_mode: ParseMode, path: PathBuf) {
+    let contents = match read_crypt_file(&path) {
+        Ok(Some(contents)) => contents,
+        Ok(None) => return,
+        Err(e) => {
+            diagnosis.fail(&format!("Could not read {}: {}", path.display(), e));
+            return;
+        }
+    };
+    let crypt_file = match CryptFile::from_str_with_mode(&contents, parse_mode) {
+        Ok(crypt_file) => crypt_file,
+        Err(e) => {
+            diagnosis.fail(&format!("Could not parse {}: {}", path.display(), e));
+            return;
+        }
+    };
+
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.permissions().readonly() => diagnosis.warn(&format!(
+            "{} is read-only, it can't be written with -w",
+            path.display()
+        )),
+        Ok(_) => {}
+        Err(e) => diagnosis.fail(&format!("Could not stat {}: {}", path.display(), e)),
+    }
+
+    let legacy_blocks = crypt_file
+        .blocks
+        .iter()
+        .filter(|block| match block {
+            Block::EncryptedCryptBlock(block) => block.wrapped_key.is_none(),
+            _ => false,
+        })
+        .count();
+    if legacy_blocks > 0 {
+        diagnosis.warn(&format!(
+            "{} has {} blocks in the format without wrapped keys, run rotate to update them",
+            path.display(),
+            legacy_blocks
+        ));
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
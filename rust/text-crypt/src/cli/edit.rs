@@ -0,0 +1,87 @@
+use std::{env, fs::File, io::Write, path::Path, process::Command, time::Duration};
+
+use crate::{
+    crypto::{decrypt, reencrypt},
+    error::EditError,
+    read::read_crypt_file,
+    secure_temp::PlaintextTempFile,
+    Block, CryptFile, ParseMode,
+};
+
+use super::lock::FileLock;
+
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Opens encrypted block `block` of the file, numbered from 1, in the editor and encrypts what
+/// was saved back into it. The plaintext is only written to a private temp file, removed once
+/// the editor exits, and the file stays locked until then.
+pub(crate) fn edit_cmd(
+    password: &str,
+    block: usize,
+    shred: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &str,
+) -> Result<(), EditError> {
+    let path = Path::new(path);
+    let filename = format!("{}", path.display());
+    // Held until the file is written
+    let _lock = FileLock::acquire(path, lock_timeout)
+        .map_err(|e| EditError::LockFile(filename.clone(), e))?
+        .ok_or_else(|| EditError::Locked(filename.clone()))?;
+    let contents = read_crypt_file(path)
+        .map_err(|e| EditError::ReadFile(filename.clone(), e))?
+        .ok_or_else(|| EditError::NotCryptFile(filename.clone()))?;
+    let mut crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| EditError::ParseCryptFile(filename.clone(), e))?;
+
+    let crypt_block = crypt_file
+        .blocks
+        .iter_mut()
+        .filter_map(|block| match block {
+            Block::EncryptedCryptBlock(crypt_block) => Some(crypt_block),
+            _ => None,
+        })
+        .nth(block.saturating_sub(1))
+        .filter(|_| block > 0)
+        .ok_or_else(|| EditError::NoSuchBlock(filename.clone(), block))?;
+    let plaintext = decrypt(password, crypt_block)?;
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "block".to_string());
+    let temp_file = PlaintextTempFile::create(&name, shred).map_err(EditError::TempFile)?;
+    temp_file.write(&plaintext).map_err(EditError::TempFile)?;
+    run_editor(temp_file.path())?;
+    let edited = temp_file.read().map_err(EditError::TempFile)?;
+    drop(temp_file);
+
+    if edited == plaintext {
+        println!("block {} of {} is unchanged", block, filename);
+        return Ok(());
+    }
+    *crypt_block = reencrypt(password, &edited, crypt_block)?;
+    let mut file = File::create(path).map_err(|e| EditError::WriteFile(filename.clone(), e))?;
+    write!(file, "{}", crypt_file).map_err(|e| EditError::WriteFile(filename.clone(), e))?;
+    println!("encrypted block {} of {}", block, filename);
+    Ok(())
+}
+
+/// Runs `$VISUAL` or `$EDITOR` on the file, which may include arguments like `code --wait`
+fn run_editor(path: &Path) -> Result<(), EditError> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| EditError::Editor(editor.clone(), e))?;
+    if !status.success() {
+        return Err(EditError::EditorFailed(editor, status));
+    }
+    Ok(())
+}
@@ -1,21 +1,31 @@
 use std::{
-    fs::{self, File},
+    fs::File,
     io::Write,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use crate::{
     crypto::encrypt,
     error::{EncryptError, EncryptErrors},
-    Block, CryptFile,
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
 };
 
-use super::walk_dir;
+use super::{
+    lock::FileLock,
+    report::{FileStatus, Report},
+    walk_dir,
+};
 
 pub(crate) fn encrypt_cmd(
     verbose: bool,
     password: &str,
+    note: Option<&str>,
     write_file: bool,
+    summary: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     paths: Vec<&str>,
 ) -> Result<(), EncryptErrors> {
     let paths = if paths.is_empty() {
@@ -29,26 +39,42 @@ pub(crate) fn encrypt_cmd(
         paths.into_iter().map(|s| PathBuf::from(s)).collect()
     };
     let print_filenames = paths.len() > 1;
+    let mut report = Report::new("encrypted");
     let errors: Vec<_> = paths
         .into_iter()
         .flat_map(|path| {
             if path.is_dir() {
-                encrypt_dir(verbose, password, write_file, &path)
+                encrypt_dir(
+                    verbose,
+                    password,
+                    note,
+                    write_file,
+                    lock_timeout,
+                    parse_mode,
+                    &path,
+                )
             } else {
-                vec![encrypt_file(
+                let result = encrypt_file(
                     verbose,
                     password,
+                    note,
                     write_file,
-                    path,
+                    lock_timeout,
+                    parse_mode,
+                    &path,
                     print_filenames,
-                )]
+                );
+                vec![(path, result)]
             }
         })
-        .filter_map(|res| match res {
-            Ok(_) => None,
-            Err(e) => Some(e),
-        })
+        .filter_map(|(path, res)| report.add(path, res))
         .collect();
+    if summary || write_file {
+        report.print(verbose);
+    }
+    if write_file && !report.has_changes() {
+        eprintln!("no changes");
+    }
 
     if errors.is_empty() {
         Ok(())
@@ -60,18 +86,32 @@ pub(crate) fn encrypt_cmd(
 fn encrypt_dir(
     verbose: bool,
     password: &str,
+    note: Option<&str>,
     write_file: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     path: &Path,
-) -> Vec<Result<(), EncryptError>> {
+) -> Vec<(PathBuf, Result<FileStatus, EncryptError>)> {
     walk_dir(path)
-        .map(|direntry| {
-            let entry =
-                direntry.map_err(|e| EncryptError::WalkDir(format!("{}", path.display()), e))?;
-            let entry_path = entry.path();
-            if entry_path.is_file() {
-                encrypt_file(verbose, password, write_file, entry_path, true)?;
+        .filter_map(|direntry| match direntry {
+            Ok(entry) if entry.path().is_file() => {
+                let result = encrypt_file(
+                    verbose,
+                    password,
+                    note,
+                    write_file,
+                    lock_timeout,
+                    parse_mode,
+                    entry.path(),
+                    true,
+                );
+                Some((entry.into_path(), result))
             }
-            Ok(())
+            Ok(_) => None,
+            Err(e) => Some((
+                e.path().unwrap_or(path).to_path_buf(),
+                Err(EncryptError::WalkDir(format!("{}", path.display()), e)),
+            )),
         })
         .collect()
 }
@@ -79,29 +119,60 @@ fn encrypt_dir(
 fn encrypt_file<P: AsRef<Path>>(
     verbose: bool,
     password: &str,
+    note: Option<&str>,
     write_file: bool,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
     path: P,
     print_filename: bool,
-) -> Result<(), EncryptError> {
+) -> Result<FileStatus, EncryptError> {
     let filename = format!("{}", path.as_ref().display());
-    let contents = fs::read_to_string(path.as_ref())
-        .map_err(|e| EncryptError::ReadFile(filename.clone(), e))?;
-
-    if !CryptFile::is_crypt_file(&contents) {
+    // Held until the file is written
+    let _lock = if write_file {
+        match FileLock::acquire(path.as_ref(), lock_timeout)
+            .map_err(|e| EncryptError::LockFile(filename.clone(), e))?
+        {
+            Some(lock) => Some(lock),
+            None => {
+                eprintln!(
+                    "Skipping encrypting {} since it is locked by another process",
+                    filename
+                );
+                return Ok(FileStatus::Skipped);
+            }
+        }
+    } else {
+        None
+    };
+    let contents = match read_crypt_file(path.as_ref())
+        .map_err(|e| EncryptError::ReadFile(filename.clone(), e))?
+    {
+        Some(contents) => contents,
+        None => {
+            if verbose {
+                eprintln!("Skipping encrypting {} since not a Crypt File", filename);
+            }
+            return Ok(FileStatus::Skipped);
+        }
+    };
+    let mut crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| EncryptError::ParseCryptFile(filename.clone(), e))?;
+    // Encrypted blocks are kept as they are so only unencrypted ones change the file
+    let changed = crypt_file.has_unencrypted_crypt_blocks();
+    if write_file && !changed {
         if verbose {
-            eprintln!("Skipping encrypting {} since not a Crypt File", filename);
+            eprintln!("No changes to {}", filename);
         }
-        return Ok(());
+        return Ok(FileStatus::Unchanged);
     }
-    let mut crypt_file = CryptFile::from_str(&contents)
-        .map_err(|e| EncryptError::ParseCryptFile(filename.clone(), e))?;
 
     let encrypted_crypt_blocks: Result<Vec<_>, EncryptError> = crypt_file
         .blocks
         .into_iter()
         .map(|block| match block {
             Block::UnencryptedCryptBlock(text) => {
-                let encrypted_block = encrypt(password, &text)?;
+                let mut encrypted_block = encrypt(password, &text)?;
+                encrypted_block.note = note.map(String::from);
                 Ok(Block::EncryptedCryptBlock(encrypted_block))
             }
             _ => Ok(block),
@@ -121,5 +192,9 @@ fn encrypt_file<P: AsRef<Path>>(
         }
         println!("{}", crypt_file);
     }
-    Ok(())
+    if changed {
+        Ok(FileStatus::Processed)
+    } else {
+        Ok(FileStatus::Unchanged)
+    }
 }
@@ -0,0 +1,83 @@
+use std::{path::Path, process::Command};
+
+use crate::{crypto::decrypt, error::EnvError, read::read_crypt_file, Block, CryptFile, ParseMode};
+
+/// Prints the variables of the crypt blocks of the file as `export KEY='value'` statements, to
+/// be evaluated by a shell
+pub(crate) fn env_cmd(password: &str, parse_mode: ParseMode, path: &str) -> Result<(), EnvError> {
+    for (key, value) in read_env(password, parse_mode, Path::new(path))? {
+        println!("export {}={}", key, shell_quote(&value));
+    }
+    Ok(())
+}
+
+/// Runs the command with the variables of the crypt blocks of the file added to its
+/// environment, exiting with its exit code
+pub(crate) fn exec_cmd(
+    password: &str,
+    parse_mode: ParseMode,
+    path: &str,
+    command: Vec<&str>,
+) -> Result<(), EnvError> {
+    let vars = read_env(password, parse_mode, Path::new(path))?;
+    let (program, args) = command
+        .split_first()
+        .expect("command is required by the cli");
+    let status = Command::new(program)
+        .args(args)
+        .envs(vars)
+        .status()
+        .map_err(|e| EnvError::Exec(program.to_string(), e))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Decrypts the crypt blocks of the file and parses their `KEY=value` lines. Empty lines,
+/// comments and a leading `export` are ignored like in dotenv files.
+fn read_env(
+    password: &str,
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Result<Vec<(String, String)>, EnvError> {
+    let filename = format!("{}", path.display());
+    let contents = read_crypt_file(path)
+        .map_err(|e| EnvError::ReadFile(filename.clone(), e))?
+        .ok_or_else(|| EnvError::NotCryptFile(filename.clone()))?;
+    let crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| EnvError::ParseCryptFile(filename.clone(), e))?;
+
+    let mut vars = Vec::new();
+    for block in crypt_file.blocks {
+        let text = match block {
+            Block::Plaintext(_) => continue,
+            Block::UnencryptedCryptBlock(text) => text,
+            Block::EncryptedCryptBlock(crypt_block) => decrypt(password, &crypt_block)?,
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            // The line isn't part of the error since it may contain a secret
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| EnvError::InvalidLine(filename.clone(), i + 1))?;
+            vars.push((key.trim().to_string(), unquote(value.trim()).to_string()));
+        }
+    }
+    Ok(vars)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+/// Quotes the value for a POSIX shell, in which nothing is special between single quotes
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
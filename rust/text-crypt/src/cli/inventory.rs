@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    error::{InventoryError, InventoryErrors},
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
+};
+
+use super::{format_timestamp, walk_dir};
+
+#[derive(Serialize)]
+struct Inventory {
+    files: Vec<FileInventory>,
+    total: Total,
+}
+
+/// Blocks of a crypt file and how its encrypted blocks are encrypted
+#[derive(Serialize)]
+struct FileInventory {
+    path: PathBuf,
+    encrypted_blocks: usize,
+    unencrypted_blocks: usize,
+    algorithms: BTreeSet<String>,
+    nonce_sizes: BTreeSet<usize>,
+    format_versions: BTreeSet<u8>,
+    /// When the least recently encrypted or rotated block was, of the blocks recording it
+    oldest_encrypted_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Total {
+    files: usize,
+    encrypted_blocks: usize,
+    unencrypted_blocks: usize,
+}
+
+/// Prints the blocks of the crypt files under the paths as a table, or as JSON
+pub(crate) fn inventory_cmd(
+    json: bool,
+    parse_mode: ParseMode,
+    paths: Vec<&str>,
+) -> Result<(), InventoryErrors> {
+    let paths = if paths.is_empty() {
+        vec![std::env::current_dir().map_err(|e| {
+            InventoryErrors::new(vec![InventoryError::ReadFile(
+                "current working directory".to_string(),
+                e,
+            )])
+        })?]
+    } else {
+        paths.into_iter().map(PathBuf::from).collect()
+    };
+
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let results = if path.is_dir() {
+            inventory_dir(parse_mode, &path)
+        } else {
+            vec![inventory_file(parse_mode, &path)]
+        };
+        for result in results {
+            match result {
+                Ok(Some(file)) => files.push(file),
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+
+    let inventory = Inventory {
+        total: Total {
+            files: files.len(),
+            encrypted_blocks: files.iter().map(|file| file.encrypted_blocks).sum(),
+            unencrypted_blocks: files.iter().map(|file| file.unencrypted_blocks).sum(),
+        },
+        files,
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&inventory).expect("serializing inventory")
+        );
+    } else {
+        print_table(&inventory);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(InventoryErrors::new(errors))
+    }
+}
+
+fn inventory_dir(
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Vec<Result<Option<FileInventory>, InventoryError>> {
+    walk_dir(path)
+        .filter_map(|direntry| match direntry {
+            Ok(entry) if entry.path().is_file() => Some(inventory_file(parse_mode, entry.path())),
+            Ok(_) => None,
+            Err(e) => Some(Err(InventoryError::WalkDir(
+                format!("{}", path.display()),
+                e,
+            ))),
+        })
+        .collect()
+}
+
+/// Returns None for files that aren't crypt files
+fn inventory_file(
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Result<Option<FileInventory>, InventoryError> {
+    let filename = format!("{}", path.display());
+    let contents =
+        match read_crypt_file(path).map_err(|e| InventoryError::ReadFile(filename.clone(), e))? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+    let crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| InventoryError::ParseCryptFile(filename, e))?;
+
+    let mut file = FileInventory {
+        path: path.to_path_buf(),
+        encrypted_blocks: 0,
+        unencrypted_blocks: 0,
+        algorithms: BTreeSet::new(),
+        nonce_sizes: BTreeSet::new(),
+        format_versions: BTreeSet::new(),
+        oldest_encrypted_at: None,
+    };
+    let mut oldest_encrypted_at: Option<u64> = None;
+    for block in crypt_file.blocks {
+        match block {
+            Block::Plaintext(_) => {}
+            Block::UnencryptedCryptBlock(_) => file.unencrypted_blocks += 1,
+            Block::EncryptedCryptBlock(block) => {
+                file.encrypted_blocks += 1;
+                file.nonce_sizes.insert(block.nonce.len());
+                file.format_versions.insert(block.format_version());
+                if let Some(encrypted_at) = block.encrypted_at {
+                    oldest_encrypted_at = Some(
+                        oldest_encrypted_at.map_or(encrypted_at, |oldest| oldest.min(encrypted_at)),
+                    );
+                }
+                file.algorithms.insert(block.algorithm);
+            }
+        }
+    }
+    file.oldest_encrypted_at = oldest_encrypted_at.map(format_timestamp);
+    Ok(Some(file))
+}
+
+fn print_table(inventory: &Inventory) {
+    let rows: Vec<[String; 7]> = inventory
+        .files
+        .iter()
+        .map(|file| {
+            [
+                format!("{}", file.path.display()),
+                file.encrypted_blocks.to_string(),
+                file.unencrypted_blocks.to_string(),
+                join(&file.algorithms),
+                join(&file.nonce_sizes),
+                join(&file.format_versions),
+                file.oldest_encrypted_at
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    let header = [
+        "FILE",
+        "ENCRYPTED",
+        "UNENCRYPTED",
+        "ALGORITHMS",
+        "NONCE SIZES",
+        "VERSIONS",
+        "OLDEST ENCRYPTED",
+    ]
+    .map(String::from);
+
+    let mut widths = header.clone().map(|column| column.len());
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{:width$}", column, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+    println!(
+        "{} files, {} encrypted blocks, {} unencrypted blocks",
+        inventory.total.files, inventory.total.encrypted_blocks, inventory.total.unencrypted_blocks
+    );
+}
+
+fn join<T: ToString>(values: &BTreeSet<T>) -> String {
+    if values.is_empty() {
+        return "-".to_string();
+    }
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
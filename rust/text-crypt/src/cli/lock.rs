@@ -0,0 +1,40 @@
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive advisory lock on a file, held for its read-modify-write cycle so that concurrent
+/// runs writing the same file don't interleave. Released when dropped.
+pub(crate) struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Locks the file, retrying until the timeout. Returns None if it is still locked by another
+    /// process by then.
+    pub(crate) fn acquire(path: &Path, timeout: Duration) -> io::Result<Option<FileLock>> {
+        // The lock is taken on its own read handle so that files that can't be opened for
+        // writing fail when written like before
+        let file = File::open(path)?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Some(FileLock { _file: file })),
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                    if Instant::now() >= deadline {
+                        return Ok(None);
+                    }
+                    thread::sleep(LOCK_RETRY_INTERVAL);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+/// What was done with a file that didn't fail
+pub(crate) enum FileStatus {
+    Processed,
+    /// A crypt file with nothing to process, left untouched when writing
+    Unchanged,
+    /// Not a crypt file, or locked by another process when writing
+    Skipped,
+}
+
+/// Summary of the files a subcommand went through, printed to stderr so that it doesn't mix
+/// with file contents printed to stdout
+pub(crate) struct Report {
+    /// Past tense of what was done to processed files, e.g. "encrypted"
+    action: &'static str,
+    processed: Vec<PathBuf>,
+    unchanged: Vec<PathBuf>,
+    skipped: Vec<PathBuf>,
+    failed: Vec<PathBuf>,
+}
+
+impl Report {
+    pub(crate) fn new(action: &'static str) -> Self {
+        Report {
+            action,
+            processed: Vec::new(),
+            unchanged: Vec::new(),
+            skipped: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+
+    /// Adds the result of a file, returning its error if it failed
+    pub(crate) fn add<E>(&mut self, path: PathBuf, result: Result<FileStatus, E>) -> Option<E> {
+        match result {
+            Ok(FileStatus::Processed) => {
+                self.processed.push(path);
+                None
+            }
+            Ok(FileStatus::Unchanged) => {
+                self.unchanged.push(path);
+                None
+            }
+            Ok(FileStatus::Skipped) => {
+                self.skipped.push(path);
+                None
+            }
+            Err(err) => {
+                self.failed.push(path);
+                Some(err)
+            }
+        }
+    }
+
+    /// Whether any file was processed
+    pub(crate) fn has_changes(&self) -> bool {
+        !self.processed.is_empty()
+    }
+
+    /// Prints the counts of files, listing them as well if verbose
+    pub(crate) fn print(&self, verbose: bool) {
+        if verbose {
+            for (status, paths) in [
+                (self.action, &self.processed),
+                ("unchanged", &self.unchanged),
+                ("skipped", &self.skipped),
+                ("failed", &self.failed),
+            ] {
+                for path in paths {
+                    eprintln!("{} {}", status, path.display());
+                }
+            }
+        }
+        eprintln!(
+            "{} {}, unchanged {}, skipped {}, failed {}",
+            self.action,
+            self.processed.len(),
+            self.unchanged.len(),
+            self.skipped.len(),
+            self.failed.len()
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    crypto::{rewrap, PASSWORD_LEN},
+    error::{RotateError, RotateErrors},
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
+};
+
+use super::{
+    lock::FileLock,
+    report::{FileStatus, Report},
+    walk_dir,
+};
+
+/// Re-encrypts the encrypted blocks of the files for the new password, writing them back
+pub(crate) fn rotate_cmd(
+    verbose: bool,
+    password: &str,
+    new_password: &str,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    paths: Vec<&str>,
+) -> Result<(), RotateErrors> {
+    if new_password.len() != PASSWORD_LEN {
+        return Err(RotateErrors::new(vec![RotateError::NewPasswordLength(
+            new_password.len(),
+        )]));
+    }
+    let paths = if paths.is_empty() {
+        vec![std::env::current_dir().map_err(|e| {
+            RotateErrors::new(vec![RotateError::ReadFile(
+                "current working directory".to_string(),
+                e,
+            )])
+        })?]
+    } else {
+        paths.into_iter().map(PathBuf::from).collect()
+    };
+    let mut report = Report::new("rotated");
+    let errors: Vec<_> = paths
+        .into_iter()
+        .flat_map(|path| {
+            if path.is_dir() {
+                rotate_dir(
+                    verbose,
+                    password,
+                    new_password,
+                    lock_timeout,
+                    parse_mode,
+                    &path,
+                )
+            } else {
+                let result = rotate_file(
+                    verbose,
+                    password,
+                    new_password,
+                    lock_timeout,
+                    parse_mode,
+                    &path,
+                );
+                vec![(path, result)]
+            }
+        })
+        .filter_map(|(path, res)| report.add(path, res))
+        .collect();
+    report.print(verbose);
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(RotateErrors::new(errors))
+    }
+}
+
+fn rotate_dir(
+    verbose: bool,
+    password: &str,
+    new_password: &str,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Vec<(PathBuf, Result<FileStatus, RotateError>)> {
+    walk_dir(path)
+        .filter_map(|direntry| match direntry {
+            Ok(entry) if entry.path().is_file() => {
+                let result = rotate_file(
+                    verbose,
+                    password,
+                    new_password,
+                    lock_timeout,
+                    parse_mode,
+                    entry.path(),
+                );
+                Some((entry.into_path(), result))
+            }
+            Ok(_) => None,
+            Err(e) => Some((
+                e.path().unwrap_or(path).to_path_buf(),
+                Err(RotateError::WalkDir(format!("{}", path.display()), e)),
+            )),
+        })
+        .collect()
+}
+
+fn rotate_file(
+    verbose: bool,
+    password: &str,
+    new_password: &str,
+    lock_timeout: Duration,
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Result<FileStatus, RotateError> {
+    let filename = format!("{}", path.display());
+    // Held until the file is written
+    let _lock = match FileLock::acquire(path, lock_timeout)
+        .map_err(|e| RotateError::LockFile(filename.clone(), e))?
+    {
+        Some(lock) => lock,
+        None => {
+            eprintln!(
+                "Skipping rotating {} since it is locked by another process",
+                filename
+            );
+            return Ok(FileStatus::Skipped);
+        }
+    };
+    let contents =
+        match read_crypt_file(path).map_err(|e| RotateError::ReadFile(filename.clone(), e))? {
+            Some(contents) => contents,
+            None => {
+                if verbose {
+                    eprintln!("Skipping rotating {} since not a Crypt File", filename);
+                }
+                return Ok(FileStatus::Skipped);
+            }
+        };
+    let mut crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| RotateError::ParseCryptFile(filename.clone(), e))?;
+    if !crypt_file.blocks.iter().any(Block::is_encrypted) {
+        return Ok(FileStatus::Unchanged);
+    }
+
+    let rotated_blocks: Result<Vec<_>, RotateError> = crypt_file
+        .blocks
+        .into_iter()
+        .map(|block| match block {
+            Block::EncryptedCryptBlock(crypt_block) => Ok(Block::EncryptedCryptBlock(rewrap(
+                password,
+                new_password,
+                &crypt_block,
+            )?)),
+            _ => Ok(block),
+        })
+        .collect();
+    crypt_file.blocks = rotated_blocks?;
+
+    let mut file = File::create(path).map_err(|e| RotateError::WriteFile(filename.clone(), e))?;
+    write!(file, "{}", crypt_file).map_err(|e| RotateError::WriteFile(filename.clone(), e))?;
+    Ok(FileStatus::Processed)
+}
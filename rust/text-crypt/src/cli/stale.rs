@@ -0,0 +1,133 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{StaleError, StaleErrors},
+    read::read_crypt_file,
+    Block, CryptFile, ParseMode,
+};
+
+use super::{format_timestamp, walk_dir};
+
+/// Encrypted block that wasn't encrypted or rotated recently enough
+struct StaleBlock {
+    path: PathBuf,
+    /// Position of the block among the encrypted blocks of the file, from 1
+    index: usize,
+    encrypted_at: Option<u64>,
+    note: Option<String>,
+}
+
+/// Prints the encrypted blocks of the files encrypted or rotated longer than `older_than` ago.
+/// Blocks that don't record when they were encrypted are stale as well. Returns whether no block
+/// is stale.
+pub(crate) fn stale_cmd(
+    older_than: Duration,
+    parse_mode: ParseMode,
+    paths: Vec<&str>,
+) -> Result<bool, StaleErrors> {
+    let paths = if paths.is_empty() {
+        vec![std::env::current_dir().map_err(|e| {
+            StaleErrors::new(vec![StaleError::ReadFile(
+                "current working directory".to_string(),
+                e,
+            )])
+        })?]
+    } else {
+        paths.into_iter().map(PathBuf::from).collect()
+    };
+    let cutoff = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .saturating_sub(older_than)
+        .as_secs();
+
+    let mut stale_blocks = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let results = if path.is_dir() {
+            stale_dir(cutoff, parse_mode, &path)
+        } else {
+            vec![stale_file(cutoff, parse_mode, &path)]
+        };
+        for result in results {
+            match result {
+                Ok(blocks) => stale_blocks.extend(blocks),
+                Err(e) => errors.push(e),
+            }
+        }
+    }
+
+    for block in &stale_blocks {
+        println!(
+            "{} block {} encrypted {}{}",
+            block.path.display(),
+            block.index,
+            block
+                .encrypted_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "at an unknown time".to_string()),
+            block
+                .note
+                .as_ref()
+                .map(|note| format!(" ({})", note))
+                .unwrap_or_default()
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(stale_blocks.is_empty())
+    } else {
+        Err(StaleErrors::new(errors))
+    }
+}
+
+fn stale_dir(
+    cutoff: u64,
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Vec<Result<Vec<StaleBlock>, StaleError>> {
+    walk_dir(path)
+        .filter_map(|direntry| match direntry {
+            Ok(entry) if entry.path().is_file() => {
+                Some(stale_file(cutoff, parse_mode, entry.path()))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(StaleError::WalkDir(format!("{}", path.display()), e))),
+        })
+        .collect()
+}
+
+fn stale_file(
+    cutoff: u64,
+    parse_mode: ParseMode,
+    path: &Path,
+) -> Result<Vec<StaleBlock>, StaleError> {
+    let filename = format!("{}", path.display());
+    let contents =
+        match read_crypt_file(path).map_err(|e| StaleError::ReadFile(filename.clone(), e))? {
+            Some(contents) => contents,
+            None => return Ok(Vec::new()),
+        };
+    let crypt_file = CryptFile::from_str_with_mode(&contents, parse_mode)
+        .map_err(|e| StaleError::ParseCryptFile(filename, e))?;
+
+    Ok(crypt_file
+        .blocks
+        .into_iter()
+        .filter_map(|block| match block {
+            Block::EncryptedCryptBlock(block) => Some(block),
+            _ => None,
+        })
+        .enumerate()
+        .filter(|(_, block)| block.encrypted_at.is_none_or(|at| at < cutoff))
+        .map(|(i, block)| StaleBlock {
+            path: path.to_path_buf(),
+            index: i + 1,
+            encrypted_at: block.encrypted_at,
+            note: block.note,
+        })
+        .collect())
+}
@@ -1,64 +1,270 @@
 use std::string::FromUtf8Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chacha20poly1305::aead::{self, Aead, NewAead};
 use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use thiserror::Error;
 
-use crate::parse::EncryptedCryptBlock;
+use crate::parse::{EncryptedCryptBlock, Recipient, WrappedKey};
 
+const ALGORITHM: &str = "ChaCha20Poly1305";
+
+/// Length in bytes of the passwords, which are used as keys as they are
+pub const PASSWORD_LEN: usize = 32;
+
+/// Encrypts the contents with a random data key, stored in the block wrapped by the password key
 pub fn encrypt(password: &str, contents: &str) -> Result<EncryptedCryptBlock, CryptoEncryptError> {
-    let key = Key::from_slice(password.as_bytes()); // 32-bytes
-    let cipher = ChaCha20Poly1305::new(key);
+    let data_key = generate_random_key();
+    let (nonce, ciphertext) =
+        seal(&data_key, contents.as_bytes()).map_err(CryptoEncryptError::Encryption)?;
 
-    let nonce_bytes = generate_random_nonce();
-    let nonce = Nonce::from_slice(&nonce_bytes); // 12-bytes; unique per message
+    Ok(EncryptedCryptBlock {
+        algorithm: ALGORITHM.to_string(),
+        nonce,
+        ciphertext,
+        wrapped_key: Some(wrap_key(password, &data_key)?),
+        encrypted_at: Some(now()),
+        note: None,
+        recipients: Vec::new(),
+    })
+}
 
-    let ciphertext = cipher
-        .encrypt(nonce, contents.as_bytes().as_ref())
-        .map_err(|e| CryptoEncryptError::Encryption(e))?;
+pub fn decrypt(
+    password: &str,
+    encrypted: &EncryptedCryptBlock,
+) -> Result<String, CryptoDecryptError> {
+    // TODO: check algorithm field before decrypting
+    let plaintext = match &encrypted.wrapped_key {
+        Some(_) => {
+            let (data_key, _) = unwrap_data_key(password, encrypted)?;
+            open(&data_key, &encrypted.nonce, &encrypted.ciphertext)
+        }
+        // Blocks from before keys were wrapped are encrypted with the password key
+        None => {
+            let key = password_key(password)
+                .ok_or(CryptoDecryptError::InvalidPasswordLength(password.len()))?;
+            open(key, &encrypted.nonce, &encrypted.ciphertext)
+        }
+    }
+    .map_err(CryptoDecryptError::Decryption)?;
+
+    String::from_utf8(plaintext).map_err(CryptoDecryptError::Utf8FromBytes)
+}
 
+/// Re-encrypts the block for `new_password`, keeping its note and recipients. Only the data key
+/// wrapped for `password` is re-encrypted, blocks from before keys were wrapped are encrypted
+/// again as a whole.
+pub fn rewrap(
+    password: &str,
+    new_password: &str,
+    encrypted: &EncryptedCryptBlock,
+) -> Result<EncryptedCryptBlock, CryptoRewrapError> {
+    match &encrypted.wrapped_key {
+        Some(wrapped_key) => {
+            let (data_key, slot) = unwrap_data_key(password, encrypted)?;
+            let mut wrapped_key = wrapped_key.clone();
+            let mut recipients = encrypted.recipients.clone();
+            match slot {
+                KeySlot::Password => wrapped_key = wrap_key(new_password, &data_key)?,
+                KeySlot::Recipient(i) => {
+                    recipients[i].wrapped_key = wrap_key(new_password, &data_key)?
+                }
+            }
+            Ok(EncryptedCryptBlock {
+                algorithm: encrypted.algorithm.clone(),
+                nonce: encrypted.nonce.clone(),
+                ciphertext: encrypted.ciphertext.clone(),
+                wrapped_key: Some(wrapped_key),
+                encrypted_at: Some(now()),
+                note: encrypted.note.clone(),
+                recipients,
+            })
+        }
+        None => {
+            let contents = decrypt(password, encrypted)?;
+            Ok(EncryptedCryptBlock {
+                note: encrypted.note.clone(),
+                ..encrypt(new_password, &contents)?
+            })
+        }
+    }
+}
+
+/// Encrypts new contents in place of the ones of the block, with the same data key so that
+/// every recipient can still decrypt it. Blocks from before keys were wrapped are encrypted
+/// again as a whole.
+pub fn reencrypt(
+    password: &str,
+    contents: &str,
+    encrypted: &EncryptedCryptBlock,
+) -> Result<EncryptedCryptBlock, CryptoRewrapError> {
+    if encrypted.wrapped_key.is_none() {
+        return Ok(EncryptedCryptBlock {
+            note: encrypted.note.clone(),
+            ..encrypt(password, contents)?
+        });
+    }
+    let (data_key, _) = unwrap_data_key(password, encrypted)?;
+    let (nonce, ciphertext) =
+        seal(&data_key, contents.as_bytes()).map_err(CryptoEncryptError::Encryption)?;
     Ok(EncryptedCryptBlock {
-        algorithm: "ChaCha20Poly1305".to_string(),
-        nonce: nonce_bytes.to_vec(),
+        algorithm: encrypted.algorithm.clone(),
+        nonce,
         ciphertext,
+        wrapped_key: encrypted.wrapped_key.clone(),
+        encrypted_at: Some(now()),
+        note: encrypted.note.clone(),
+        recipients: encrypted.recipients.clone(),
     })
 }
 
-fn generate_random_nonce() -> [u8; 12] {
-    use rand::prelude::*;
-    use rand_chacha::ChaCha20Rng;
+/// Wraps the data key of the block for the recipient, replacing the key they already had.
+/// `password` is any password that can already decrypt the block.
+pub fn grant(
+    password: &str,
+    recipient: &str,
+    recipient_password: &str,
+    encrypted: &mut EncryptedCryptBlock,
+) -> Result<(), CryptoGrantError> {
+    if encrypted.wrapped_key.is_none() {
+        return Err(CryptoGrantError::NoDataKey);
+    }
+    let (data_key, _) = unwrap_data_key(password, encrypted)?;
+    let wrapped_key = wrap_key(recipient_password, &data_key)?;
+    match encrypted
+        .recipients
+        .iter_mut()
+        .find(|existing| existing.name == recipient)
+    {
+        Some(existing) => existing.wrapped_key = wrapped_key,
+        None => encrypted.recipients.push(Recipient {
+            name: recipient.to_string(),
+            wrapped_key,
+        }),
+    }
+    Ok(())
+}
 
-    let mut rng = ChaCha20Rng::from_entropy();
-    let mut nonce: [u8; 12] = [0; 12];
-    rng.fill(&mut nonce);
-    nonce
+/// Which of the wrapped keys of a block a password opened
+enum KeySlot {
+    /// The key of the password the block was encrypted or rotated with
+    Password,
+    /// The key of the recipient at this index
+    Recipient(usize),
 }
 
-pub fn decrypt(
+/// Unwraps the data key of the block with the first of its wrapped keys the password opens
+fn unwrap_data_key(
     password: &str,
     encrypted: &EncryptedCryptBlock,
-) -> Result<String, CryptoDecryptError> {
-    let key = Key::from_slice(password.as_bytes()); // 32-bytes
-                                                    // TODO: check algorithm field before decrypting
-    let cipher = ChaCha20Poly1305::new(key);
+) -> Result<(Key, KeySlot), CryptoDecryptError> {
+    let wrapped_key = encrypted
+        .wrapped_key
+        .as_ref()
+        .expect("blocks with recipients have a wrapped key");
+    let err = match unwrap_key(password, wrapped_key) {
+        Ok(data_key) => return Ok((data_key, KeySlot::Password)),
+        Err(e) => e,
+    };
+    encrypted
+        .recipients
+        .iter()
+        .enumerate()
+        .find_map(|(i, recipient)| {
+            unwrap_key(password, &recipient.wrapped_key)
+                .ok()
+                .map(|data_key| (data_key, KeySlot::Recipient(i)))
+        })
+        .ok_or(err)
+}
 
-    let nonce_bytes = &encrypted.nonce;
+/// Seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs()
+}
+
+/// The key of the password, None unless it is PASSWORD_LEN bytes long
+fn password_key(password: &str) -> Option<&Key> {
+    if password.len() == PASSWORD_LEN {
+        Some(Key::from_slice(password.as_bytes()))
+    } else {
+        None
+    }
+}
+
+fn wrap_key(password: &str, data_key: &Key) -> Result<WrappedKey, CryptoEncryptError> {
+    let key =
+        password_key(password).ok_or(CryptoEncryptError::InvalidPasswordLength(password.len()))?;
+    let (nonce, ciphertext) =
+        seal(key, data_key.as_slice()).map_err(CryptoEncryptError::KeyWrapping)?;
+    Ok(WrappedKey {
+        algorithm: ALGORITHM.to_string(),
+        nonce,
+        ciphertext,
+    })
+}
+
+fn unwrap_key(password: &str, wrapped_key: &WrappedKey) -> Result<Key, CryptoDecryptError> {
+    let key =
+        password_key(password).ok_or(CryptoDecryptError::InvalidPasswordLength(password.len()))?;
+    let data_key = open(key, &wrapped_key.nonce, &wrapped_key.ciphertext)
+        .map_err(CryptoDecryptError::KeyUnwrapping)?;
+    if data_key.len() != 32 {
+        return Err(CryptoDecryptError::InvalidDataKeyLength(data_key.len()));
+    }
+    Ok(*Key::from_slice(&data_key))
+}
+
+/// Encrypts with a new random nonce, returning it along with the ciphertext
+fn seal(key: &Key, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), aead::Error> {
+    let cipher = ChaCha20Poly1305::new(key);
 
+    let nonce_bytes = generate_random_nonce();
     let nonce = Nonce::from_slice(&nonce_bytes); // 12-bytes; unique per message
 
-    let ciphertext_bytes = &encrypted.ciphertext;
+    let ciphertext = cipher.encrypt(nonce, plaintext)?;
+    Ok((nonce_bytes.to_vec(), ciphertext))
+}
+
+fn open(key: &Key, nonce_bytes: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, aead::Error> {
+    let cipher = ChaCha20Poly1305::new(key);
+    let nonce = Nonce::from_slice(nonce_bytes); // 12-bytes; unique per message
+    cipher.decrypt(nonce, ciphertext)
+}
+
+fn generate_random_nonce() -> [u8; 12] {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
+
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut nonce: [u8; 12] = [0; 12];
+    rng.fill(&mut nonce);
+    nonce
+}
 
-    let plaintext = cipher
-        .decrypt(nonce, ciphertext_bytes.as_ref())
-        .map_err(|e| CryptoDecryptError::Decryption(e))?;
+fn generate_random_key() -> Key {
+    use rand::prelude::*;
+    use rand_chacha::ChaCha20Rng;
 
-    Ok(String::from_utf8(plaintext).map_err(|e| CryptoDecryptError::Utf8FromBytes(e))?)
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut key: [u8; 32] = [0; 32];
+    rng.fill(&mut key);
+    *Key::from_slice(&key)
 }
 
 #[derive(Error, Debug)]
 pub enum CryptoEncryptError {
     #[error("Failed encryption: {}", .0)]
     Encryption(aead::Error),
+
+    #[error("Failed encrypting data key: {}", .0)]
+    KeyWrapping(aead::Error),
+
+    #[error("Password is {} bytes instead of {}", .0, PASSWORD_LEN)]
+    InvalidPasswordLength(usize),
 }
 
 #[derive(Error, Debug)]
@@ -66,6 +272,109 @@ pub enum CryptoDecryptError {
     #[error("Failed decryption: {}", .0)]
     Decryption(aead::Error),
 
+    #[error("Failed decrypting data key, the password may be wrong: {}", .0)]
+    KeyUnwrapping(aead::Error),
+
+    #[error("Decrypted data key is {} bytes instead of 32", .0)]
+    InvalidDataKeyLength(usize),
+
+    #[error("Password is {} bytes instead of {}", .0, PASSWORD_LEN)]
+    InvalidPasswordLength(usize),
+
     #[error("Failed parsing utf-8 from decrypted bytes: {}", .0)]
     Utf8FromBytes(FromUtf8Error),
 }
+
+#[derive(Error, Debug)]
+pub enum CryptoGrantError {
+    #[error(
+        "Block is encrypted with the password key directly, rotate it to give it a data key first"
+    )]
+    NoDataKey,
+
+    #[error(transparent)]
+    Encryption(#[from] CryptoEncryptError),
+
+    #[error(transparent)]
+    Decryption(#[from] CryptoDecryptError),
+}
+
+#[derive(Error, Debug)]
+pub enum CryptoRewrapError {
+    #[error(transparent)]
+    Encryption(#[from] CryptoEncryptError),
+
+    #[error(transparent)]
+    Decryption(#[from] CryptoDecryptError),
+}
+
+#[test]
+fn test_grant_decrypts_with_recipient_password() {
+    let password = "an example very very secret key.";
+    let alice_password = "alice's own very very secret key";
+    let mut encrypted = encrypt(password, "DB_PASSWORD=hunter2").unwrap();
+
+    grant(password, "alice", alice_password, &mut encrypted).unwrap();
+
+    assert_eq!(encrypted.recipients.len(), 1);
+    assert_eq!(encrypted.recipients[0].name, "alice");
+    assert_eq!(
+        decrypt(alice_password, &encrypted).unwrap(),
+        "DB_PASSWORD=hunter2"
+    );
+    assert_eq!(
+        decrypt(password, &encrypted).unwrap(),
+        "DB_PASSWORD=hunter2"
+    );
+
+    // Rotating alice's password leaves the one the block was encrypted with as it was
+    let new_alice_password = "alice's new very very secret key";
+    let rotated = rewrap(alice_password, new_alice_password, &encrypted).unwrap();
+    assert_eq!(rotated.wrapped_key, encrypted.wrapped_key);
+    assert!(decrypt(alice_password, &rotated).is_err());
+    assert_eq!(
+        decrypt(new_alice_password, &rotated).unwrap(),
+        "DB_PASSWORD=hunter2"
+    );
+}
+
+#[test]
+fn test_reencrypt_keeps_recipients() {
+    let password = "an example very very secret key.";
+    let alice_password = "alice's own very very secret key";
+    let mut encrypted = encrypt(password, "DB_PASSWORD=hunter2").unwrap();
+    grant(password, "alice", alice_password, &mut encrypted).unwrap();
+
+    let edited = reencrypt(alice_password, "DB_PASSWORD=correct horse", &encrypted).unwrap();
+
+    assert_ne!(edited.nonce, encrypted.nonce);
+    assert_eq!(
+        decrypt(password, &edited).unwrap(),
+        "DB_PASSWORD=correct horse"
+    );
+    assert_eq!(
+        decrypt(alice_password, &edited).unwrap(),
+        "DB_PASSWORD=correct horse"
+    );
+}
+
+#[test]
+fn test_short_password_is_an_error() {
+    let password = "an example very very secret key.";
+    let encrypted = encrypt(password, "DB_PASSWORD=hunter2").unwrap();
+
+    assert!(matches!(
+        encrypt("short", "DB_PASSWORD=hunter2"),
+        Err(CryptoEncryptError::InvalidPasswordLength(5))
+    ));
+    assert!(matches!(
+        decrypt("short", &encrypted),
+        Err(CryptoDecryptError::InvalidPasswordLength(5))
+    ));
+    assert!(matches!(
+        rewrap(password, "short", &encrypted),
+        Err(CryptoRewrapError::Encryption(
+            CryptoEncryptError::InvalidPasswordLength(5)
+        ))
+    ));
+}
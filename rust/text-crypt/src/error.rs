@@ -2,7 +2,9 @@ use std::fmt;
 
 use thiserror::Error;
 
-use crate::crypto::{CryptoDecryptError, CryptoEncryptError};
+use crate::crypto::{
+    CryptoDecryptError, CryptoEncryptError, CryptoGrantError, CryptoRewrapError, PASSWORD_LEN,
+};
 
 #[derive(Error, Debug)]
 pub enum CheckError {
@@ -49,6 +51,9 @@ pub enum EncryptError {
     #[error("Error reading file: {} Error: {}", .0, .1)]
     ReadFile(String, std::io::Error),
 
+    #[error("Error locking file: {} Error: {}", .0, .1)]
+    LockFile(String, std::io::Error),
+
     #[error("Error writing file: {} Error: {}", .0, .1)]
     WriteFile(String, std::io::Error),
 
@@ -87,6 +92,9 @@ pub enum DecryptError {
     #[error("Error reading file: {} Error: {}", .0, .1)]
     ReadFile(String, std::io::Error),
 
+    #[error("Error locking file: {} Error: {}", .0, .1)]
+    LockFile(String, std::io::Error),
+
     #[error("Error writing file: {} Error: {}", .0, .1)]
     WriteFile(String, std::io::Error),
 
@@ -94,6 +102,9 @@ pub enum DecryptError {
     WalkDir(String, walkdir::Error),
     #[error(transparent)]
     Decryption(#[from] CryptoDecryptError),
+
+    #[error("Refusing to print decrypted contents since stdout is not a terminal. Use -w to write them to the files, --force-stdout to print them anyway or --raw to pipe only the decrypted blocks")]
+    StdoutNotTerminal,
 }
 
 pub struct DecryptErrors {
@@ -116,6 +127,205 @@ impl fmt::Debug for DecryptErrors {
     }
 }
 
+#[derive(Error, Debug)]
+pub enum RotateError {
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Error locking file: {} Error: {}", .0, .1)]
+    LockFile(String, std::io::Error),
+
+    #[error("Error writing file: {} Error: {}", .0, .1)]
+    WriteFile(String, std::io::Error),
+
+    #[error("Error walking dir: {} Error: {}", .0, .1)]
+    WalkDir(String, walkdir::Error),
+
+    #[error("New password is {} bytes instead of {}", .0, PASSWORD_LEN)]
+    NewPasswordLength(usize),
+
+    #[error(transparent)]
+    Rewrap(#[from] CryptoRewrapError),
+}
+
+pub struct RotateErrors {
+    errors: Vec<RotateError>,
+}
+
+impl RotateErrors {
+    pub fn new(errors: Vec<RotateError>) -> Self {
+        RotateErrors { errors }
+    }
+}
+
+impl fmt::Debug for RotateErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\nErrors encountered rotating files\n")?;
+        self.errors
+            .iter()
+            .map(|error| write!(f, "{}\n", error))
+            .collect()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum InventoryError {
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Error walking dir: {} Error: {}", .0, .1)]
+    WalkDir(String, walkdir::Error),
+
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+}
+
+pub struct InventoryErrors {
+    errors: Vec<InventoryError>,
+}
+
+impl InventoryErrors {
+    pub fn new(errors: Vec<InventoryError>) -> Self {
+        InventoryErrors { errors }
+    }
+}
+
+impl fmt::Debug for InventoryErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\nErrors encountered taking the inventory of files")?;
+        self.errors
+            .iter()
+            .try_for_each(|error| writeln!(f, "{}", error))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum StaleError {
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Error walking dir: {} Error: {}", .0, .1)]
+    WalkDir(String, walkdir::Error),
+
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+}
+
+pub struct StaleErrors {
+    errors: Vec<StaleError>,
+}
+
+impl StaleErrors {
+    pub fn new(errors: Vec<StaleError>) -> Self {
+        StaleErrors { errors }
+    }
+}
+
+impl fmt::Debug for StaleErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\nErrors encountered looking for stale blocks")?;
+        self.errors
+            .iter()
+            .try_for_each(|error| writeln!(f, "{}", error))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EnvError {
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Not a crypt file: {}", .0)]
+    NotCryptFile(String),
+
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+
+    #[error("Invalid line in crypt block of file: {} at line {} of the block, expected KEY=value", .0, .1)]
+    InvalidLine(String, usize),
+
+    #[error("Error running: {} Error: {}", .0, .1)]
+    Exec(String, std::io::Error),
+
+    #[error(transparent)]
+    Decryption(#[from] CryptoDecryptError),
+}
+
+#[derive(Error, Debug)]
+pub enum AccessError {
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Not a crypt file: {}", .0)]
+    NotCryptFile(String),
+
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+
+    #[error("Error locking file: {} Error: {}", .0, .1)]
+    LockFile(String, std::io::Error),
+
+    #[error("File is locked by another process: {}", .0)]
+    Locked(String),
+
+    #[error("Error writing file: {} Error: {}", .0, .1)]
+    WriteFile(String, std::io::Error),
+
+    #[error("No encrypted block {} in file: {}", .1, .0)]
+    NoSuchBlock(String, usize),
+
+    #[error("Recipient name {} is reserved for the password the blocks were encrypted with", .0)]
+    ReservedRecipient(String),
+
+    #[error("Recipient password is {} bytes instead of {}", .0, PASSWORD_LEN)]
+    RecipientPasswordLength(usize),
+
+    #[error(transparent)]
+    Grant(#[from] CryptoGrantError),
+}
+
+#[derive(Error, Debug)]
+pub enum EditError {
+    #[error("Error reading file: {} Error: {}", .0, .1)]
+    ReadFile(String, std::io::Error),
+
+    #[error("Not a crypt file: {}", .0)]
+    NotCryptFile(String),
+
+    #[error("Error parsing file: {} Error: {}", .0, .1)]
+    ParseCryptFile(String, ParseError),
+
+    #[error("Error locking file: {} Error: {}", .0, .1)]
+    LockFile(String, std::io::Error),
+
+    #[error("File is locked by another process: {}", .0)]
+    Locked(String),
+
+    #[error("Error writing file: {} Error: {}", .0, .1)]
+    WriteFile(String, std::io::Error),
+
+    #[error("No encrypted block {} in file: {}", .1, .0)]
+    NoSuchBlock(String, usize),
+
+    #[error("Error with the temporary plaintext file Error: {}", .0)]
+    TempFile(std::io::Error),
+
+    #[error("Error running editor: {} Error: {}", .0, .1)]
+    Editor(String, std::io::Error),
+
+    #[error("Editor {} exited with {}, the block was left as it was", .0, .1)]
+    EditorFailed(String, std::process::ExitStatus),
+
+    #[error(transparent)]
+    Decryption(#[from] CryptoDecryptError),
+
+    #[error(transparent)]
+    Reencryption(#[from] CryptoRewrapError),
+}
+
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("The number of Begin and End Crypt blocks don't match")]
@@ -2,7 +2,12 @@ pub mod cli;
 pub mod crypto;
 pub mod error;
 pub mod parse;
+pub mod read;
+pub mod secure_temp;
 
 pub use parse::Block;
 pub use parse::CryptFile;
 pub use parse::EncryptedCryptBlock;
+pub use parse::ParseMode;
+pub use parse::Recipient;
+pub use parse::WrappedKey;
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::ops::Range;
 
 use crate::error::EncryptedCryptEncodingError;
 use crate::error::ParseError;
@@ -30,6 +31,38 @@ pub struct EncryptedCryptBlock {
     pub algorithm: String,
     pub nonce: Vec<u8>,
     pub ciphertext: Vec<u8>,
+    /// Random key the ciphertext is encrypted with, itself encrypted with the key derived from
+    /// the password. None for blocks encrypted directly with the password key before keys were
+    /// wrapped.
+    #[serde(default)]
+    pub wrapped_key: Option<WrappedKey>,
+    /// Seconds since the unix epoch of when the block was last encrypted or rotated. Stored in
+    /// the clear so that the age of blocks can be checked without the password.
+    #[serde(default)]
+    pub encrypted_at: Option<u64>,
+    /// Note about the secret, e.g. who to ask to rotate it. Stored in the clear.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Other recipients the data key is wrapped for, each with their own password. Their names
+    /// are stored in the clear so that who can decrypt the block is known without a password.
+    #[serde(default)]
+    pub recipients: Vec<Recipient>,
+}
+
+/// Data key of a block encrypted with the key derived from the password, so that changing the
+/// password only re-encrypts the data keys rather than the contents
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WrappedKey {
+    pub algorithm: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Data key of a block wrapped for a recipient other than the password it was encrypted with
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Recipient {
+    pub name: String,
+    pub wrapped_key: WrappedKey,
 }
 
 impl EncryptedCryptBlock {
@@ -54,6 +87,15 @@ impl EncryptedCryptBlock {
         Ok(block)
     }
 
+    /// Version of the format the block is encrypted in, 1 for blocks encrypted with the password
+    /// key and 2 for blocks encrypted with a wrapped data key
+    pub fn format_version(&self) -> u8 {
+        match self.wrapped_key {
+            Some(_) => 2,
+            None => 1,
+        }
+    }
+
     pub fn to_ascii_armor(&self) -> Result<String, EncryptedCryptEncodingError> {
         let mut buf = Vec::new();
         // TODO: extract to `fn to_msg_pack()`?
@@ -74,6 +116,21 @@ fn test_parsing_encrypted_crypt_block() {
         algorithm: "test_algo".to_string(),
         nonce: b"nonce".to_vec(),
         ciphertext: b"this is some good ciphertext".to_vec(),
+        wrapped_key: Some(WrappedKey {
+            algorithm: "test_algo".to_string(),
+            nonce: b"key nonce".to_vec(),
+            ciphertext: b"wrapped data key".to_vec(),
+        }),
+        encrypted_at: Some(1_650_000_000),
+        note: Some("rotate with the db password".to_string()),
+        recipients: vec![Recipient {
+            name: "alice".to_string(),
+            wrapped_key: WrappedKey {
+                algorithm: "test_algo".to_string(),
+                nonce: b"alice key nonce".to_vec(),
+                ciphertext: b"data key wrapped for alice".to_vec(),
+            },
+        }],
     };
     let armored = block.to_ascii_armor().unwrap();
 
@@ -82,6 +139,41 @@ fn test_parsing_encrypted_crypt_block() {
     assert_eq!(parsed, block);
 }
 
+#[test]
+fn test_parsing_encrypted_crypt_block_without_wrapped_key() {
+    // Blocks encrypted before keys were wrapped only have the first three fields
+    let mut buf = Vec::new();
+    (
+        "test_algo",
+        b"nonce".to_vec(),
+        b"this is some good ciphertext".to_vec(),
+    )
+        .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+    let armored = format!(
+        "{}{}{}{}",
+        BEGIN_CRYPT_STR,
+        BEGIN_ENCRYPTED_CRYPT_ENCRYPTION_MARKER_STR,
+        base64::encode_config(buf, BASE64_CONFIG),
+        END_CRYPT_STR
+    );
+
+    let parsed = EncryptedCryptBlock::from_str(&armored).unwrap();
+
+    assert_eq!(
+        parsed,
+        EncryptedCryptBlock {
+            algorithm: "test_algo".to_string(),
+            nonce: b"nonce".to_vec(),
+            ciphertext: b"this is some good ciphertext".to_vec(),
+            wrapped_key: None,
+            encrypted_at: None,
+            note: None,
+            recipients: Vec::new(),
+        }
+    );
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Block {
     Plaintext(String),
@@ -98,6 +190,17 @@ impl Block {
     }
 }
 
+/// How the block delimiters are found in the contents of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Every delimiter starts or ends a block
+    Plain,
+    /// Delimiters in Markdown inline code spans are ignored, as they are usually documentation
+    /// about the delimiters. Fenced code blocks are parsed like plain text, so crypt blocks can
+    /// be kept in them.
+    Markdown,
+}
+
 #[derive(Debug)]
 pub struct CryptFile {
     pub blocks: Vec<Block>,
@@ -140,11 +243,27 @@ impl CryptFile {
     }
 
     pub fn from_str(contents: &str) -> Result<CryptFile, ParseError> {
+        CryptFile::from_str_with_mode(contents, ParseMode::Plain)
+    }
+
+    pub fn from_str_with_mode(contents: &str, mode: ParseMode) -> Result<CryptFile, ParseError> {
         let mut blocks = Vec::new();
 
+        let ignored_ranges = match mode {
+            ParseMode::Plain => Vec::new(),
+            ParseMode::Markdown => markdown_code_spans(contents),
+        };
+        let is_delimiter =
+            |(idx, _): &(usize, &str)| !ignored_ranges.iter().any(|range| range.contains(idx));
         // Replace with Regexes with case insensitive matching
-        let crypt_block_starts: Vec<_> = contents.match_indices(BEGIN_CRYPT_STR).collect();
-        let crypt_block_ends: Vec<_> = contents.match_indices(END_CRYPT_STR).collect();
+        let crypt_block_starts: Vec<_> = contents
+            .match_indices(BEGIN_CRYPT_STR)
+            .filter(is_delimiter)
+            .collect();
+        let crypt_block_ends: Vec<_> = contents
+            .match_indices(END_CRYPT_STR)
+            .filter(is_delimiter)
+            .collect();
         if crypt_block_starts.len() != crypt_block_ends.len() {
             return Err(ParseError::MismatchNumStartEndCryptBlocks);
         }
@@ -193,6 +312,75 @@ impl CryptFile {
     }
 }
 
+/// Byte ranges of the Markdown inline code spans of the contents. Backticks in fenced code
+/// blocks don't start code spans.
+fn markdown_code_spans(contents: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    // Character and length of the opening fence of the fenced code block the line is in
+    let mut fence: Option<(char, usize)> = None;
+    let mut line_start = 0;
+    for line in contents.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~');
+        let fence_len = fence_char.map_or(0, |c| trimmed.chars().take_while(|x| *x == c).count());
+        match fence {
+            None if fence_len >= 3 => fence = Some((fence_char.unwrap(), fence_len)),
+            None => spans.extend(
+                line_code_spans(line)
+                    .into_iter()
+                    .map(|span| (line_start + span.start)..(line_start + span.end)),
+            ),
+            Some((open_char, open_len)) => {
+                if fence_char == Some(open_char)
+                    && fence_len >= open_len
+                    && trimmed[fence_len..].trim().is_empty()
+                {
+                    fence = None;
+                }
+            }
+        }
+        line_start += line.len();
+    }
+    spans
+}
+
+/// Byte ranges of the code spans of a line, from a run of backticks to the next run of as many
+fn line_code_spans(line: &str) -> Vec<Range<usize>> {
+    let bytes = line.as_bytes();
+    let backticks_at = |i: usize| bytes[i..].iter().take_while(|b| **b == b'`').count();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'`' {
+            i += 1;
+            continue;
+        }
+        let open_len = backticks_at(i);
+        let mut j = i + open_len;
+        let mut close = None;
+        while j < bytes.len() {
+            if bytes[j] == b'`' {
+                let len = backticks_at(j);
+                if len == open_len {
+                    close = Some(j);
+                    break;
+                }
+                j += len;
+            } else {
+                j += 1;
+            }
+        }
+        match close {
+            Some(close) => {
+                spans.push(i..(close + open_len));
+                i = close + open_len;
+            }
+            None => i += open_len,
+        }
+    }
+    spans
+}
+
 #[test]
 fn test_from_str_unencrypted() {
     let contents = r#"hello worldBEGIN CRYPThelloworldencryptedEND CRYPTBEGIN CRYPTthisis a test
@@ -234,4 +422,44 @@ ending plaintext";
     );
 }
 
+#[test]
+fn test_from_str_markdown_ignores_code_spans() {
+    let contents = "Use `BEGIN CRYPT` and `END CRYPT` to mark secrets
+BEGIN CRYPT
+secret
+END CRYPT
+";
+
+    let parsed = CryptFile::from_str_with_mode(contents, ParseMode::Markdown).unwrap();
+
+    assert_eq!(
+        parsed.blocks,
+        vec![
+            Block::Plaintext("Use `BEGIN CRYPT` and `END CRYPT` to mark secrets\n".to_string()),
+            Block::UnencryptedCryptBlock("secret".to_string()),
+            Block::Plaintext("\n".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_from_str_markdown_parses_fenced_code_blocks() {
+    // Backticks in fenced code blocks are not code spans
+    let contents = "```sh
+echo `BEGIN CRYPT secret END CRYPT`
+```
+";
+
+    let parsed = CryptFile::from_str_with_mode(contents, ParseMode::Markdown).unwrap();
+
+    assert_eq!(
+        parsed.blocks,
+        vec![
+            Block::Plaintext("```sh\necho `".to_string()),
+            Block::UnencryptedCryptBlock("secret".to_string()),
+            Block::Plaintext("`\n```\n".to_string()),
+        ]
+    );
+}
+
 // TODO: Add more test cases for errors
@@ -0,0 +1,74 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use lazy_static::lazy_static;
+use memmap2::Mmap;
+use regex::bytes::Regex;
+
+/// Number of bytes read first to tell binary files apart before reading the rest
+const PREFILTER_LEN: usize = 8 * 1024;
+
+/// Files at least this large are memory-mapped rather than read into memory
+const MMAP_MIN_LEN: u64 = 1024 * 1024;
+
+/// Extensions of files that are never text, skipped without being opened
+const BINARY_EXTENSIONS: &[&str] = &[
+    "7z", "a", "avi", "bin", "bmp", "class", "dll", "dylib", "exe", "flac", "gif", "gpg", "gz",
+    "ico", "iso", "jar", "jpeg", "jpg", "mkv", "mov", "mp3", "mp4", "o", "ogg", "otf", "pdf",
+    "png", "pyc", "so", "tar", "tgz", "ttf", "wasm", "wav", "webm", "webp", "woff", "woff2", "xz",
+    "zip", "zst",
+];
+
+lazy_static! {
+    static ref IS_CRYPT_FILE_RE: Regex = Regex::new(r"(?i)BEGIN[\s\W\p{Punct}]*CRYPT").unwrap();
+}
+
+/// Reads the file if it contains crypt blocks, None if it doesn't or is binary. Only the
+/// first few KB of binary files are read and the contents of large files are searched without
+/// copying them, so that walking large trees stays fast.
+pub fn read_crypt_file(path: &Path) -> io::Result<Option<String>> {
+    let is_binary_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| BINARY_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false);
+    if is_binary_extension {
+        return Ok(None);
+    }
+
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut prefix = Vec::with_capacity(PREFILTER_LEN);
+    (&mut file)
+        .take(PREFILTER_LEN as u64)
+        .read_to_end(&mut prefix)?;
+    // Text files don't contain NUL bytes
+    if prefix.contains(&0) {
+        return Ok(None);
+    }
+
+    if len >= MMAP_MIN_LEN {
+        // Safety: the file could be modified while mapped, in which case the contents read are
+        // inconsistent like they would be reading it while it is being written to
+        let mmap = unsafe { Mmap::map(&file)? };
+        if !IS_CRYPT_FILE_RE.is_match(&mmap) {
+            return Ok(None);
+        }
+        return to_string(mmap.to_vec()).map(Some);
+    }
+
+    let mut contents = prefix;
+    file.read_to_end(&mut contents)?;
+    if !IS_CRYPT_FILE_RE.is_match(&contents) {
+        return Ok(None);
+    }
+    to_string(contents).map(Some)
+}
+
+fn to_string(contents: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
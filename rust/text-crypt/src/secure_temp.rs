@@ -0,0 +1,141 @@
+use std::{
+    env,
+    fs::{self, DirBuilder, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+
+const SHRED_BUFFER_LEN: usize = 4096;
+
+/// Temporary file holding plaintext, e.g. decrypted contents handed to an editor. It is created
+/// readable and writable by its owner only, in a new directory only its owner can list.
+///
+/// The file and its directory are removed when dropped, the file shredded first with `shred`.
+pub struct PlaintextTempFile {
+    dir: PathBuf,
+    path: PathBuf,
+    shred: bool,
+}
+
+impl PlaintextTempFile {
+    /// Creates the empty file `name` in a new private directory of the temp dir of the OS
+    pub fn create(name: &str, shred: bool) -> io::Result<PlaintextTempFile> {
+        let dir = create_private_dir()?;
+        let path = dir.join(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        if let Err(e) = options.open(&path) {
+            let _ = fs::remove_dir(&dir);
+            return Err(e);
+        }
+        Ok(PlaintextTempFile { dir, path, shred })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&self, contents: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    }
+
+    pub fn read(&self) -> io::Result<String> {
+        fs::read_to_string(&self.path)
+    }
+}
+
+impl Drop for PlaintextTempFile {
+    fn drop(&mut self) {
+        let removed = if self.shred {
+            shred(&self.path)
+        } else {
+            fs::remove_file(&self.path)
+        };
+        // Programs like editors may have left their own files next to it
+        let removed = removed.and_then(|_| fs::remove_dir_all(&self.dir));
+        if let Err(e) = removed {
+            eprintln!(
+                "Error removing temporary plaintext {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Overwrites the contents of the file with random bytes synced to disk before removing it.
+///
+/// Filesystems that copy on write or journal data, as well as SSDs remapping their blocks, may
+/// still keep the plaintext elsewhere on the disk.
+pub fn shred(path: &Path) -> io::Result<()> {
+    let mut remaining = fs::metadata(path)?.len() as usize;
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let mut rng = ChaCha20Rng::from_entropy();
+    let mut buf = [0; SHRED_BUFFER_LEN];
+    while remaining > 0 {
+        let len = remaining.min(SHRED_BUFFER_LEN);
+        rng.fill_bytes(&mut buf[..len]);
+        file.write_all(&buf[..len])?;
+        remaining -= len;
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)
+}
+
+/// Creates a new directory in the temp dir of the OS that only its owner can access
+fn create_private_dir() -> io::Result<PathBuf> {
+    let mut rng = ChaCha20Rng::from_entropy();
+    loop {
+        let dir = env::temp_dir().join(format!("text-crypt-{:016x}", rng.next_u64()));
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        builder.mode(0o700);
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_plaintext_temp_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_file = PlaintextTempFile::create("secrets.txt", false).unwrap();
+    let dir = temp_file.dir.clone();
+    let file_mode = fs::metadata(temp_file.path()).unwrap().permissions().mode();
+    let dir_mode = fs::metadata(&dir).unwrap().permissions().mode();
+
+    assert_eq!(file_mode & 0o777, 0o600);
+    assert_eq!(dir_mode & 0o777, 0o700);
+
+    drop(temp_file);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_shred_removes_file() {
+    let temp_file = PlaintextTempFile::create("secrets.txt", true).unwrap();
+    temp_file.write("password=hunter2").unwrap();
+    assert_eq!(temp_file.read().unwrap(), "password=hunter2");
+    let path = temp_file.path().to_path_buf();
+
+    drop(temp_file);
+    assert!(!path.exists());
+}